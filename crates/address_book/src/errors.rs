@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConstellationAddressBookError {
+    #[error("Bad peer {0}, expected id@host:port")]
+    BadPeer(String),
+    #[error("Unable to read address book {0}: {1}")]
+    File(String, String),
+}
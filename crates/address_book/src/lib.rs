@@ -1,3 +1,6 @@
+pub mod errors;
+mod source;
 mod task;
 
+pub use source::{CrawledPeers, Source};
 pub use task::run;
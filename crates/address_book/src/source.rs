@@ -0,0 +1,90 @@
+use crate::errors::ConstellationAddressBookError;
+use chrono::Utc;
+use serde_json::json;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use terra_rust_api::addressbook::NodeAddr;
+use terra_rust_api::AddressBook;
+
+/// peers (as id@host:port) the RPC crawler found in its node's net_info
+pub type CrawledPeers = Arc<Mutex<Vec<String>>>;
+
+/// somewhere nodes are found. each node records which sources reported it, and when
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// an addrbook.json served over http(s)
+    Url(String),
+    /// an addrbook.json on disk, eg. from one of our own nodes
+    File(String),
+    /// peers found by the RPC crawler
+    RpcCrawler,
+}
+
+impl Source {
+    /// `rpc` is the crawler, anything starting http:// or https:// a URL, and the rest files
+    pub fn parse(source: &str) -> Source {
+        if source == "rpc" {
+            Source::RpcCrawler
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            Source::Url(source.into())
+        } else {
+            Source::File(source.into())
+        }
+    }
+
+    pub async fn fetch(&self, crawled: &CrawledPeers) -> anyhow::Result<Vec<NodeAddr>> {
+        match self {
+            Source::Url(url) => {
+                log::info!("Grabbing {}", url);
+                Ok(terra_rust_api::Terra::address_book(url).await?.addrs)
+            }
+            Source::File(file) => {
+                let book: AddressBook = std::fs::read_to_string(file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+                    .map_err(|e| ConstellationAddressBookError::File(file.clone(), e))?;
+                Ok(book.addrs)
+            }
+            Source::RpcCrawler => {
+                let peers = {
+                    crawled
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .clone()
+                };
+                peers.iter().map(|peer| crawled_entry(peer)).collect()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Url(url) => write!(f, "{}", url),
+            Source::File(file) => write!(f, "{}", file),
+            Source::RpcCrawler => write!(f, "rpc"),
+        }
+    }
+}
+
+/// an address book entry for a crawled peer. built as tendermint writes addrbook.json,
+/// so it reads the same way as one fetched from a URL
+fn crawled_entry(peer: &str) -> anyhow::Result<NodeAddr> {
+    let bad_peer = || ConstellationAddressBookError::BadPeer(peer.into());
+    let (id, host_port) = peer.split_once('@').ok_or_else(bad_peer)?;
+    let (ip, port) = host_port.rsplit_once(':').ok_or_else(bad_peer)?;
+    let port: u16 = port.parse().map_err(|_| bad_peer())?;
+    let addr = json!({ "id": id, "ip": ip, "port": port });
+    let never = "0001-01-01T00:00:00Z";
+    Ok(serde_json::from_value(json!({
+        "addr": addr,
+        "src": addr,
+        "buckets": [],
+        "attempts": 0,
+        "bucket_type": 1,
+        "last_attempt": never,
+        "last_success": Utc::now(),
+        "last_ban_time": never,
+    }))?)
+}
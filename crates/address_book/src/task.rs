@@ -1,60 +1,181 @@
-use constellation_shared::state::AppState;
-use terra_rust_api::AddressBook;
+use actix_broker::{Broker, SystemBroker};
+use chrono::Utc;
+use constellation_node_events::{MessageNodeDiscovered, MessageNodeGone, MessageNodeIpChanged};
+use constellation_state::{AppSightings, SharedState};
+use constellation_status::ModuleReporter;
+use constellation_store::{Batch, Store, Table};
+use terra_rust_api::addressbook::NodeAddr;
+
+use crate::source::{CrawledPeers, Source};
 
 use std::collections::hash_map::Entry;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::PoisonError;
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time;
 
-pub async fn run(state: AppState, period: Duration, address_book_url: String) {
+/// merge every source into the state. a source that can't be read is reported,
+/// and the others are still merged. new nodes, nodes on a new IP, nodes that
+/// no source reports any more, and gone nodes coming back are announced on the broker.
+/// returns once `shutdown` turns true
+pub async fn run(
+    state: SharedState,
+    period: Duration,
+    sources: Vec<Source>,
+    crawled: CrawledPeers,
+    seen: AppSightings,
+    store: Option<Store>,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
     let mut interval = time::interval(period);
+    // node -> (id, ip) of everything the sources reported last time. the nodes restored with the
+    // state count too, so one no source reports after a restart is still announced as gone
+    let mut present: HashMap<String, (String, String)> = state
+        .snapshot()
+        .nodes
+        .iter()
+        .map(|(node, entry)| (node.clone(), (entry.addr.id.clone(), entry.addr.ip.clone())))
+        .collect();
+    // ids announced as gone, and not reported since
+    let mut gone: HashSet<String> = HashSet::new();
     loop {
-        match grab_address_book(&address_book_url).await {
-            Ok(the_book) => {
-                let mut nodes = state.lock().unwrap();
-                the_book.addrs.iter().for_each(|entry| {
-                    /*
-                    if !nodes.nodes.contains_key(&entry.addr.to_string()) {
-                        nodes.nodes.insert(entry.addr.to_string(), entry.clone());
-                        nodes.new_nodes.insert(entry.addr.to_string());
-                        nodes.new_ips_bgp.insert(entry.addr.ip.clone());
-                        nodes.new_ips_geo.insert(entry.addr.ip.clone());
-                    }*/
-                    if let Entry::Vacant(e) = nodes.nodes.entry(entry.addr.to_string()) {
-                        e.insert(entry.clone());
-                        nodes.new_nodes.insert(entry.addr.to_string());
-                        nodes.new_ips_bgp.insert(entry.addr.ip.clone());
-                        nodes.new_ips_geo.insert(entry.addr.ip.clone());
-                    }
+        let mut books: Vec<(String, Vec<NodeAddr>)> = vec![];
+        for source in &sources {
+            match source.fetch(&crawled).await {
+                Ok(addrs) => books.push((source.to_string(), addrs)),
+                Err(e) => {
+                    log::error!("Error: {} {}", source, e);
+                    status.error(format!("{}: {}", source, e));
+                }
+            }
+        }
+        if books.is_empty() {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.changed() => break,
+            }
+            continue;
+        }
+        let mut batch = Batch::default();
+        let mut discovered: Vec<MessageNodeDiscovered> = vec![];
+        let mut ip_changed: Vec<MessageNodeIpChanged> = vec![];
+        state.update(|nodes| {
+            for (source, entry) in books
+                .iter()
+                .flat_map(|(source, addrs)| addrs.iter().map(move |entry| (source, entry)))
+            {
+                let mut new_node = false;
+                if let Entry::Vacant(e) = nodes.nodes.entry(entry.addr.to_string()) {
+                    new_node = true;
+                    e.insert(entry.clone());
+                    nodes.new_nodes.insert(entry.addr.to_string());
+                    nodes.new_ips_bgp.insert(entry.addr.ip.clone());
+                    nodes.new_ips_geo.insert(entry.addr.ip.clone());
+                    batch.put(Table::Nodes, &entry.addr.to_string(), entry);
+                    batch.insert(Table::NewNodes, &entry.addr.to_string());
+                    batch.insert(Table::NewIpsBgp, &entry.addr.ip);
+                    batch.insert(Table::NewIpsGeo, &entry.addr.ip);
+                }
 
-                    let mut s = match nodes.id_ip_addr.get(&entry.addr.id) {
-                        Some(set) => set.clone(),
-                        None => HashSet::new(),
-                    };
-                    s.insert(entry.addr.clone());
-                    nodes
-                        .id_ip_addr
-                        .insert((&entry.addr.id.clone()).to_string(), s);
-                    let mut s = match nodes.ip_ip_addr.get(&entry.addr.ip) {
-                        Some(set) => set.clone(),
-                        None => HashSet::new(),
-                    };
-                    s.insert(entry.addr.clone());
-                    nodes
-                        .ip_ip_addr
-                        .insert((&entry.addr.ip.clone()).to_string(), s);
-                })
+                let mut s = match nodes.id_ip_addr.get(&entry.addr.id) {
+                    Some(set) => set.clone(),
+                    None => HashSet::new(),
+                };
+                if gone.remove(&entry.addr.id) {
+                    discovered.push(MessageNodeDiscovered {
+                        node: entry.addr.to_string(),
+                        id: entry.addr.id.clone(),
+                        ip: entry.addr.ip.clone(),
+                        source: source.clone(),
+                    });
+                } else if s.is_empty() {
+                    if new_node {
+                        discovered.push(MessageNodeDiscovered {
+                            node: entry.addr.to_string(),
+                            id: entry.addr.id.clone(),
+                            ip: entry.addr.ip.clone(),
+                            source: source.clone(),
+                        });
+                    }
+                } else if !s.iter().any(|addr| addr.ip == entry.addr.ip) {
+                    let mut previous = s.iter().map(|addr| addr.ip.clone()).collect::<Vec<_>>();
+                    previous.sort();
+                    previous.dedup();
+                    ip_changed.push(MessageNodeIpChanged {
+                        id: entry.addr.id.clone(),
+                        ip: entry.addr.ip.clone(),
+                        previous,
+                    });
+                }
+                if s.insert(entry.addr.clone()) {
+                    batch.put(Table::IdIpAddr, &entry.addr.id, &s);
+                }
+                nodes
+                    .id_ip_addr
+                    .insert((&entry.addr.id.clone()).to_string(), s);
+                let mut s = match nodes.ip_ip_addr.get(&entry.addr.ip) {
+                    Some(set) => set.clone(),
+                    None => HashSet::new(),
+                };
+                if s.insert(entry.addr.clone()) {
+                    batch.put(Table::IpIpAddr, &entry.addr.ip, &s);
+                }
+                nodes
+                    .ip_ip_addr
+                    .insert((&entry.addr.ip.clone()).to_string(), s);
+            }
+        });
+        let mut merged = HashMap::new();
+        {
+            let now = Utc::now();
+            let mut sightings = seen.lock().unwrap_or_else(PoisonError::into_inner);
+            for (source, addrs) in &books {
+                for entry in addrs {
+                    let node = entry.addr.to_string();
+                    sightings.saw_node(&node, &entry.addr.ip, source, now);
+                    merged.insert(node, (entry.addr.id.clone(), entry.addr.ip.clone()));
+                }
+            }
+        }
+        for msg in discovered {
+            Broker::<SystemBroker>::issue_async(msg);
+        }
+        for msg in ip_changed {
+            Broker::<SystemBroker>::issue_async(msg);
+        }
+        let reported = merged.len();
+        // a source that couldn't be read says nothing about what has gone
+        let complete = books.len() == sources.len();
+        let mut still_present = HashMap::new();
+        for (node, (id, ip)) in std::mem::take(&mut present) {
+            if merged.contains_key(&node) {
+                continue;
             }
-            Err(e) => {
-                log::error!("Error: {}", e);
+            if complete {
+                gone.insert(id.clone());
+                Broker::<SystemBroker>::issue_async(MessageNodeGone { node, id, ip });
+            } else {
+                still_present.insert(node, (id, ip));
             }
         }
-        interval.tick().await;
+        // an id is only gone once none of its nodes are reported
+        for (id, _) in merged.values() {
+            gone.remove(id);
+        }
+        merged.extend(still_present);
+        present = merged;
+        if let Some(store) = &store {
+            if let Err(e) = store.apply(batch) {
+                log::error!("Unable to store address book changes {}", e);
+                status.error(format!("store: {}", e));
+            }
+        }
+        status.success(reported);
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
     }
 }
-
-async fn grab_address_book(address_book_url: &str) -> anyhow::Result<AddressBook> {
-    log::info!("Grabbing {}", address_book_url);
-    let addresses = terra_rust_api::Terra::address_book(address_book_url).await?;
-    Ok(addresses)
-}
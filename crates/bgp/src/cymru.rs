@@ -0,0 +1,110 @@
+use crate::errors::ConstellationBGPError;
+use chrono::Utc;
+use constellation_shared::state::{IpAsnMapping, ASN};
+
+/// an origin record: "13335 209242 | 1.1.1.0/24 | AU | apnic | 2011-08-11"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    /// every AS announcing the prefix. most answers have one
+    pub asns: Vec<String>,
+    pub prefix: String,
+    pub country: String,
+    /// eg. arin, ripencc
+    pub registry: String,
+    /// when the prefix was allocated, if known
+    pub allocated: Option<String>,
+}
+
+impl Origin {
+    /// how specific the prefix is. ranges that aren't a prefix count as least specific
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix
+            .split_once('/')
+            .and_then(|(_, len)| len.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// as kept in the state, which only has room for the first origin
+    pub fn mapping(&self) -> IpAsnMapping {
+        IpAsnMapping {
+            asn: self.asns[0].clone(),
+            range: self.prefix.clone(),
+            country: self.country.clone(),
+            network: self.registry.clone(),
+            last_updated: Utc::now(),
+        }
+    }
+}
+
+fn malformed(record: &str, reason: &'static str) -> ConstellationBGPError {
+    ConstellationBGPError::Malformed(record.to_string(), reason)
+}
+
+/// the fields of a TXT record, which some resolvers hand back still quoted
+fn fields(record: &str, max: usize) -> Vec<&str> {
+    record
+        .trim()
+        .trim_matches('"')
+        .splitn(max, '|')
+        .map(|f| f.trim())
+        .collect()
+}
+
+fn asn_number<'a>(record: &str, asn: &'a str) -> Result<&'a str, ConstellationBGPError> {
+    let asn = asn
+        .strip_prefix("AS")
+        .or_else(|| asn.strip_prefix("as"))
+        .unwrap_or(asn);
+    asn.parse::<u32>()
+        .map(|_| asn)
+        .map_err(|_| malformed(record, "AS number isn't a number"))
+}
+
+/// a TXT record from origin.asn.cymru.com or origin6.asn.cymru.com
+pub fn parse_origin(record: &str) -> Result<Origin, ConstellationBGPError> {
+    let fields = fields(record, 5);
+    if fields.len() < 4 {
+        return Err(malformed(
+            record,
+            "expected AS | prefix | country | registry | allocated",
+        ));
+    }
+    let asns = fields[0]
+        .split_whitespace()
+        .map(|asn| asn_number(record, asn).map(|a| a.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if asns.is_empty() {
+        return Err(malformed(record, "no AS number"));
+    }
+    if fields[1].is_empty() {
+        return Err(malformed(record, "no prefix"));
+    }
+    Ok(Origin {
+        asns,
+        prefix: fields[1].to_string(),
+        country: fields[2].to_string(),
+        registry: fields[3].to_string(),
+        allocated: fields
+            .get(4)
+            .filter(|allocated| !allocated.is_empty())
+            .map(|allocated| allocated.to_string()),
+    })
+}
+
+/// a TXT record from asn.cymru.com: "13335 | US | arin | 2010-07-14 | CLOUDFLARENET, US"
+pub fn parse_asn(record: &str) -> Result<ASN, ConstellationBGPError> {
+    let fields = fields(record, 5);
+    if fields.len() < 5 {
+        return Err(malformed(
+            record,
+            "expected AS | country | registry | allocated | name",
+        ));
+    }
+    Ok(ASN {
+        asn: asn_number(record, fields[0])?.to_string(),
+        country: fields[1].to_string(),
+        net: fields[2].to_string(),
+        desc: fields[4].to_string(),
+        last_updated: Utc::now(),
+    })
+}
@@ -4,4 +4,10 @@ use thiserror::Error;
 pub enum ConstellationBGPError {
     #[error("Bad IP ? {0}")]
     BadIp(String),
+    #[error("Bad nameserver {0}. expected ip or ip:port")]
+    BadNameserver(String),
+    #[error("Malformed cymru record '{0}': {1}")]
+    Malformed(String, &'static str),
+    #[error("ASN database {0}: {1}")]
+    Database(String, String),
 }
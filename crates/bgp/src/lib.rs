@@ -1,5 +1,12 @@
 // hi
+mod cymru;
 pub mod errors;
+mod origin;
+mod source;
 mod task;
 
-pub use task::run;
+pub use constellation_enrich::RateLimiter;
+pub use cymru::{parse_asn, parse_origin, Origin};
+pub use origin::origin_hostname;
+pub use source::{AsnSource, CymruDns, MmdbAsn, TsvAsn};
+pub use task::{run, BgpSettings};
@@ -0,0 +1,71 @@
+use crate::errors::ConstellationBGPError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// the cymru name whose TXT record holds the origin ASN for `ip`.
+/// IPv4 goes to origin.asn.cymru.com with the octets reversed, IPv6 to origin6.asn.cymru.com
+/// with the nibbles reversed. IPv4 mapped IPv6 addresses are looked up as the IPv4 address
+pub fn origin_hostname(ip: &str) -> Result<String, ConstellationBGPError> {
+    Ok(match parse_ip(ip)? {
+        IpAddr::V4(v4) => v4_hostname(&v4),
+        IpAddr::V6(v6) => v6_hostname(&v6),
+    })
+}
+
+/// an address book IP, with or without brackets. IPv4 mapped IPv6 addresses come back as IPv4
+pub(crate) fn parse_ip(ip: &str) -> Result<IpAddr, ConstellationBGPError> {
+    constellation_enrich::parse_ip(ip).ok_or_else(|| ConstellationBGPError::BadIp(ip.to_string()))
+}
+
+fn v4_hostname(ip: &Ipv4Addr) -> String {
+    let [a, b, c, d] = ip.octets();
+    format!("{}.{}.{}.{}.origin.asn.cymru.com.", d, c, b, a)
+}
+
+fn v6_hostname(ip: &Ipv6Addr) -> String {
+    let mut name = String::with_capacity(64 + 24);
+    for byte in ip.octets().iter().rev() {
+        name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+    }
+    name.push_str("origin6.asn.cymru.com.");
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverses_octets_and_nibbles() {
+        let expected = [
+            ("1.2.3.4", "4.3.2.1.origin.asn.cymru.com."),
+            ("203.0.113.7", "7.113.0.203.origin.asn.cymru.com."),
+            (
+                "2001:0db8:0000:0000:0000:0000:0567:89ab",
+                "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com.",
+            ),
+            (
+                "2001:db8::567:89ab",
+                "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com.",
+            ),
+            (
+                "[2606:4700:4700::1111]",
+                "1.1.1.1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.7.4.0.0.7.4.6.0.6.2.origin6.asn.cymru.com.",
+            ),
+            ("::ffff:192.0.2.1", "1.2.0.192.origin.asn.cymru.com."),
+        ];
+        for (ip, hostname) in &expected {
+            assert_eq!(origin_hostname(ip).unwrap(), *hostname, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn refuses_bad_ips() {
+        for bad in &["1.2.3", "1.2.3.256", "node.example.com", "2001:db8::g", ""] {
+            assert!(
+                matches!(origin_hostname(bad), Err(ConstellationBGPError::BadIp(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+}
@@ -0,0 +1,22 @@
+mod dns;
+mod mmdb;
+mod tsv;
+
+use crate::cymru::Origin;
+use async_trait::async_trait;
+use constellation_shared::state::ASN;
+
+pub use dns::CymruDns;
+pub use mmdb::MmdbAsn;
+pub use tsv::TsvAsn;
+
+/// where the bgp task finds out which ASN an IP is announced from, and who that ASN is
+#[async_trait]
+pub trait AsnSource: Send + Sync {
+    /// for logs, eg. "cymru dns via 1.1.1.1:53"
+    fn describe(&self) -> String;
+    /// `Ok(None)` when nothing announces the IP
+    async fn ip_asn(&self, ip: &str) -> anyhow::Result<Option<Origin>>;
+    /// `asn` is the number, without the AS
+    async fn asn(&self, asn: &str) -> anyhow::Result<Option<ASN>>;
+}
@@ -0,0 +1,106 @@
+use crate::cymru::{parse_asn, parse_origin, Origin};
+use crate::errors::ConstellationBGPError;
+use crate::origin::origin_hostname;
+use crate::source::AsnSource;
+use async_trait::async_trait;
+use constellation_enrich::RateLimiter;
+use constellation_shared::state::ASN;
+use std::net::{IpAddr, SocketAddr};
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// TXT lookups against team cymru's origin & asn zones
+pub struct CymruDns {
+    resolver: TokioAsyncResolver,
+    nameserver: Option<SocketAddr>,
+    /// every query waits its turn, origin and asn alike
+    limiter: RateLimiter,
+}
+
+impl CymruDns {
+    /// with no nameserver, the resolver's defaults are used.
+    /// `nameserver` is ip or ip:port, the port defaulting to 53.
+    /// at most `per_sec` queries a second go to it, shared with every other `CymruDns` using it
+    pub fn new(nameserver: Option<&str>, per_sec: u32) -> anyhow::Result<CymruDns> {
+        let nameserver = match nameserver {
+            Some(ns) => Some(
+                ns.parse::<SocketAddr>()
+                    .or_else(|_| ns.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| ConstellationBGPError::BadNameserver(ns.to_string()))?,
+            ),
+            None => None,
+        };
+        let config = match nameserver {
+            Some(ns) => ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&[ns.ip()], ns.port(), true),
+            ),
+            None => ResolverConfig::default(),
+        };
+        Ok(CymruDns {
+            resolver: TokioAsyncResolver::tokio(config, ResolverOpts::default())?,
+            nameserver,
+            limiter: RateLimiter::for_upstream(&upstream(nameserver), per_sec),
+        })
+    }
+
+    async fn txt(&self, hostname: &str) -> anyhow::Result<Vec<String>> {
+        self.limiter.acquire().await;
+        let txt_lookup = self.resolver.txt_lookup(hostname).await?;
+        Ok(txt_lookup.iter().map(|f| f.to_string()).collect())
+    }
+}
+
+/// eg. "cymru dns via 1.1.1.1:53"
+fn upstream(nameserver: Option<SocketAddr>) -> String {
+    match nameserver {
+        Some(ns) => format!("cymru dns via {}", ns),
+        None => "cymru dns".into(),
+    }
+}
+
+#[async_trait]
+impl AsnSource for CymruDns {
+    fn describe(&self) -> String {
+        upstream(self.nameserver)
+    }
+
+    /// when several prefixes hold the IP, the most specific wins
+    async fn ip_asn(&self, ip: &str) -> anyhow::Result<Option<Origin>> {
+        let hostname = origin_hostname(ip)?;
+        let mut origins = vec![];
+        let mut malformed = None;
+        for record in self.txt(&hostname).await? {
+            match parse_origin(&record) {
+                Ok(origin) => origins.push(origin),
+                Err(e) => {
+                    log::warn!("{} {}", hostname, e);
+                    malformed.get_or_insert(e);
+                }
+            }
+        }
+        match (
+            origins.into_iter().max_by_key(|o| o.prefix_len()),
+            malformed,
+        ) {
+            (Some(origin), _) => Ok(Some(origin)),
+            (None, Some(e)) => Err(e.into()),
+            (None, None) => {
+                log::info!("Unable to resolve {} via {}", ip, hostname);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn asn(&self, asn: &str) -> anyhow::Result<Option<ASN>> {
+        let hostname = format!("as{}.asn.cymru.com.", asn);
+        match self.txt(&hostname).await?.first() {
+            Some(record) => Ok(Some(parse_asn(record)?)),
+            None => {
+                log::info!("Unable to resolve AS{} via {}", asn, hostname);
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::cymru::Origin;
+use crate::errors::ConstellationBGPError;
+use crate::origin::parse_ip;
+use crate::source::AsnSource;
+use async_trait::async_trait;
+use chrono::Utc;
+use constellation_shared::state::ASN;
+use maxminddb::geoip2;
+use maxminddb::MaxMindDBError;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+
+/// a maxmind GeoLite2-ASN database
+pub struct MmdbAsn {
+    file: String,
+    reader: maxminddb::Reader<Vec<u8>>,
+    /// the database can only be searched by IP, so ASN names are kept from the IP lookups,
+    /// which the bgp task always does first
+    names: Mutex<HashMap<u32, String>>,
+}
+
+impl MmdbAsn {
+    pub fn open(file: &str) -> anyhow::Result<MmdbAsn> {
+        let reader = maxminddb::Reader::open_readfile(file)
+            .map_err(|e| ConstellationBGPError::Database(file.into(), e.to_string()))?;
+        if !reader.metadata.database_type.contains("ASN") {
+            log::warn!(
+                "{} is a {} database, not an ASN one",
+                file,
+                reader.metadata.database_type
+            );
+        }
+        Ok(MmdbAsn {
+            file: file.into(),
+            reader,
+            names: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// `ip` with everything past the first `prefix_len` bits cleared, as ip/prefix_len
+fn prefix(ip: &IpAddr, prefix_len: usize) -> String {
+    let network = match ip {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::from((u32::from(*v4) & mask).to_be_bytes())
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::from((u128::from(*v6) & mask).to_be_bytes())
+        }
+    };
+    format!("{}/{}", network, prefix_len)
+}
+
+#[async_trait]
+impl AsnSource for MmdbAsn {
+    fn describe(&self) -> String {
+        format!("mmdb {}", self.file)
+    }
+
+    async fn ip_asn(&self, ip: &str) -> anyhow::Result<Option<Origin>> {
+        let addr = parse_ip(ip)?;
+        let (found, prefix_len) = match self.reader.lookup_prefix::<geoip2::Asn>(addr) {
+            Ok(found) => found,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let asn = match found.autonomous_system_number {
+            Some(asn) => asn,
+            None => return Ok(None),
+        };
+        if let Some(name) = found.autonomous_system_organization {
+            self.names
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(asn, name.to_string());
+        }
+        Ok(Some(Origin {
+            asns: vec![asn.to_string()],
+            prefix: prefix(&addr, prefix_len),
+            // none of these are in the ASN database
+            country: "".into(),
+            registry: "".into(),
+            allocated: None,
+        }))
+    }
+
+    async fn asn(&self, asn: &str) -> anyhow::Result<Option<ASN>> {
+        let number = match asn.parse::<u32>() {
+            Ok(number) => number,
+            Err(_) => return Ok(None),
+        };
+        Ok(self
+            .names
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&number)
+            .map(|name| ASN {
+                asn: asn.to_string(),
+                country: "".into(),
+                net: "".into(),
+                desc: name.clone(),
+                last_updated: Utc::now(),
+            }))
+    }
+}
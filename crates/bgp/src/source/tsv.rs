@@ -0,0 +1,248 @@
+use crate::cymru::Origin;
+use crate::errors::ConstellationBGPError;
+use crate::origin::parse_ip;
+use crate::source::AsnSource;
+use async_trait::async_trait;
+use chrono::Utc;
+use constellation_shared::state::ASN;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// one line of the file
+struct Range {
+    start: u128,
+    end: u128,
+    asn: u32,
+}
+
+/// the ranges of one address family, sorted by start.
+/// `reach[i]` is the furthest end of any range up to i, so a lookup knows when to stop looking back
+#[derive(Default)]
+struct Table {
+    ranges: Vec<Range>,
+    reach: Vec<u128>,
+}
+
+impl Table {
+    fn index(&mut self) {
+        self.ranges
+            .sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        let mut furthest = 0;
+        self.reach = self
+            .ranges
+            .iter()
+            .map(|r| {
+                furthest = furthest.max(r.end);
+                furthest
+            })
+            .collect();
+    }
+
+    /// the narrowest range holding `ip`, so a more specific announcement wins
+    fn find(&self, ip: u128) -> Option<&Range> {
+        let mut i = self.ranges.partition_point(|r| r.start <= ip);
+        let mut best: Option<&Range> = None;
+        while i > 0 && self.reach[i - 1] >= ip {
+            i -= 1;
+            let range = &self.ranges[i];
+            if range.end >= ip
+                && best
+                    .map(|b| range.end - range.start < b.end - b.start)
+                    .unwrap_or(true)
+            {
+                best = Some(range);
+            }
+        }
+        best
+    }
+}
+
+/// an iptoasn.com style file: range_start, range_end, AS number, country, description, tab separated.
+/// IPv4 & IPv6 can be mixed. AS0 (not routed) is treated as no answer
+pub struct TsvAsn {
+    file: String,
+    v4: Table,
+    v6: Table,
+    /// AS number to (country, description)
+    asns: HashMap<u32, (String, String)>,
+}
+
+impl TsvAsn {
+    pub fn open(file: &str) -> anyhow::Result<TsvAsn> {
+        let bad = |line: usize, msg: &str| {
+            ConstellationBGPError::Database(file.into(), format!("line {}: {}", line, msg))
+        };
+        let mut db = TsvAsn {
+            file: file.into(),
+            v4: Table::default(),
+            v6: Table::default(),
+            asns: HashMap::new(),
+        };
+        let reader = BufReader::new(
+            File::open(file)
+                .map_err(|e| ConstellationBGPError::Database(file.into(), e.to_string()))?,
+        );
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 5 {
+                return Err(bad(n + 1, "expected 5 tab separated fields").into());
+            }
+            let start = fields[0]
+                .parse::<IpAddr>()
+                .map_err(|_| bad(n + 1, "bad range start"))?;
+            let end = fields[1]
+                .parse::<IpAddr>()
+                .map_err(|_| bad(n + 1, "bad range end"))?;
+            let asn = fields[2]
+                .parse::<u32>()
+                .map_err(|_| bad(n + 1, "bad AS number"))?;
+            let table = match (start, end) {
+                (IpAddr::V4(_), IpAddr::V4(_)) => &mut db.v4,
+                (IpAddr::V6(_), IpAddr::V6(_)) => &mut db.v6,
+                _ => return Err(bad(n + 1, "range mixes IPv4 & IPv6").into()),
+            };
+            let (start, end) = (number(&start), number(&end));
+            if start > end {
+                return Err(bad(n + 1, "range ends before it starts").into());
+            }
+            if asn == 0 {
+                continue;
+            }
+            table.ranges.push(Range { start, end, asn });
+            db.asns
+                .entry(asn)
+                .or_insert_with(|| (fields[3].trim().to_string(), fields[4].trim().to_string()));
+        }
+        db.v4.index();
+        db.v6.index();
+        log::info!(
+            "{}: {} IPv4 and {} IPv6 ranges, {} ASNs",
+            file,
+            db.v4.ranges.len(),
+            db.v6.ranges.len(),
+            db.asns.len()
+        );
+        Ok(db)
+    }
+}
+
+fn number(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(*v4) as u128,
+        IpAddr::V6(v6) => u128::from(*v6),
+    }
+}
+
+/// a range as a prefix (eg. 1.0.0.0/24) when it is one, otherwise start-end
+fn range_label(range: &Range, v4: bool) -> String {
+    let show = |n: u128| -> String {
+        if v4 {
+            Ipv4Addr::from(n as u32).to_string()
+        } else {
+            Ipv6Addr::from(n).to_string()
+        }
+    };
+    let bits = if v4 { 32 } else { 128 };
+    let size = (range.end - range.start).checked_add(1);
+    match size {
+        Some(size) if size.is_power_of_two() && range.start % size == 0 => {
+            format!("{}/{}", show(range.start), bits - size.trailing_zeros())
+        }
+        // the whole address space
+        None => format!("{}/0", show(0)),
+        _ => format!("{}-{}", show(range.start), show(range.end)),
+    }
+}
+
+#[async_trait]
+impl AsnSource for TsvAsn {
+    fn describe(&self) -> String {
+        format!("tsv {}", self.file)
+    }
+
+    async fn ip_asn(&self, ip: &str) -> anyhow::Result<Option<Origin>> {
+        let addr = parse_ip(ip)?;
+        let v4 = addr.is_ipv4();
+        let table = if v4 { &self.v4 } else { &self.v6 };
+        Ok(table.find(number(&addr)).map(|range| Origin {
+            asns: vec![range.asn.to_string()],
+            prefix: range_label(range, v4),
+            country: self
+                .asns
+                .get(&range.asn)
+                .map(|(country, _)| country.clone())
+                .unwrap_or_default(),
+            // neither is in the file
+            registry: "".into(),
+            allocated: None,
+        }))
+    }
+
+    async fn asn(&self, asn: &str) -> anyhow::Result<Option<ASN>> {
+        let number = match asn.parse::<u32>() {
+            Ok(number) => number,
+            Err(_) => return Ok(None),
+        };
+        Ok(self.asns.get(&number).map(|(country, desc)| ASN {
+            asn: asn.to_string(),
+            country: country.clone(),
+            net: "".into(),
+            desc: desc.clone(),
+            last_updated: Utc::now(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn looks_up_the_sample() {
+        let file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/ip2asn-sample.tsv"
+        );
+        let source = TsvAsn::open(file).unwrap();
+        let expected = [
+            ("1.0.0.7", Some(("13335", "1.0.0.0/24", "US"))),
+            // AS0, not routed
+            ("1.0.2.1", None),
+            ("::ffff:8.8.8.8", Some(("15169", "8.8.8.0/24", "US"))),
+            // inside 45.0.0.0/8, but 45.76.0.0/16 is more specific
+            ("45.76.1.1", Some(("20473", "45.76.0.0/16", "US"))),
+            ("45.1.1.1", Some(("64500", "45.0.0.0/8", "ZZ"))),
+            ("95.216.5.5", Some(("24940", "95.216.0.0/15", "DE"))),
+            (
+                "136.243.1.15",
+                Some(("24940", "136.243.1.10-136.243.1.20", "DE")),
+            ),
+            ("136.243.1.21", None),
+            ("[2a01:4f8:1::1]", Some(("24940", "2a01:4f8::/32", "DE"))),
+            (
+                "2606:4700:4700::1111",
+                Some(("13335", "2606:4700::/32", "US")),
+            ),
+            ("2001:db9::1", None),
+        ];
+        for (ip, want) in &expected {
+            let found = source.ip_asn(ip).await.unwrap();
+            let got = found
+                .as_ref()
+                .map(|f| (f.asns[0].as_str(), f.prefix.as_str(), f.country.as_str()));
+            assert_eq!(got, *want, "{}", ip);
+        }
+        let google = source.asn("15169").await.unwrap().unwrap();
+        assert_eq!(
+            (google.desc.as_str(), google.country.as_str()),
+            ("GOOGLE", "US")
+        );
+        assert!(source.asn("64999").await.unwrap().is_none());
+    }
+}
@@ -1,162 +1,306 @@
-use crate::errors::ConstellationBGPError::BadIp;
+use crate::cymru::Origin;
+use crate::source::AsnSource;
+use actix_broker::{Broker, SystemBroker};
 use chrono::Utc;
-use constellation_shared::state::{AppState, IpAsnMapping, ASN};
+use constellation_enrich::{
+    save_enrichment, AppEnrichment, Change, Pipeline, RateLimiter, Refresh,
+};
+use constellation_node_events::MessageIpAsnChanged;
+use constellation_shared::state::ASN;
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
+use constellation_store::{Batch, Store, Table};
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time;
-use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
-use trust_dns_resolver::TokioAsyncResolver;
 
-pub async fn run(state: AppState, period: Duration) {
-    let mut interval = time::interval(period);
+/// lookups are slow, so what has been found is published as it goes rather than all at the end.
+/// each publish copies the whole state, so not more often than this
+const PUBLISH_EVERY: Duration = Duration::from_secs(10);
 
-    match &TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()) {
-        Ok(resolver) => loop {
-            let mut ips_tbd: Vec<String> = vec![];
-            let mut work_ip_asn: HashMap<String, IpAsnMapping> = HashMap::new();
-            let mut work_asn: HashMap<String, ASN>;
-            {
-                let the_state = state.lock().unwrap();
-                for ip in &the_state.new_ips_bgp {
-                    if !the_state.ip_asn.contains_key(ip) {
-                        ips_tbd.push(ip.to_string());
-                    }
+/// an IP's origin, and the details of any of its ASNs not seen before
+struct Found {
+    origin: Origin,
+    asns: Vec<ASN>,
+    /// ASNs with no details, which wait out a backoff before being asked about again
+    missing_asns: Vec<String>,
+}
+
+/// how the bgp task runs
+#[derive(Clone, Debug)]
+pub struct BgpSettings {
+    pub period: Duration,
+    /// lookups in flight at once
+    pub concurrency: usize,
+    pub refresh: Refresh,
+    /// where what was missed & changed is saved after each run
+    pub enrichment_file: String,
+    pub chain: String,
+}
+
+/// look up the ASN of every new IP in `source`, every period, along with those whose answer
+/// is older than the refresh TTL. IPs with no answer wait out a backoff before being tried again.
+/// `concurrency` lookups run at once, and the source keeps its queries to what its upstream will take.
+/// returns once `shutdown` turns true
+pub async fn run(
+    state: SharedState,
+    source: Arc<dyn AsnSource>,
+    enrichment: AppEnrichment,
+    store: Option<Store>,
+    settings: BgpSettings,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let BgpSettings {
+        period,
+        concurrency,
+        refresh,
+        enrichment_file,
+        chain,
+    } = settings;
+    let mut interval = time::interval(period);
+    log::info!("Looking up ASNs in {}", source.describe());
+    // a source with an upstream limits each of its own queries, as one IP can take several
+    let pipeline = Pipeline::new(
+        &chain,
+        "bgp",
+        concurrency,
+        PUBLISH_EVERY,
+        RateLimiter::unlimited(),
+    );
+    loop {
+        let now = Utc::now();
+        let stale_before = refresh.stale_before(now);
+        let mut ips_tbd: Vec<String> = vec![];
+        // ASNs already known, or being looked up by another worker
+        let known_asns: Arc<Mutex<HashSet<String>>>;
+        let mut batch = Batch::default();
+        {
+            let the_state = state.snapshot();
+            let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+            enrich.prune(&the_state);
+            for ip in &the_state.new_ips_bgp {
+                if !the_state.ip_asn.contains_key(ip) && enrich.due("bgp", ip, now) {
+                    ips_tbd.push(ip.to_string());
                 }
-                work_asn = the_state.asn.clone();
             }
-            if !ips_tbd.is_empty() {
-                {
-                    log::info!("New IPS = {}", ips_tbd.len());
-                    for ip in ips_tbd {
-                        match grab_asn_for_ip(resolver, &ip).await {
-                            Ok(ip_asn_det) => match ip_asn_det {
-                                Some(det) => {
-                                    log::info!("Filled in IP {}", ip);
-                                    work_ip_asn.insert(ip.to_string(), det.clone());
-                                    if !work_asn.contains_key(&det.asn) {
-                                        match grab_asn_details(resolver, &det.asn).await {
-                                            Ok(asn_det) => match asn_det {
-                                                Some(a) => {
-                                                    let mut the_state = state.lock().unwrap();
-                                                    work_asn.insert(det.asn.clone(), a.clone());
-                                                    the_state.asn.insert(det.asn.clone(), a);
-                                                }
-                                                None => {
-                                                    log::info!("ASN - no response {}", &det.asn);
-                                                }
-                                            },
-                                            Err(e) => {
-                                                log::error!(
-                                                    "Fetching info for ASN AS{} - {}",
-                                                    &det.asn,
-                                                    e.to_string()
-                                                );
-                                            }
-                                        };
-                                    }
-                                    {
-                                        let mut the_state = state.lock().unwrap();
-                                        the_state.ip_asn.insert(ip.clone(), det.clone());
-                                        the_state.new_ips_bgp.remove(&ip);
-                                        match the_state.asn_ip.get(&det.asn) {
-                                            Some(set) => {
-                                                let mut new_set = set.clone();
-                                                new_set.insert(ip);
-                                                the_state.asn_ip.insert(det.asn, new_set.clone());
-                                            }
-                                            None => {
-                                                let mut set: HashSet<String> = HashSet::new();
-                                                set.insert(ip);
-                                                the_state.asn_ip.insert(det.asn, set.clone());
-                                            }
-                                        }
-                                    }
-                                }
-                                None => {
-                                    log::info!("Filled in IP {} - no response", ip);
-                                }
-                            },
-                            Err(e) => {
-                                log::error!("Fetching info for IP {} - {}", ip, e.to_string())
-                            }
-                        }
-                    }
-                }
-            } else {
-                log::info!("No new IPs to scan");
+            let (stale, picked) = refresh.pick_stale(
+                the_state
+                    .ip_asn
+                    .iter()
+                    .filter(|(ip, _)| enrich.due("bgp", ip, now))
+                    .map(|(ip, mapping)| (ip.as_str(), mapping.last_updated)),
+                now,
+            );
+            if stale > 0 {
+                log::info!("Stale IPS = {}, refreshing {}", stale, picked.len());
             }
-            interval.tick().await;
-        },
-        Err(e) => log::error!("Unable to start BGP {}", e),
+            ips_tbd.extend(picked);
+            // stale ASN details are looked up again along with the next IP in them,
+            // and those with no details once their backoff is over
+            known_asns = Arc::new(Mutex::new(
+                the_state
+                    .asn
+                    .iter()
+                    .filter(|(_, a)| stale_before.map(|s| a.last_updated >= s).unwrap_or(true))
+                    .map(|(asn, _)| asn)
+                    .chain(enrich.backed_off("asn", now))
+                    .cloned()
+                    .collect(),
+            ));
+        }
+        let mut filled: usize = 0;
+        if !ips_tbd.is_empty() {
+            log::info!("IPS to look up = {}", ips_tbd.len());
+            let tally = pipeline
+                .run(
+                    ips_tbd,
+                    |ip| lookup(source.clone(), known_asns.clone(), ip),
+                    |found| publish(&state, &enrichment, &refresh, found, &mut batch),
+                )
+                .await;
+            log::info!("BGP: {}", tally);
+            filled = tally.found;
+            let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+            for ip in &tally.missed {
+                enrich.missed("bgp", ip, now, &refresh);
+            }
+            constellation_metrics::enrich_backed_off(&chain, "bgp", enrich.waiting("bgp"));
+            if let Some(e) = tally.last_error {
+                status.error(e);
+            }
+        } else {
+            log::info!("No new or stale IPs to scan");
+        }
+        if let Err(e) = save_enrichment(&enrichment, &enrichment_file) {
+            log::error!("Unable to save {} {}", enrichment_file, e);
+            status.error(format!("save {}: {}", enrichment_file, e));
+        }
+        if let Some(store) = &store {
+            if let Err(e) = store.apply(batch) {
+                log::error!("Unable to store BGP changes {}", e);
+                status.error(format!("store: {}", e));
+            }
+        }
+        status.success(filled);
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
     }
 }
 
-async fn grab_asn_for_ip(
-    resolver: &TokioAsyncResolver,
-    ip: &str,
-) -> anyhow::Result<Option<IpAsnMapping>> {
-    log::info!("Grabbing ASN for IP {}", ip);
-    let bits = ip.split('.').collect::<Vec<&str>>();
-    if bits.len() == 4 {
-        let hostname = format!(
-            "{}.{}.{}.{}.origin.asn.cymru.com.",
-            bits[3], bits[2], bits[1], bits[0]
-        );
-        Ok(match dns_resolve_txt(resolver, &hostname).await? {
-            Some(ip_asn_mapping) => {
-                let bits = ip_asn_mapping.split('|').collect::<Vec<_>>();
-                let asn_num = bits[0].trim().split(' ').collect::<Vec<&str>>();
-                Some(IpAsnMapping {
-                    asn: asn_num[0].trim().to_string(),
-                    range: bits[1].trim().to_string(),
-                    country: bits[2].trim().to_string(),
-                    network: bits[3].trim().to_string(),
-                    last_updated: Utc::now(),
-                })
+/// the IP's origin, then the details of each of its ASNs that no one else has
+async fn lookup(
+    source: Arc<dyn AsnSource>,
+    known_asns: Arc<Mutex<HashSet<String>>>,
+    ip: String,
+) -> anyhow::Result<Option<Found>> {
+    let origin = match source.ip_asn(&ip).await? {
+        Some(origin) => origin,
+        None => return Ok(None),
+    };
+    let mut asns = vec![];
+    let mut missing_asns = vec![];
+    for asn in &origin.asns {
+        if !known_asns
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(asn.clone())
+        {
+            continue;
+        }
+        match source.asn(asn).await {
+            Ok(Some(details)) => asns.push(details),
+            Ok(None) => {
+                log::info!("ASN - no response {}", asn);
+                missing_asns.push(asn.clone());
             }
-            None => {
-                log::info!("Unable to resolve {} via {}", ip, hostname);
-                None
+            Err(e) => {
+                // leave it for the next IP in the ASN
+                log::error!("Fetching info for ASN AS{} - {}", asn, e);
+                known_asns
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(asn);
             }
-        })
-    } else {
-        Err(BadIp(ip.to_string()).into())
+        }
     }
+    Ok(Some(Found {
+        origin,
+        asns,
+        missing_asns,
+    }))
 }
-async fn grab_asn_details(resolver: &TokioAsyncResolver, asn: &str) -> anyhow::Result<Option<ASN>> {
-    log::info!("Grabbing ASN details for AS{}", asn);
-
-    let hostname = format!("as{}.asn.cymru.com.", asn);
-    Ok(match dns_resolve_txt(resolver, &hostname).await? {
-        Some(ip_asn_mapping) => {
-            let bits = ip_asn_mapping.split('|').collect::<Vec<_>>();
-            Some(ASN {
-                asn: bits[0].trim().to_string(),
 
-                country: bits[1].trim().to_string(),
-                net: bits[2].trim().to_string(),
-                desc: bits[4].trim().to_string(),
-                last_updated: Utc::now(),
-            })
+/// move what has been looked up so far into the state, in one update.
+/// `ip_asn` only has room for the first origin, so every origin is kept in the enrichment as well,
+/// and the IP is listed under each of them in `asn_ip`.
+/// an IP looked up again is taken out of every ASN no longer among its origins, and a change of
+/// first origin is recorded
+fn publish(
+    state: &SharedState,
+    enrichment: &AppEnrichment,
+    refresh: &Refresh,
+    found: Vec<(String, Found)>,
+    batch: &mut Batch,
+) {
+    let mut answered = vec![];
+    let mut details = vec![];
+    let mut missing = vec![];
+    let mut moved = vec![];
+    state.update(|the_state| {
+        // every ASN the IPs being published are listed under now
+        let looked_up = found
+            .iter()
+            .map(|(ip, _)| ip.as_str())
+            .collect::<HashSet<_>>();
+        let mut listed: HashMap<String, Vec<String>> = HashMap::new();
+        for (asn, ips) in &the_state.asn_ip {
+            for ip in ips.iter().filter(|ip| looked_up.contains(ip.as_str())) {
+                listed.entry(ip.clone()).or_default().push(asn.clone());
+            }
         }
-        None => {
-            log::info!("Unable to resolve AS{} via {}", asn, hostname);
-            None
+        for (ip, found) in found {
+            let Found {
+                origin,
+                asns,
+                missing_asns,
+            } = found;
+            missing.extend(missing_asns);
+            for a in asns {
+                details.push(a.asn.clone());
+                batch.put(Table::Asn, &a.asn, &a);
+                the_state.asn.insert(a.asn.clone(), a);
+            }
+            let mut det = origin.mapping();
+            // multi-origin prefixes don't always list their origins in the same order, so an IP
+            // keeps its ASN while it is still one of them
+            if let Some(previous) = the_state.ip_asn.get(&ip) {
+                if origin.asns.contains(&previous.asn) {
+                    det.asn = previous.asn.clone();
+                }
+            }
+            let mut origins = vec![det.asn.clone()];
+            origins.extend(origin.asns.iter().filter(|asn| **asn != det.asn).cloned());
+            answered.push((ip.clone(), origins));
+            if let Some(previous) = the_state.ip_asn.insert(ip.clone(), det.clone()) {
+                if previous.asn != det.asn {
+                    moved.push((ip.clone(), previous.asn, det.asn.clone()));
+                }
+            }
+            for gone in listed.remove(&ip).unwrap_or_default() {
+                if origin.asns.contains(&gone) {
+                    continue;
+                }
+                if let Some(set) = the_state.asn_ip.get_mut(&gone) {
+                    set.remove(&ip);
+                    if set.is_empty() {
+                        the_state.asn_ip.remove(&gone);
+                        batch.remove(Table::AsnIp, &gone);
+                    } else {
+                        batch.put(Table::AsnIp, &gone, &*set);
+                    }
+                }
+            }
+            the_state.new_ips_bgp.remove(&ip);
+            batch.put(Table::IpAsn, &ip, &det);
+            batch.remove(Table::NewIpsBgp, &ip);
+            for asn in &origin.asns {
+                let set = the_state
+                    .asn_ip
+                    .entry(asn.clone())
+                    .or_insert_with(HashSet::new);
+                set.insert(ip.clone());
+                batch.put(Table::AsnIp, asn, &*set);
+            }
         }
-    })
-}
-async fn dns_resolve_txt(
-    resolver: &TokioAsyncResolver,
-    hostname: &str,
-) -> anyhow::Result<Option<String>> {
-    let txt_lookup = resolver.txt_lookup(hostname).await?;
-    match txt_lookup
-        .iter()
-        .map(|f| f.to_string())
-        .collect::<Vec<_>>()
-        .first()
-    {
-        Some(txt_return) => Ok(Some(txt_return.to_string())),
-        None => Ok(None),
+    });
+    let now = Utc::now();
+    let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+    for (ip, origins) in &answered {
+        enrich.found("bgp", ip);
+        enrich.set_origins(ip, origins);
+    }
+    for asn in &details {
+        enrich.found("asn", asn);
+    }
+    for asn in &missing {
+        enrich.missed("asn", asn, now, refresh);
+    }
+    for (ip, previous, asn) in moved {
+        log::info!("{} moved from AS{} to AS{}", ip, previous, asn);
+        enrich.changed(
+            &ip,
+            Change {
+                at: Utc::now(),
+                what: "asn".into(),
+                from: previous.clone(),
+                to: asn.clone(),
+            },
+        );
+        Broker::<SystemBroker>::issue_async(MessageIpAsnChanged { ip, asn, previous });
     }
 }
@@ -0,0 +1,142 @@
+use constellation_bgp::errors::ConstellationBGPError;
+use constellation_bgp::{parse_asn, parse_origin};
+use constellation_enrich::Enrichment;
+use std::fs;
+
+const ORIGIN_FIELDS: &str = "expected AS | prefix | country | registry | allocated";
+const ASN_FIELDS: &str = "expected AS | country | registry | allocated | name";
+
+/// the records in tests/fixtures/cymru/`file`, without comments & blank lines
+fn records(file: &str) -> Vec<String> {
+    let path = format!(
+        "{}/tests/fixtures/cymru/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        file
+    );
+    fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {}", path, e))
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// the reason `result` was refused, checking it names the record
+fn refused<T>(record: &str, result: Result<T, ConstellationBGPError>) -> &'static str {
+    match result {
+        Err(ConstellationBGPError::Malformed(refused, reason)) => {
+            assert_eq!(refused, record);
+            reason
+        }
+        Err(e) => panic!("'{}' should be malformed, got {}", record, e),
+        Ok(_) => panic!("'{}' should be refused", record),
+    }
+}
+
+#[test]
+fn good_records_parse() {
+    for record in records("origin.txt") {
+        let origin = parse_origin(&record).unwrap_or_else(|e| panic!("{}", e));
+        assert!(!origin.asns.is_empty(), "{}", record);
+        assert!(!origin.prefix.is_empty(), "{}", record);
+    }
+    for record in records("asn.txt") {
+        parse_asn(&record).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+#[test]
+fn malformed_records_are_refused() {
+    for record in records("origin-malformed.txt") {
+        refused(&record, parse_origin(&record));
+    }
+    for record in records("asn-malformed.txt") {
+        refused(&record, parse_asn(&record));
+    }
+}
+
+#[test]
+fn keeps_every_origin() {
+    let multi = parse_origin("13335 209242 | 104.16.0.0/13 | US | arin | 2014-03-28").unwrap();
+    assert_eq!(multi.asns, ["13335", "209242"]);
+    // the first is what ip_asn gets
+    assert_eq!(multi.mapping().asn, "13335");
+    assert_eq!(multi.prefix_len(), 13);
+}
+
+#[test]
+fn keeps_every_origin_of_the_fixtures() {
+    let mut enrichment = Enrichment::default();
+    let mut multi = 0;
+    for (n, record) in records("origin.txt").iter().enumerate() {
+        let origin = parse_origin(record).unwrap();
+        let ip = format!("10.0.0.{}", n);
+        enrichment.set_origins(&ip, &origin.asns);
+        assert_eq!(
+            enrichment.origins(&ip, &origin.mapping().asn),
+            origin.asns,
+            "{}",
+            record
+        );
+        if origin.asns.len() > 1 {
+            multi += 1;
+        }
+    }
+    assert!(multi > 0, "origin.txt should have a multi-origin record");
+    // only the IPs with more than one origin need keeping
+    assert_eq!(enrichment.origins.len(), multi);
+}
+
+#[test]
+fn refuses_truncated_records() {
+    for record in &[
+        "13335",
+        "13335 |",
+        "13335 | 1.1.1.0/24",
+        "13335 | 1.1.1.0/24 | AU",
+    ] {
+        assert_eq!(refused(record, parse_origin(record)), ORIGIN_FIELDS);
+    }
+    for record in &["13335 | US | arin | 2010-07-14", "\"\""] {
+        assert_eq!(refused(record, parse_asn(record)), ASN_FIELDS);
+    }
+}
+
+#[test]
+fn refuses_empty_fields() {
+    let record = " | 1.1.1.0/24 | AU | apnic | 2011-08-11";
+    assert_eq!(refused(record, parse_origin(record)), "no AS number");
+    let record = "13335 |  | AU | apnic | 2011-08-11";
+    assert_eq!(refused(record, parse_origin(record)), "no prefix");
+    let record = " | US | arin | 2010-07-14 | CLOUDFLARENET, US";
+    assert_eq!(
+        refused(record, parse_asn(record)),
+        "AS number isn't a number"
+    );
+
+    // an empty allocation date or name is still an answer
+    let undated = parse_origin("63949 | 172.104.0.0/15 | US | arin |").unwrap();
+    assert!(undated.allocated.is_none());
+    assert_eq!(undated.registry, "arin");
+    assert_eq!(parse_asn("64512 | ZZ | other | |").unwrap().desc, "");
+}
+
+#[test]
+fn handles_unexpected_field_counts() {
+    // a 4 field origin has no allocation date
+    let short = parse_origin("14061 | 167.99.0.0/16 | US | arin").unwrap();
+    assert!(short.allocated.is_none());
+    // the name is the last field, and may have a pipe in it
+    let piped = parse_asn("65001 | US | arin | 2001-01-01 | ODD | NAME, US").unwrap();
+    assert_eq!(piped.desc, "ODD | NAME, US");
+    let record = "v=spf1 include:_spf.google.com ~all";
+    assert_eq!(refused(record, parse_origin(record)), ORIGIN_FIELDS);
+    assert_eq!(refused(record, parse_asn(record)), ASN_FIELDS);
+}
+
+#[test]
+fn unquotes_records() {
+    let quoted = parse_asn("\"14061 | US | arin | 2012-09-25 | DIGITALOCEAN-ASN, US\"").unwrap();
+    assert_eq!(quoted.asn, "14061");
+    assert_eq!(quoted.desc, "DIGITALOCEAN-ASN, US");
+}
@@ -1,2 +1,4 @@
+mod gateway;
 mod validator;
-pub use validator::DiscordValidatorActor;
+pub use gateway::{DiscordRoute, GatewayRelay};
+pub use validator::{DiscordValidatorActor, MessageDrain};
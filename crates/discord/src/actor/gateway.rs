@@ -0,0 +1,120 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::actor::DiscordValidatorActor;
+use crate::BrokerType;
+use actix::prelude::*;
+use actix_broker::BrokerSubscribe;
+use actor_discord::types::events::{ChannelEvent, Event, MessageEvent};
+use constellation_shared::MessageSendMessageEvent;
+
+/// how often the relay looks for chain actors that have started since it last looked
+const INTRODUCE_EVERY: Duration = Duration::from_secs(5);
+
+/// where a chain's discord actor can be found once it has started. empty while it isn't running
+pub type DiscordRoute = Arc<Mutex<Option<Addr<DiscordValidatorActor>>>>;
+
+/// the process runs one gateway session, whose events arrive on the process broker.
+/// this passes them on to each chain's actor, on whichever thread it is running, along with
+/// the alerts of the modules that run once for the whole process
+pub struct GatewayRelay {
+    routes: Vec<DiscordRoute>,
+    /// the actor each route was last given the guild's channels
+    introduced: Vec<Option<Addr<DiscordValidatorActor>>>,
+    /// the guild's channel list is only sent when the gateway connects, so actors that start
+    /// afterwards are given this one
+    guild: Option<Event>,
+}
+
+impl GatewayRelay {
+    pub fn new(routes: Vec<DiscordRoute>) -> GatewayRelay {
+        let introduced = vec![None; routes.len()];
+        GatewayRelay {
+            routes,
+            introduced,
+            guild: None,
+        }
+    }
+
+    fn actors(&self) -> Vec<Option<Addr<DiscordValidatorActor>>> {
+        self.routes
+            .iter()
+            .map(|route| route.lock().unwrap_or_else(PoisonError::into_inner).clone())
+            .collect()
+    }
+
+    fn forward<M>(&self, msg: M)
+    where
+        M: Message<Result = ()> + Send + Clone + 'static,
+        DiscordValidatorActor: Handler<M>,
+    {
+        for actor in self.actors().into_iter().flatten() {
+            actor.do_send(msg.clone());
+        }
+    }
+
+    /// hand the guild's channels to the actors that haven't had them
+    fn introduce(&mut self) {
+        let guild = match &self.guild {
+            Some(guild) => guild.clone(),
+            None => return,
+        };
+        for (i, actor) in self.actors().into_iter().enumerate() {
+            if let Some(addr) = &actor {
+                if actor != self.introduced[i] {
+                    addr.do_send(guild.clone());
+                }
+            }
+            self.introduced[i] = actor;
+        }
+    }
+}
+
+impl Actor for GatewayRelay {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_sync::<BrokerType, Event>(ctx);
+        self.subscribe_sync::<BrokerType, MessageEvent>(ctx);
+        self.subscribe_sync::<BrokerType, ChannelEvent>(ctx);
+        self.subscribe_sync::<BrokerType, MessageSendMessageEvent>(ctx);
+        ctx.run_interval(INTRODUCE_EVERY, |act, _ctx| act.introduce());
+        log::info!("Discord gateway relaying to {} chains", self.routes.len());
+    }
+}
+
+impl Handler<Event> for GatewayRelay {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event, _ctx: &mut Self::Context) {
+        if let Event::GuildCreate(_) = &msg {
+            self.guild = Some(msg.clone());
+            self.introduced = self.actors();
+        }
+        self.forward(msg);
+    }
+}
+
+impl Handler<MessageEvent> for GatewayRelay {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageEvent, _ctx: &mut Self::Context) {
+        self.forward(msg);
+    }
+}
+
+impl Handler<ChannelEvent> for GatewayRelay {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelEvent, _ctx: &mut Self::Context) {
+        self.forward(msg);
+    }
+}
+
+impl Handler<MessageSendMessageEvent> for GatewayRelay {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageSendMessageEvent, _ctx: &mut Self::Context) {
+        self.forward(msg);
+    }
+}
@@ -12,6 +12,13 @@ use constellation_shared::{
     MessageSendMessageEvent, MessageValidator, MessageValidatorEvent, SendMessageEventType,
 };
 use std::collections::hash_map::Entry;
+use std::future::Future;
+use tokio::sync::oneshot;
+
+/// resolves once all the pending discord calls have gone out
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MessageDrain {}
 
 pub struct BlockHeightTime {
     pub datetime: DateTime<Utc>,
@@ -36,7 +43,15 @@ pub struct DiscordValidatorActor {
     pub connect_addr: String,
     pub announcement_channel: Option<GuildChannel>,
     pub private_channel: Option<GuildChannel>,
+    pub announcement_channel_name: String,
+    pub private_channel_name: String,
     pub max_retries: usize,
+    /// the chain this actor reports on, used for explorer links
+    pub chain_id: String,
+    /// discord calls spawned, but not finished yet
+    pub in_flight: usize,
+    /// shutdown is waiting for the in-flight calls to finish
+    pub drain_waiters: Vec<oneshot::Sender<()>>,
 }
 
 impl DiscordValidatorActor {
@@ -45,6 +60,9 @@ impl DiscordValidatorActor {
         //   category_prefix: &str,
         connect_addr: &str,
         max_retries: usize,
+        announcement_channel_name: &str,
+        private_channel_name: &str,
+        chain_id: &str,
     ) -> anyhow::Result<DiscordValidatorActor> {
         log::info!("Discord Starting");
 
@@ -61,9 +79,32 @@ impl DiscordValidatorActor {
             guild_id: None,
             announcement_channel: None,
             private_channel: None,
+            announcement_channel_name: announcement_channel_name.into(),
+            private_channel_name: private_channel_name.into(),
             max_retries,
+            chain_id: chain_id.into(),
+            in_flight: 0,
+            drain_waiters: vec![],
         })
     }
+
+    /// run a discord call in the background, keeping track of it so shutdown can wait for it
+    fn send_later<F>(&mut self, call: F, ctx: &mut Context<Self>)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        self.in_flight += 1;
+        call.into_actor(self)
+            .map(|_, act, _ctx| {
+                act.in_flight -= 1;
+                if act.in_flight == 0 {
+                    for waiter in act.drain_waiters.drain(..) {
+                        let _ = waiter.send(());
+                    }
+                }
+            })
+            .spawn(ctx);
+    }
 }
 impl Actor for DiscordValidatorActor {
     type Context = Context<Self>;
@@ -111,18 +152,24 @@ impl Handler<MessageValidator> for DiscordValidatorActor {
                             sanitized
                         );
 
-                        async move {
-                            let create_ch = discord_api.create_channel(guild, details).await;
-                            match create_ch {
-                                Ok(ch) => log::info!("Channel {} being created", ch.name),
-                                Err(e) => log::error!("Error {}", e),
-                            }
-                        }
-                        .into_actor(self)
-                        .spawn(ctx)
+                        let chain_id = self.chain_id.clone();
+                        self.send_later(
+                            async move {
+                                let create_ch = discord_api.create_channel(guild, details).await;
+                                match create_ch {
+                                    Ok(ch) => log::info!("Channel {} being created", ch.name),
+                                    Err(e) => {
+                                        log::error!("Error {}", e);
+                                        constellation_metrics::discord_send_failed(&chain_id)
+                                    }
+                                }
+                            },
+                            ctx,
+                        );
                     }
                     Err(e) => {
-                        log::error!("Unable to build discord API? {}", e)
+                        log::error!("Unable to build discord API? {}", e);
+                        constellation_metrics::discord_send_failed(&self.chain_id)
                     }
                 }
             }
@@ -154,8 +201,8 @@ impl Handler<MessageValidatorEvent> for DiscordValidatorActor {
         };
         let hash_url = msg.hash.map(|hash| {
             format!(
-                "[hash](https://finder.extraterrestrial.money/columbus-4/tx/{})",
-                hash
+                "[hash](https://finder.extraterrestrial.money/{}/tx/{})",
+                self.chain_id, hash
             )
         });
         let announce = if self.announcement_channel.is_some() {
@@ -216,54 +263,69 @@ impl Handler<MessageValidatorEvent> for DiscordValidatorActor {
                     let announcement_channel = self.announcement_channel;
                     let private_channel = self.private_channel;
                     let message_type = msg.event_type;
+                    let chain_id = self.chain_id.clone();
 
                     //  let moniker = msg.moniker.unwrap_or(operator.clone()).clone();
-                    async move {
-                        if let Some(announce_msg) = announce {
-                            let _ann_result = api
-                                .create_message(
-                                    announcement_channel.unwrap().channel_id,
-                                    announce_msg,
-                                )
-                                .await;
-                        };
-                        match message_type {
-                            SendMessageEventType::PRIVATE => {
-                                if let Some(private) = private_channel {
-                                    let msg_result =
-                                        api.create_message(private.channel_id, message).await;
+                    self.send_later(
+                        async move {
+                            if let Some(announce_msg) = announce {
+                                if let Err(e) = api
+                                    .create_message(
+                                        announcement_channel.unwrap().channel_id,
+                                        announce_msg,
+                                    )
+                                    .await
+                                {
+                                    log::error!("Error sending announcement {}", e);
+                                    constellation_metrics::discord_send_failed(&chain_id)
+                                }
+                            };
+                            match message_type {
+                                SendMessageEventType::PRIVATE => {
+                                    if let Some(private) = private_channel {
+                                        let msg_result =
+                                            api.create_message(private.channel_id, message).await;
+                                        match msg_result {
+                                            Ok(m) => {
+                                                log::info!(
+                                                    "private message sent to {} {}",
+                                                    moniker.clone(),
+                                                    m.id.to_string()
+                                                )
+                                            }
+                                            Err(e) => {
+                                                log::error!("Error sending message {}", e);
+                                                constellation_metrics::discord_send_failed(
+                                                    &chain_id,
+                                                )
+                                            }
+                                        };
+                                    }
+                                }
+                                _ => {
+                                    let msg_result = api.create_message(channel, message).await;
                                     match msg_result {
                                         Ok(m) => {
-                                            log::info!(
-                                                "private message sent to {} {}",
+                                            log::debug!(
+                                                "message sent to {} {}",
                                                 moniker.clone(),
                                                 m.id.to_string()
                                             )
                                         }
-                                        Err(e) => log::error!("Error sending message {}", e),
+                                        Err(e) => {
+                                            log::error!("Error sending message {}", e);
+                                            constellation_metrics::discord_send_failed(&chain_id)
+                                        }
                                     };
                                 }
                             }
-                            _ => {
-                                let msg_result = api.create_message(channel, message).await;
-                                match msg_result {
-                                    Ok(m) => {
-                                        log::debug!(
-                                            "message sent to {} {}",
-                                            moniker.clone(),
-                                            m.id.to_string()
-                                        )
-                                    }
-                                    Err(e) => log::error!("Error sending message {}", e),
-                                };
-                            }
-                        }
-                    }
-                    .into_actor(self)
-                    .spawn(ctx)
+                        },
+                        ctx,
+                    );
                 }
                 Err(e) => {
-                    log::error!("Unable to create discord api {}", e)
+                    log::error!("Unable to create discord api {}", e);
+                    constellation_metrics::discord_send_failed(&self.chain_id)
                 }
             }
         } else {
@@ -279,8 +341,8 @@ impl Handler<MessageSendMessageEvent> for DiscordValidatorActor {
 
         let hash_url = msg.hash.map(|hash| {
             format!(
-                "[hash](https://finder.extraterrestrial.money/columbus-4/tx/{})",
-                hash
+                "[hash](https://finder.extraterrestrial.money/{}/tx/{})",
+                self.chain_id, hash
             )
         });
         let announce = if self.announcement_channel.is_some() {
@@ -318,30 +380,44 @@ impl Handler<MessageSendMessageEvent> for DiscordValidatorActor {
                 let announcement_channel = self.announcement_channel;
                 let private_channel = self.private_channel;
                 let message_type = msg.event_type;
+                let chain_id = self.chain_id.clone();
 
-                async move {
-                    if let Some(announce_msg) = announce {
-                        let _ann_result = api
-                            .create_message(announcement_channel.unwrap().channel_id, announce_msg)
-                            .await;
-                    };
-                    if let SendMessageEventType::PRIVATE = message_type {
-                        if let Some(private) = private_channel {
-                            let msg_result = api.create_message(private.channel_id, message).await;
-                            match msg_result {
-                                Ok(m) => {
-                                    log::info!("private message sent to {}", m.id.to_string())
-                                }
-                                Err(e) => log::error!("Error sending message {}", e),
-                            };
+                self.send_later(
+                    async move {
+                        if let Some(announce_msg) = announce {
+                            if let Err(e) = api
+                                .create_message(
+                                    announcement_channel.unwrap().channel_id,
+                                    announce_msg,
+                                )
+                                .await
+                            {
+                                log::error!("Error sending announcement {}", e);
+                                constellation_metrics::discord_send_failed(&chain_id)
+                            }
+                        };
+                        if let SendMessageEventType::PRIVATE = message_type {
+                            if let Some(private) = private_channel {
+                                let msg_result =
+                                    api.create_message(private.channel_id, message).await;
+                                match msg_result {
+                                    Ok(m) => {
+                                        log::info!("private message sent to {}", m.id.to_string())
+                                    }
+                                    Err(e) => {
+                                        log::error!("Error sending message {}", e);
+                                        constellation_metrics::discord_send_failed(&chain_id)
+                                    }
+                                };
+                            }
                         }
-                    }
-                }
-                .into_actor(self)
-                .spawn(ctx)
+                    },
+                    ctx,
+                );
             }
             Err(e) => {
-                log::error!("Unable to create discord api {}", e)
+                log::error!("Unable to create discord api {}", e);
+                constellation_metrics::discord_send_failed(&self.chain_id)
             }
         }
     }
@@ -372,12 +448,12 @@ impl Handler<Event> for DiscordValidatorActor {
                 self.guild_id = Some(gc.id);
 
                 for c in gc.channels {
-                    if c.name == "announcements" {
+                    if c.name == self.announcement_channel_name {
                         self.announcement_channel = Some(GuildChannel {
                             guild_id: gc.id,
                             channel_id: c.id,
                         })
-                    } else if c.name == "private" {
+                    } else if c.name == self.private_channel_name {
                         self.private_channel = Some(GuildChannel {
                             guild_id: gc.id,
                             channel_id: c.id,
@@ -479,3 +555,22 @@ impl Handler<MessageEvent> for DiscordValidatorActor {
 
     fn handle(&mut self, _msg: MessageEvent, _ctx: &mut Self::Context) {}
 }
+impl Handler<MessageDrain> for DiscordValidatorActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _msg: MessageDrain, _ctx: &mut Self::Context) -> Self::Result {
+        log::info!("Discord waiting on {} pending messages", self.in_flight);
+        let (waiter, drained) = oneshot::channel();
+        if self.in_flight == 0 {
+            let _ = waiter.send(());
+        } else {
+            self.drain_waiters.push(waiter);
+        }
+        Box::pin(
+            async move {
+                let _ = drained.await;
+            }
+            .into_actor(self),
+        )
+    }
+}
@@ -4,5 +4,5 @@ mod errors;
 mod task;
 
 use actix_broker::SystemBroker;
-pub use task::run;
+pub use task::{run, HEARTBEAT};
 pub(crate) type BrokerType = SystemBroker;
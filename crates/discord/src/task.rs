@@ -4,10 +4,20 @@
 use actor_discord::DiscordAPI;
 use actor_discord::DiscordBot;
 use actor_discord::GatewayIntents;
-use constellation_shared::state::AppState;
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
 use tokio::time::Duration;
 
-pub async fn run(_state: AppState, discord_token: String, discord_url: String, max_retries: usize) {
+/// how often a connected gateway tells the status registry it is still there
+pub const HEARTBEAT: Duration = Duration::from_secs(60);
+
+pub async fn run(
+    _state: SharedState,
+    discord_token: String,
+    discord_url: String,
+    max_retries: usize,
+    status: ModuleReporter,
+) {
     let intents: GatewayIntents = GatewayIntents::GUILDS
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::GUILD_MESSAGES
@@ -16,13 +26,34 @@ pub async fn run(_state: AppState, discord_token: String, discord_url: String, m
     loop {
         match DiscordAPI::create(&discord_token, &discord_url, max_retries) {
             Ok(discord_api) => match DiscordBot::create(&discord_api, intents).await {
-                Ok(mut discord_bot) => match discord_bot.start_websocket().await {
-                    Ok(_) => {}
-                    Err(e) => log::error!("Error websocket:{}", e),
-                },
-                Err(e) => log::error!("Error creating bot:{}", e),
+                Ok(mut discord_bot) => {
+                    // the websocket only returns when it disconnects
+                    let connected = async {
+                        loop {
+                            status.success(1);
+                            tokio::time::sleep(HEARTBEAT).await;
+                        }
+                    };
+                    tokio::select! {
+                        result = discord_bot.start_websocket() => match result {
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!("Error websocket:{}", e);
+                                status.error(format!("websocket: {}", e))
+                            }
+                        },
+                        _ = connected => {}
+                    }
+                }
+                Err(e) => {
+                    log::error!("Error creating bot:{}", e);
+                    status.error(format!("creating bot: {}", e))
+                }
             },
-            Err(e) => log::error!("Error creating api:{}", e),
+            Err(e) => {
+                log::error!("Error creating api:{}", e);
+                status.error(format!("creating api: {}", e))
+            }
         };
         log::warn!("Discord Disconnected..waiting 2 secs and trying again");
         tokio::time::sleep(Duration::from_secs(2)).await;
@@ -0,0 +1,236 @@
+use crate::refresh::Refresh;
+use chrono::{DateTime, Utc};
+use constellation_shared::state::State;
+use constellation_state::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// the most changes kept for one IP. older ones are dropped
+const HISTORY_PER_IP: usize = 20;
+
+pub type AppEnrichment = Arc<Mutex<Enrichment>>;
+
+/// an IP that had no answer, and when it is next worth asking about
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Miss {
+    /// lookups in a row with no answer
+    pub misses: u32,
+    pub last_tried: DateTime<Utc>,
+    pub retry_at: DateTime<Utc>,
+}
+
+/// an IP's answer changing when it was looked up again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub at: DateTime<Utc>,
+    /// what changed. "asn" or "country"
+    pub what: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// what the bgp & geo lookups need to remember between runs: the IPs that went unanswered,
+/// when each was located, and how their answers changed. one `enrichment_file` per chain
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Enrichment {
+    /// IPs with no answer, per queue ("bgp", "geo"). the "asn" queue holds ASNs with no details
+    pub misses: BTreeMap<String, HashMap<String, Miss>>,
+    /// when geo last located each IP. bgp's answers have their own `last_updated`
+    pub located: HashMap<String, DateTime<Utc>>,
+    /// changes seen when IPs were looked up again, oldest first
+    pub history: HashMap<String, Vec<Change>>,
+    /// every ASN announcing an IP's prefix, the one in `ip_asn` first. `ip_asn` only has room for
+    /// one, so IPs with a single origin aren't kept here
+    pub origins: HashMap<String, Vec<String>>,
+}
+
+impl Enrichment {
+    /// false while an IP with no answer is waiting out its backoff
+    pub fn due(&self, queue: &str, ip: &str, now: DateTime<Utc>) -> bool {
+        self.misses
+            .get(queue)
+            .and_then(|misses| misses.get(ip))
+            .map(|miss| miss.retry_at <= now)
+            .unwrap_or(true)
+    }
+
+    /// the IP had no answer. it waits longer each time
+    pub fn missed(&mut self, queue: &str, ip: &str, now: DateTime<Utc>, refresh: &Refresh) {
+        let miss = self
+            .misses
+            .entry(queue.to_string())
+            .or_insert_with(HashMap::new)
+            .entry(ip.to_string())
+            .or_insert(Miss {
+                misses: 0,
+                last_tried: now,
+                retry_at: now,
+            });
+        miss.misses += 1;
+        miss.last_tried = now;
+        miss.retry_at = now + refresh.backoff(miss.misses);
+    }
+
+    /// what is waiting out its backoff in `queue`
+    pub fn backed_off<'a>(
+        &'a self,
+        queue: &str,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = &'a String> + 'a {
+        self.misses
+            .get(queue)
+            .into_iter()
+            .flat_map(|misses| misses.iter())
+            .filter(move |(_, miss)| miss.retry_at > now)
+            .map(|(key, _)| key)
+    }
+
+    /// the IP had an answer, so it is no longer backed off
+    pub fn found(&mut self, queue: &str, ip: &str) {
+        if let Some(misses) = self.misses.get_mut(queue) {
+            misses.remove(ip);
+        }
+    }
+
+    pub fn waiting(&self, queue: &str) -> usize {
+        self.misses.get(queue).map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// the ASNs announcing the IP, `asns[0]` being the one in `ip_asn`
+    pub fn set_origins(&mut self, ip: &str, asns: &[String]) {
+        if asns.len() > 1 {
+            self.origins.insert(ip.to_string(), asns.to_vec());
+        } else {
+            self.origins.remove(ip);
+        }
+    }
+
+    /// every ASN announcing the IP. `first` is its ASN in `ip_asn`
+    pub fn origins(&self, ip: &str, first: &str) -> Vec<String> {
+        match self.origins.get(ip) {
+            Some(asns) => asns.clone(),
+            None => vec![first.to_string()],
+        }
+    }
+
+    pub fn changed(&mut self, ip: &str, change: Change) {
+        let changes = self.history.entry(ip.to_string()).or_insert_with(Vec::new);
+        changes.push(change);
+        if changes.len() > HISTORY_PER_IP {
+            changes.remove(0);
+        }
+    }
+
+    /// forget IPs (and ASNs) the state no longer has. returns how many were dropped
+    pub fn prune(&mut self, state: &State) -> usize {
+        let known = |ip: &String| {
+            state.ip_ip_addr.contains_key(ip)
+                || state.new_ips_bgp.contains(ip)
+                || state.new_ips_geo.contains(ip)
+        };
+        let mut dropped = 0;
+        for (queue, misses) in self.misses.iter_mut() {
+            let before = misses.len();
+            if queue == "asn" {
+                misses.retain(|asn, _| state.asn_ip.contains_key(asn));
+            } else {
+                misses.retain(|ip, _| known(ip));
+            }
+            dropped += before - misses.len();
+        }
+        let before = self.located.len() + self.history.len() + self.origins.len();
+        self.located.retain(|ip, _| known(ip));
+        self.history.retain(|ip, _| known(ip));
+        self.origins.retain(|ip, _| known(ip));
+        dropped + before - self.located.len() - self.history.len() - self.origins.len()
+    }
+
+    /// a missing file is nothing remembered
+    pub fn load(file: &str) -> anyhow::Result<Enrichment> {
+        if !Path::new(file).exists() {
+            return Ok(Enrichment::default());
+        }
+        Ok(serde_json::from_reader(File::open(file)?)?)
+    }
+}
+
+/// bgp & geo save to the same file, so the lock is held until it is in place. otherwise one
+/// could replace the other's newer save with an older copy
+pub fn save_enrichment(enrichment: &AppEnrichment, file: &str) -> anyhow::Result<()> {
+    let enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+    write_atomic(file, &serde_json::to_vec(&*enrich)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn writes_asn_changes_to_history() {
+        let mut enrichment = Enrichment::default();
+        let at = Utc::now();
+        enrichment.changed(
+            "10.0.0.1",
+            Change {
+                at,
+                what: "asn".into(),
+                from: "13335".into(),
+                to: "15169".into(),
+            },
+        );
+        let changes = &enrichment.history["10.0.0.1"];
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].what, "asn");
+        assert_eq!(
+            (changes[0].from.as_str(), changes[0].to.as_str()),
+            ("13335", "15169")
+        );
+        assert_eq!(changes[0].at, at);
+
+        for n in 0..HISTORY_PER_IP {
+            enrichment.changed(
+                "10.0.0.1",
+                Change {
+                    at,
+                    what: "asn".into(),
+                    from: n.to_string(),
+                    to: (n + 1).to_string(),
+                },
+            );
+        }
+        // the oldest is dropped
+        let changes = &enrichment.history["10.0.0.1"];
+        assert_eq!(changes.len(), HISTORY_PER_IP);
+        assert_eq!(changes[0].from, "0");
+    }
+
+    #[test]
+    fn backs_off_misses_until_found() {
+        let refresh = Refresh {
+            ttl: None,
+            per_run: 10,
+            retry: Duration::seconds(60),
+            retry_max: Duration::seconds(300),
+        };
+        let now = Utc::now();
+        let mut enrichment = Enrichment::default();
+        enrichment.missed("asn", "64512", now, &refresh);
+        enrichment.missed("asn", "64512", now, &refresh);
+        assert!(!enrichment.due("asn", "64512", now + Duration::seconds(119)));
+        assert!(enrichment.due("asn", "64512", now + Duration::seconds(120)));
+        assert_eq!(
+            enrichment.backed_off("asn", now).collect::<Vec<_>>(),
+            ["64512"]
+        );
+        assert!(enrichment.due("bgp", "64512", now));
+
+        enrichment.found("asn", "64512");
+        assert!(enrichment.due("asn", "64512", now));
+        assert_eq!(enrichment.waiting("asn"), 0);
+    }
+}
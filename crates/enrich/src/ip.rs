@@ -0,0 +1,53 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// an address book IP, with or without brackets. IPv4 mapped IPv6 addresses come back as IPv4
+pub fn parse_ip(ip: &str) -> Option<IpAddr> {
+    let addr = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok()?;
+    Some(match addr {
+        IpAddr::V6(v6) => match mapped_v4(&v6) {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    })
+}
+
+/// ::ffff:a.b.c.d
+fn mapped_v4(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, high, low] => Some(Ipv4Addr::new(
+            (high >> 8) as u8,
+            high as u8,
+            (low >> 8) as u8,
+            low as u8,
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_address_book_ips() {
+        let v6 = "2001:db8::1".parse::<IpAddr>().unwrap();
+        assert_eq!(
+            parse_ip("1.2.3.4"),
+            Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)))
+        );
+        assert_eq!(parse_ip("2001:db8::1"), Some(v6));
+        assert_eq!(parse_ip("[2001:db8::1]"), Some(v6));
+        assert_eq!(
+            parse_ip("::ffff:192.0.2.1"),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+        for bad in &["1.2.3", "1.2.3.256", "node.example.com", "2001:db8::g", ""] {
+            assert_eq!(parse_ip(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod enrichment;
+mod ip;
+mod limiter;
+mod pipeline;
+mod refresh;
+
+pub use enrichment::{save_enrichment, AppEnrichment, Change, Enrichment, Miss};
+pub use ip::parse_ip;
+pub use limiter::RateLimiter;
+pub use pipeline::{Pipeline, Tally};
+pub use refresh::Refresh;
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+lazy_static! {
+    static ref UPSTREAMS: Mutex<HashMap<String, RateLimiter>> = Mutex::new(HashMap::new());
+}
+
+/// spaces out the requests to one upstream, however many workers are asking.
+/// clones share the one limit
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    gap: Option<Duration>,
+    /// when the next request may go
+    next: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+    /// at most `per_sec` requests a second. 0 is no limit
+    pub fn new(per_sec: u32) -> RateLimiter {
+        RateLimiter {
+            gap: match per_sec {
+                0 => None,
+                n => Some(Duration::from_secs(1) / n),
+            },
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// the one limiter for `upstream` in this process, so chains sharing an upstream share its limit.
+    /// the first caller's `per_sec` wins
+    pub fn for_upstream(upstream: &str, per_sec: u32) -> RateLimiter {
+        UPSTREAMS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(upstream.to_string())
+            .or_insert_with(|| RateLimiter::new(per_sec))
+            .clone()
+    }
+
+    pub fn unlimited() -> RateLimiter {
+        RateLimiter::new(0)
+    }
+
+    /// wait for our turn
+    pub async fn acquire(&self) {
+        let gap = match self.gap {
+            Some(gap) => gap,
+            None => return,
+        };
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next).max(Instant::now());
+            *next = slot + gap;
+            slot
+        };
+        time::sleep_until(time::Instant::from_std(slot)).await;
+    }
+}
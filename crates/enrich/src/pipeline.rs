@@ -0,0 +1,119 @@
+use crate::limiter::RateLimiter;
+use futures::stream::{self, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// how a run of lookups went
+#[derive(Debug, Default)]
+pub struct Tally {
+    pub found: usize,
+    /// the upstream had no answer
+    pub missing: usize,
+    pub failed: usize,
+    pub last_error: Option<String>,
+    /// the IPs with no answer, or whose lookup failed, to be backed off alike
+    pub missed: Vec<String>,
+}
+
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} found, {} with no answer, {} failed",
+            self.found, self.missing, self.failed
+        )
+    }
+}
+
+/// works through a queue of IPs with a pool of concurrent lookups
+pub struct Pipeline {
+    /// metric labels
+    chain: String,
+    queue: &'static str,
+    concurrency: usize,
+    /// the least time between two flushes
+    flush_every: Duration,
+    limiter: RateLimiter,
+}
+
+impl Pipeline {
+    pub fn new(
+        chain: &str,
+        queue: &'static str,
+        concurrency: usize,
+        flush_every: Duration,
+        limiter: RateLimiter,
+    ) -> Pipeline {
+        Pipeline {
+            chain: chain.to_string(),
+            queue,
+            concurrency: concurrency.max(1),
+            flush_every,
+            limiter,
+        }
+    }
+
+    /// look every IP up, `concurrency` at a time and no faster than the limiter allows.
+    /// what is found goes to `apply` at most once every `flush_every` and once more at the end, so
+    /// the state is written once per flush however many IPs each lookup takes
+    pub async fn run<T, F, Fut, A>(&self, ips: Vec<String>, lookup: F, mut apply: A) -> Tally
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<T>>>,
+        A: FnMut(Vec<(String, T)>),
+    {
+        let total = ips.len();
+        constellation_metrics::enrich_remaining(&self.chain, self.queue, total);
+        let mut tally = Tally::default();
+        let mut found = vec![];
+        let mut done = 0;
+        let mut flushed = Instant::now();
+        let mut results = stream::iter(ips)
+            .map(|ip| {
+                let lookup = &lookup;
+                let limiter = &self.limiter;
+                async move {
+                    limiter.acquire().await;
+                    let result = lookup(ip.clone()).await;
+                    (ip, result)
+                }
+            })
+            .buffer_unordered(self.concurrency);
+        while let Some((ip, result)) = results.next().await {
+            done += 1;
+            let outcome = match result {
+                Ok(Some(value)) => {
+                    tally.found += 1;
+                    found.push((ip, value));
+                    "found"
+                }
+                Ok(None) => {
+                    log::info!("{} lookup for {} - no response", self.queue, ip);
+                    tally.missing += 1;
+                    tally.missed.push(ip);
+                    "missing"
+                }
+                Err(e) => {
+                    log::error!("{} lookup for {} - {}", self.queue, ip, e);
+                    tally.failed += 1;
+                    tally.last_error = Some(format!("IP {} - {}", ip, e));
+                    tally.missed.push(ip);
+                    "failed"
+                }
+            };
+            constellation_metrics::enrich_lookup(&self.chain, self.queue, outcome);
+            if !found.is_empty() && flushed.elapsed() >= self.flush_every {
+                apply(std::mem::take(&mut found));
+                flushed = Instant::now();
+                constellation_metrics::enrich_remaining(&self.chain, self.queue, total - done);
+                log::info!("{}: {}/{} looked up", self.queue, done, total);
+            }
+        }
+        if !found.is_empty() {
+            apply(found);
+        }
+        constellation_metrics::enrich_remaining(&self.chain, self.queue, 0);
+        tally
+    }
+}
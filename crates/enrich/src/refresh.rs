@@ -0,0 +1,102 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// when a lookup's answer is looked up again
+#[derive(Clone, Copy, Debug)]
+pub struct Refresh {
+    /// how old an answer can get before it is looked up again. None never looks again
+    pub ttl: Option<Duration>,
+    /// most stale IPs looked up again each run, oldest first, so a backlog is worked through
+    /// over several runs rather than all at once
+    pub per_run: usize,
+    /// how long an IP with no answer waits before it is tried again. doubles with each miss
+    pub retry: Duration,
+    /// the longest an IP with no answer waits
+    pub retry_max: Duration,
+}
+
+impl Refresh {
+    /// answers from before this are stale
+    pub fn stale_before(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.ttl.map(|ttl| now - ttl)
+    }
+
+    /// what to look up again this run: the answers from before the TTL, oldest first and at most
+    /// `per_run` of them. also returns how many were stale
+    pub fn pick_stale<'a>(
+        &self,
+        answered: impl IntoIterator<Item = (&'a str, DateTime<Utc>)>,
+        now: DateTime<Utc>,
+    ) -> (usize, Vec<String>) {
+        let stale_before = match self.stale_before(now) {
+            Some(stale_before) => stale_before,
+            None => return (0, vec![]),
+        };
+        let mut stale = answered
+            .into_iter()
+            .filter(|(_, at)| *at < stale_before)
+            .collect::<Vec<_>>();
+        stale.sort_by_key(|(_, at)| *at);
+        let picked = stale
+            .iter()
+            .take(self.per_run)
+            .map(|(ip, _)| ip.to_string())
+            .collect();
+        (stale.len(), picked)
+    }
+
+    /// the wait after `misses` lookups in a row with no answer
+    pub fn backoff(&self, misses: u32) -> Duration {
+        let doublings = misses.saturating_sub(1).min(30);
+        let wait = self
+            .retry
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.retry_max);
+        wait.min(self.retry_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refresh(ttl_hours: Option<i64>, per_run: usize) -> Refresh {
+        Refresh {
+            ttl: ttl_hours.map(Duration::hours),
+            per_run,
+            retry: Duration::seconds(60),
+            retry_max: Duration::seconds(300),
+        }
+    }
+
+    #[test]
+    fn picks_the_oldest_expired_answers() {
+        let now = Utc::now();
+        let answered = vec![
+            ("10.0.0.1", now - Duration::hours(30)),
+            ("10.0.0.2", now - Duration::hours(1)),
+            ("10.0.0.3", now - Duration::hours(50)),
+            ("10.0.0.4", now - Duration::hours(25)),
+        ];
+        let (stale, picked) = refresh(Some(24), 2).pick_stale(answered.clone(), now);
+        assert_eq!(stale, 3);
+        assert_eq!(picked, ["10.0.0.3", "10.0.0.1"]);
+
+        let (stale, picked) = refresh(Some(24), 10).pick_stale(answered.clone(), now);
+        assert_eq!(stale, 3);
+        assert_eq!(picked, ["10.0.0.3", "10.0.0.1", "10.0.0.4"]);
+
+        // no TTL never looks again
+        assert_eq!(refresh(None, 10).pick_stale(answered, now), (0, vec![]));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let refresh = refresh(None, 10);
+        let waits = (1..=5)
+            .map(|misses| refresh.backoff(misses).num_seconds())
+            .collect::<Vec<_>>();
+        assert_eq!(waits, [60, 120, 240, 300, 300]);
+        assert_eq!(refresh.backoff(0), Duration::seconds(60));
+        assert_eq!(refresh.backoff(u32::MAX), Duration::seconds(300));
+    }
+}
@@ -0,0 +1,174 @@
+use constellation_enrich::{Pipeline, RateLimiter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+fn ips(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!("10.0.{}.{}", i / 256, i % 256))
+        .collect()
+}
+
+#[tokio::test]
+async fn keeps_to_the_concurrency() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let lookup = |ip: String| {
+        let (in_flight, most) = (in_flight.clone(), most.clone());
+        async move {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            time::sleep(Duration::from_millis(10)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok::<_, anyhow::Error>(Some(ip))
+        }
+    };
+    let pipeline = Pipeline::new(
+        "test",
+        "bgp",
+        4,
+        Duration::from_secs(60),
+        RateLimiter::unlimited(),
+    );
+    let tally = pipeline.run(ips(40), lookup, |_| {}).await;
+    assert_eq!(tally.found, 40);
+    assert_eq!(most.load(Ordering::SeqCst), 4);
+
+    // 0 is taken as 1
+    most.store(0, Ordering::SeqCst);
+    let pipeline = Pipeline::new(
+        "test",
+        "bgp",
+        0,
+        Duration::from_secs(60),
+        RateLimiter::unlimited(),
+    );
+    pipeline.run(ips(5), lookup, |_| {}).await;
+    assert_eq!(most.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn spaces_lookups_out_to_the_rate() {
+    let started = Arc::new(Mutex::new(vec![]));
+    let lookup = |ip: String| {
+        let started = started.clone();
+        async move {
+            started.lock().unwrap().push(Instant::now());
+            Ok::<_, anyhow::Error>(Some(ip))
+        }
+    };
+    // 50 a second is one every 20ms, however many are allowed at once
+    let begin = Instant::now();
+    let pipeline = Pipeline::new(
+        "test",
+        "bgp",
+        10,
+        Duration::from_secs(60),
+        RateLimiter::new(50),
+    );
+    pipeline.run(ips(10), lookup, |_| {}).await;
+
+    let mut started = started.lock().unwrap().clone();
+    started.sort();
+    let after = started
+        .iter()
+        .map(|at| (*at - begin).as_millis())
+        .collect::<Vec<_>>();
+    assert_eq!(after.len(), 10);
+    for (i, ms) in after.iter().enumerate() {
+        assert!(*ms >= i as u128 * 20, "{:?}", after);
+    }
+}
+
+#[tokio::test]
+async fn shares_an_upstream_limit() {
+    let a = RateLimiter::for_upstream("pipeline-test:53", 20);
+    let b = RateLimiter::for_upstream("pipeline-test:53", 1000);
+    let start = Instant::now();
+    for limiter in [&a, &b, &a, &b].iter() {
+        limiter.acquire().await;
+    }
+    // the first caller's 20 a second holds for both, so the 4th goes 150ms after the 1st
+    assert!(start.elapsed() >= Duration::from_millis(145));
+
+    let other = RateLimiter::for_upstream("pipeline-test-other:53", 1000);
+    let start = Instant::now();
+    other.acquire().await;
+    assert!(start.elapsed() < Duration::from_millis(45));
+}
+
+#[tokio::test]
+async fn tallies_found_missing_and_failed() {
+    let lookup = |ip: String| async move {
+        if ip.ends_with(".13") {
+            anyhow::bail!("timed out")
+        }
+        if ip.ends_with('7') {
+            return Ok(None);
+        }
+        Ok(Some(ip.len()))
+    };
+    let pipeline = Pipeline::new(
+        "test",
+        "geo",
+        8,
+        Duration::from_secs(60),
+        RateLimiter::unlimited(),
+    );
+    let mut applied = vec![];
+    let tally = pipeline
+        .run(ips(40), lookup, |found| applied.push(found.len()))
+        .await;
+
+    // 10.0.0.13, and 10.0.0.7, .17, .27 & .37
+    assert_eq!(tally.failed, 1);
+    assert_eq!(tally.missing, 4);
+    assert_eq!(tally.found, 35);
+    assert_eq!(
+        tally.last_error.as_deref(),
+        Some("IP 10.0.0.13 - timed out")
+    );
+    let mut missed = tally.missed.clone();
+    missed.sort();
+    assert_eq!(
+        missed,
+        vec![
+            "10.0.0.13",
+            "10.0.0.17",
+            "10.0.0.27",
+            "10.0.0.37",
+            "10.0.0.7"
+        ]
+    );
+    // nothing was due a flush until the end
+    assert_eq!(applied, vec![35]);
+}
+
+#[tokio::test]
+async fn flushes_no_more_often_than_asked() {
+    let lookup = |ip: String| async move {
+        time::sleep(Duration::from_millis(10)).await;
+        Ok::<_, anyhow::Error>(Some(ip))
+    };
+    let pipeline = Pipeline::new(
+        "test",
+        "geo",
+        1,
+        Duration::from_millis(35),
+        RateLimiter::unlimited(),
+    );
+    let mut applied = vec![];
+    let tally = pipeline
+        .run(ips(20), lookup, |found| applied.push(found.len()))
+        .await;
+    assert_eq!(tally.found, 20);
+    assert_eq!(applied.iter().sum::<usize>(), 20);
+    // 200ms of lookups, flushed at most every 35ms and once at the end
+    assert!(applied.len() > 1 && applied.len() <= 7, "{:?}", applied);
+    assert!(
+        applied[..applied.len() - 1].iter().all(|n| *n >= 3),
+        "{:?}",
+        applied
+    );
+}
@@ -1,3 +1,3 @@
 mod task;
 
-pub use task::run;
+pub use task::{run, GeoSettings};
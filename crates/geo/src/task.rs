@@ -1,168 +1,420 @@
-use chrono::Utc;
-use constellation_shared::state::{AppState, GeoCity, GeoContinent, GeoCountry};
+use actix_broker::{Broker, SystemBroker};
+use chrono::{DateTime, Utc};
+use constellation_enrich::{
+    parse_ip, save_enrichment, AppEnrichment, Change, Enrichment, Pipeline, RateLimiter, Refresh,
+};
+use constellation_node_events::MessageIpCountryChanged;
+use constellation_shared::state::{GeoCity, GeoContinent, GeoCountry, GeoID, State};
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
+use constellation_store::{Batch, Store, Table};
 use maxminddb::geoip2::City;
-use maxminddb::MaxMindDBError;
+use maxminddb::{MaxMindDBError, Reader};
 use std::collections::hash_map::Entry;
-use std::collections::HashSet;
-use std::net::{AddrParseError, IpAddr};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, PoisonError};
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time;
 
-pub async fn run(state: AppState, period: Duration, db_filename: String) {
+/// each publish copies the whole state, so located IPs are gathered up for this long first
+const PUBLISH_EVERY: Duration = Duration::from_secs(10);
+
+/// where an IP is, copied out of the db so the lookup can run on another thread
+struct Located {
+    city: Option<GeoCity>,
+    country: Option<GeoCountry>,
+    continent: Option<GeoContinent>,
+}
+
+/// how the geo task runs
+#[derive(Clone, Debug)]
+pub struct GeoSettings {
+    pub period: Duration,
+    /// the maxmind city database
+    pub db_file: String,
+    /// lookups in flight at once
+    pub concurrency: usize,
+    pub refresh: Refresh,
+    /// where what was missed & changed is saved after each run
+    pub enrichment_file: String,
+    pub chain: String,
+}
+
+/// locate every new IP, every period, along with those located longer ago than the refresh TTL.
+/// IPs the db doesn't have wait out a backoff before being tried again.
+/// lookups run `concurrency` at a time on the blocking pool. returns once `shutdown` turns true
+pub async fn run(
+    state: SharedState,
+    enrichment: AppEnrichment,
+    store: Option<Store>,
+    settings: GeoSettings,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let GeoSettings {
+        period,
+        db_file: db_filename,
+        concurrency,
+        refresh,
+        enrichment_file,
+        chain,
+    } = settings;
     let mut interval = time::interval(period);
+    // the db is local, so there is nothing to rate limit
+    let pipeline = Pipeline::new(
+        &chain,
+        "geo",
+        concurrency,
+        PUBLISH_EVERY,
+        RateLimiter::unlimited(),
+    );
 
     loop {
         match maxminddb::Reader::open_readfile(&db_filename) {
             Ok(maxmind) => {
+                let maxmind = Arc::new(maxmind);
+                let now = Utc::now();
                 let mut ips_tbd: Vec<String> = vec![];
+                let mut located: usize = 0;
+                let mut batch = Batch::default();
 
                 {
-                    let the_state = state.lock().unwrap();
+                    let the_state = state.snapshot();
+                    let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+                    enrich.prune(&the_state);
+                    let backfilled = backfill(&mut enrich, &the_state, now);
+                    if backfilled > 0 {
+                        log::info!(
+                            "{} IPs had no located time. treating them as located now",
+                            backfilled
+                        );
+                    }
                     for ip in &the_state.new_ips_geo {
-                        if !the_state.geo_ip_country.contains_key(ip) {
+                        if !the_state.geo_ip_country.contains_key(ip) && enrich.due("geo", ip, now)
+                        {
                             ips_tbd.push(ip.to_string());
                         }
                     }
+                    let (stale, picked) = refresh.pick_stale(
+                        enrich
+                            .located
+                            .iter()
+                            .filter(|(ip, _)| enrich.due("geo", ip, now))
+                            .map(|(ip, at)| (ip.as_str(), *at)),
+                        now,
+                    );
+                    if stale > 0 {
+                        log::info!("Stale IPS = {}, refreshing {}", stale, picked.len());
+                    }
+                    ips_tbd.extend(picked);
                 }
                 if !ips_tbd.is_empty() {
-                    {
-                        log::info!("New IPS = {}", ips_tbd.len());
-                        for ip in ips_tbd {
-                            let ip_addr_r: Result<IpAddr, AddrParseError> = ip.parse();
-                            match ip_addr_r {
-                                Ok(ip_add) => {
-                                    let city_r: Result<City, MaxMindDBError> =
-                                        maxmind.lookup(ip_add);
-                                    match city_r {
-                                        Ok(city) => {
-                                            let city_id = city
-                                                .city
-                                                .as_ref()
-                                                .map(|f| f.geoname_id.unwrap_or(0))
-                                                .unwrap_or(0);
-                                            let country_id = city
-                                                .country
-                                                .as_ref()
-                                                .map(|f| f.geoname_id.unwrap_or(0))
-                                                .unwrap_or(0);
-                                            let continent_id = city
-                                                .continent
-                                                .as_ref()
-                                                .map(|f| f.geoname_id.unwrap_or(0))
-                                                .unwrap_or(0);
-
-                                            let mut the_state = state.lock().unwrap();
-                                            if let Some(cx) = city.city {
-                                                let name = cx
-                                                    .names
-                                                    .map(|b| *b.get("en").unwrap_or(&"-none-"))
-                                                    .map(|f| f.to_string());
-                                                if let Entry::Vacant(e) = the_state
-                                                    .geo_city
-                                                    .entry(cx.geoname_id.unwrap_or(0))
-                                                {
-                                                    e.insert(
-                                                        //cx.geoname_id.unwrap_or(0),
-                                                        GeoCity {
-                                                            geoname_id: cx.geoname_id.unwrap_or(0),
-                                                            name,
-                                                            country: country_id,
-                                                            continent: continent_id,
-                                                            last_updated: Utc::now(),
-                                                        },
-                                                    );
-                                                }
-                                                the_state.geo_ip_city.insert(ip.clone(), city_id);
-                                                let mut s =
-                                                    match the_state.geo_city_ip.get(&city_id) {
-                                                        Some(set) => set.clone(),
-                                                        None => HashSet::new(),
-                                                    };
-                                                s.insert(ip.clone());
-                                                the_state.geo_city_ip.insert(city_id, s);
-                                            };
-                                            if let Some(cx) = city.country {
-                                                let name = cx
-                                                    .names
-                                                    .map(|b| *b.get("en").unwrap_or(&"-none-"))
-                                                    .map(|f| f.to_string());
-                                                if let Entry::Vacant(e) = the_state
-                                                    .geo_country
-                                                    .entry(cx.geoname_id.unwrap_or(0))
-                                                {
-                                                    e.insert(GeoCountry {
-                                                        geoname_id: cx.geoname_id.unwrap_or(0),
-                                                        name,
-                                                        is_in_european_union: cx
-                                                            .is_in_european_union,
-                                                        iso_code: cx
-                                                            .iso_code
-                                                            .map(|f| f.to_string()),
-                                                        last_updated: Utc::now(),
-                                                    });
-                                                }
-                                                the_state
-                                                    .geo_ip_country
-                                                    .insert(ip.clone(), country_id);
-                                                let mut s =
-                                                    match the_state.geo_country_ip.get(&country_id)
-                                                    {
-                                                        Some(set) => set.clone(),
-                                                        None => HashSet::new(),
-                                                    };
-                                                s.insert(ip.clone());
-                                                the_state.geo_country_ip.insert(country_id, s);
-                                            };
-                                            if let Some(cx) = city.continent {
-                                                let name = cx
-                                                    .names
-                                                    .map(|b| *b.get("en").unwrap_or(&"-none-"))
-                                                    .map(|f| f.to_string());
-                                                if let Entry::Vacant(e) = the_state
-                                                    .geo_continent
-                                                    .entry(cx.geoname_id.unwrap_or(0))
-                                                {
-                                                    e.insert(GeoContinent {
-                                                        geoname_id: cx.geoname_id.unwrap_or(0),
-                                                        name,
-                                                        code: cx.code.map(|f| f.to_string()),
-                                                        last_updated: Utc::now(),
-                                                    });
-                                                }
-                                                the_state
-                                                    .geo_ip_continent
-                                                    .insert(ip.clone(), continent_id);
-                                                let mut s = match the_state
-                                                    .geo_continent_ip
-                                                    .get(&continent_id)
-                                                {
-                                                    Some(set) => set.clone(),
-                                                    None => HashSet::new(),
-                                                };
-                                                s.insert(ip.clone());
-                                                the_state.geo_continent_ip.insert(continent_id, s);
-                                            };
-                                            the_state.new_ips_geo.remove(&ip);
-                                        }
-                                        Err(e) => {
-                                            log::error!("DB Error {} {}", ip, e);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    log::error!("Unable to parse IP#{} {}", ip, e);
+                    log::info!("IPS to locate = {}", ips_tbd.len());
+                    let tally = pipeline
+                        .run(
+                            ips_tbd,
+                            |ip| {
+                                let maxmind = maxmind.clone();
+                                async move {
+                                    tokio::task::spawn_blocking(move || locate(&maxmind, &ip))
+                                        .await?
                                 }
-                            }
-                        }
+                            },
+                            |found| publish(&state, &enrichment, found, &mut batch),
+                        )
+                        .await;
+                    log::info!("GEO: {}", tally);
+                    located = tally.found;
+                    let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+                    for ip in &tally.missed {
+                        enrich.missed("geo", ip, now, &refresh);
+                    }
+                    constellation_metrics::enrich_backed_off(&chain, "geo", enrich.waiting("geo"));
+                    if let Some(e) = tally.last_error {
+                        status.error(e);
                     }
                 } else {
-                    log::info!("No new IPs to scan");
+                    log::info!("No new or stale IPs to scan");
                 }
+                if let Err(e) = save_enrichment(&enrichment, &enrichment_file) {
+                    log::error!("Unable to save {} {}", enrichment_file, e);
+                    status.error(format!("save {}: {}", enrichment_file, e));
+                }
+                if let Some(store) = &store {
+                    if let Err(e) = store.apply(batch) {
+                        log::error!("Unable to store GEO changes {}", e);
+                        status.error(format!("store: {}", e));
+                    }
+                }
+                status.success(located);
 
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
             }
 
-            Err(e) => panic!("Unable to start GEO {}", e),
+            Err(e) => {
+                status.error(format!("Unable to start GEO {}", e));
+                panic!("Unable to start GEO {}", e)
+            }
         }
     }
 }
+
+/// IPs located before located times were kept are treated as located now, so they are
+/// looked up again a TTL from now rather than all at once. returns how many were added
+fn backfill(enrich: &mut Enrichment, state: &State, now: DateTime<Utc>) -> usize {
+    let mut added = 0;
+    for ip in state.geo_ip_country.keys() {
+        if !enrich.located.contains_key(ip) {
+            enrich.located.insert(ip.clone(), now);
+            added += 1;
+        }
+    }
+    added
+}
+
+fn english(names: Option<BTreeMap<&str, &str>>) -> Option<String> {
+    names
+        .map(|b| *b.get("en").unwrap_or(&"-none-"))
+        .map(|f| f.to_string())
+}
+
+/// an address book IP, bracketed or not, as maxmind wants it
+fn ip_addr(ip: &str) -> anyhow::Result<IpAddr> {
+    parse_ip(ip).ok_or_else(|| anyhow::anyhow!("Unable to parse IP#{}", ip))
+}
+
+fn locate(maxmind: &Reader<Vec<u8>>, ip: &str) -> anyhow::Result<Option<Located>> {
+    let city: City = match maxmind.lookup(ip_addr(ip)?) {
+        Ok(city) => city,
+        Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+        Err(e) => anyhow::bail!("DB Error {} {}", ip, e),
+    };
+    let country_id = city
+        .country
+        .as_ref()
+        .map(|f| f.geoname_id.unwrap_or(0))
+        .unwrap_or(0);
+    let continent_id = city
+        .continent
+        .as_ref()
+        .map(|f| f.geoname_id.unwrap_or(0))
+        .unwrap_or(0);
+    Ok(Some(Located {
+        city: city.city.map(|cx| GeoCity {
+            geoname_id: cx.geoname_id.unwrap_or(0),
+            name: english(cx.names),
+            country: country_id,
+            continent: continent_id,
+            last_updated: Utc::now(),
+        }),
+        country: city.country.map(|cx| GeoCountry {
+            geoname_id: cx.geoname_id.unwrap_or(0),
+            name: english(cx.names),
+            is_in_european_union: cx.is_in_european_union,
+            iso_code: cx.iso_code.map(|f| f.to_string()),
+            last_updated: Utc::now(),
+        }),
+        continent: city.continent.map(|cx| GeoContinent {
+            geoname_id: cx.geoname_id.unwrap_or(0),
+            name: english(cx.names),
+            code: cx.code.map(|f| f.to_string()),
+            last_updated: Utc::now(),
+        }),
+    }))
+}
+
+/// move what has been located so far into the state, in one update.
+/// an IP located again in a different country has the move recorded & announced
+fn publish(
+    state: &SharedState,
+    enrichment: &AppEnrichment,
+    found: Vec<(String, Located)>,
+    batch: &mut Batch,
+) {
+    let mut answered = vec![];
+    let mut moved = vec![];
+    state.update(|the_state| {
+        for (ip, location) in found {
+            if let Some(change) = record(the_state, batch, ip.clone(), location) {
+                moved.push((ip.clone(), change));
+            }
+            answered.push(ip);
+        }
+    });
+    let now = Utc::now();
+    let mut enrich = enrichment.lock().unwrap_or_else(PoisonError::into_inner);
+    for ip in answered {
+        enrich.found("geo", &ip);
+        enrich.located.insert(ip, now);
+    }
+    for (ip, change) in moved {
+        log::info!("{} moved from {} to {}", ip, change.from, change.to);
+        Broker::<SystemBroker>::issue_async(MessageIpCountryChanged {
+            ip: ip.clone(),
+            country: change.to.clone(),
+            previous: change.from.clone(),
+        });
+        enrich.changed(&ip, change);
+    }
+}
+
+/// put a located IP into the state & its indexes, taking it out of any place it was in before.
+/// returns the change when it was in another country
+fn record(
+    the_state: &mut State,
+    batch: &mut Batch,
+    ip: String,
+    location: Located,
+) -> Option<Change> {
+    let mut change = None;
+    if let Some(city) = location.city {
+        let city_id = city.geoname_id;
+        if let Entry::Vacant(e) = the_state.geo_city.entry(city_id) {
+            batch.put(Table::GeoCity, &city_id.to_string(), &city);
+            e.insert(city);
+        }
+        if let Some(previous) = the_state.geo_ip_city.insert(ip.clone(), city_id) {
+            if previous != city_id {
+                unindex(
+                    &mut the_state.geo_city_ip,
+                    Table::GeoCityIp,
+                    previous,
+                    &ip,
+                    batch,
+                );
+            }
+        }
+        batch.put(Table::GeoIpCity, &ip, &city_id);
+        index(
+            &mut the_state.geo_city_ip,
+            Table::GeoCityIp,
+            city_id,
+            &ip,
+            batch,
+        );
+    }
+    if let Some(country) = location.country {
+        let country_id = country.geoname_id;
+        if let Entry::Vacant(e) = the_state.geo_country.entry(country_id) {
+            batch.put(Table::GeoCountry, &country_id.to_string(), &country);
+            e.insert(country);
+        }
+        if let Some(previous) = the_state.geo_ip_country.insert(ip.clone(), country_id) {
+            if previous != country_id {
+                unindex(
+                    &mut the_state.geo_country_ip,
+                    Table::GeoCountryIp,
+                    previous,
+                    &ip,
+                    batch,
+                );
+                change = Some(Change {
+                    at: Utc::now(),
+                    what: "country".into(),
+                    from: country_name(the_state, previous),
+                    to: country_name(the_state, country_id),
+                });
+            }
+        }
+        batch.put(Table::GeoIpCountry, &ip, &country_id);
+        index(
+            &mut the_state.geo_country_ip,
+            Table::GeoCountryIp,
+            country_id,
+            &ip,
+            batch,
+        );
+    }
+    if let Some(continent) = location.continent {
+        let continent_id = continent.geoname_id;
+        if let Entry::Vacant(e) = the_state.geo_continent.entry(continent_id) {
+            batch.put(Table::GeoContinent, &continent_id.to_string(), &continent);
+            e.insert(continent);
+        }
+        if let Some(previous) = the_state.geo_ip_continent.insert(ip.clone(), continent_id) {
+            if previous != continent_id {
+                unindex(
+                    &mut the_state.geo_continent_ip,
+                    Table::GeoContinentIp,
+                    previous,
+                    &ip,
+                    batch,
+                );
+            }
+        }
+        batch.put(Table::GeoIpContinent, &ip, &continent_id);
+        index(
+            &mut the_state.geo_continent_ip,
+            Table::GeoContinentIp,
+            continent_id,
+            &ip,
+            batch,
+        );
+    }
+    the_state.new_ips_geo.remove(&ip);
+    batch.remove(Table::NewIpsGeo, &ip);
+    change
+}
+
+/// the country's ISO code, or its geoname id when it has none
+fn country_name(the_state: &State, id: GeoID) -> String {
+    the_state
+        .geo_country
+        .get(&id)
+        .and_then(|c| c.iso_code.clone())
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// add the IP to its place's reverse index
+fn index(
+    reverse: &mut HashMap<GeoID, HashSet<String>>,
+    table: Table,
+    id: GeoID,
+    ip: &str,
+    batch: &mut Batch,
+) {
+    let set = reverse.entry(id).or_insert_with(HashSet::new);
+    set.insert(ip.to_string());
+    batch.put(table, &id.to_string(), &*set);
+}
+
+/// take the IP out of a place it is no longer in. places left with no IPs are dropped from the index
+fn unindex(
+    reverse: &mut HashMap<GeoID, HashSet<String>>,
+    table: Table,
+    id: GeoID,
+    ip: &str,
+    batch: &mut Batch,
+) {
+    if let Some(set) = reverse.get_mut(&id) {
+        set.remove(ip);
+        if set.is_empty() {
+            reverse.remove(&id);
+            batch.remove(table, &id.to_string());
+        } else {
+            batch.put(table, &id.to_string(), &*set);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_bracketed_ips() {
+        let v6 = "2001:db8::1".parse::<IpAddr>().unwrap();
+        assert_eq!(ip_addr("[2001:db8::1]").unwrap(), v6);
+        assert_eq!(ip_addr("2001:db8::1").unwrap(), v6);
+        assert_eq!(
+            ip_addr("10.0.0.1").unwrap(),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+        assert!(ip_addr("[node.example.com]").is_err());
+    }
+}
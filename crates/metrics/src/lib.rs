@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod metrics;
+mod state;
+
+pub use metrics::{
+    discord_send_failed, encode, enrich_backed_off, enrich_lookup, enrich_remaining, exchange_rate,
+    node_event, nodes_reachable, nodes_wrong_network, timed, validator, validator_reward_rate,
+};
+pub use state::observe_states;
@@ -0,0 +1,213 @@
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::future::Future;
+use std::time::Instant;
+
+// everything is labelled with the chain_id, so several chains can share the one registry
+lazy_static! {
+    pub(crate) static ref NODES: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_nodes",
+        "nodes in the address book",
+        &["chain"]
+    )
+    .unwrap();
+    pub(crate) static ref NODE_IPS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_node_ips",
+        "distinct IPs the nodes are on",
+        &["chain"]
+    )
+    .unwrap();
+    pub(crate) static ref COUNTRY_IPS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_country_ips",
+        "node IPs in each country",
+        &["chain", "country"]
+    )
+    .unwrap();
+    pub(crate) static ref ASN_IPS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_asn_ips",
+        "node IPs in each ASN",
+        &["chain", "asn"]
+    )
+    .unwrap();
+    pub(crate) static ref PENDING_IPS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_pending_ips",
+        "IPs waiting for a geo/bgp lookup",
+        &["chain", "queue"]
+    )
+    .unwrap();
+    static ref ENRICH_REMAINING: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_enrich_remaining",
+        "IPs left in the geo/bgp run underway",
+        &["chain", "queue"]
+    )
+    .unwrap();
+    static ref ENRICH_BACKED_OFF: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_enrich_backed_off",
+        "IPs that had no answer, waiting to be tried again",
+        &["chain", "queue"]
+    )
+    .unwrap();
+    static ref ENRICH_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "constellation_enrich_lookups_total",
+        "geo/bgp lookups, by whether they found anything",
+        &["chain", "queue", "outcome"]
+    )
+    .unwrap();
+    static ref VALIDATOR_TOKENS: GaugeVec = register_gauge_vec!(
+        "constellation_validator_tokens",
+        "tokens staked with the validator",
+        &["chain", "operator", "moniker"]
+    )
+    .unwrap();
+    static ref VALIDATOR_ABSTAINS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_validator_abstains",
+        "oracle abstains seen from the validator",
+        &["chain", "operator", "moniker"]
+    )
+    .unwrap();
+    static ref VALIDATOR_DRIFTS: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_validator_drifts",
+        "oracle price drifts seen from the validator",
+        &["chain", "operator", "moniker"]
+    )
+    .unwrap();
+    static ref VALIDATOR_REWARD_RATE: GaugeVec = register_gauge_vec!(
+        "constellation_validator_daily_reward_rate",
+        "rewards per staked token per day, sampled hourly",
+        &["chain", "operator", "moniker"]
+    )
+    .unwrap();
+    static ref EXCHANGE_RATE: GaugeVec = register_gauge_vec!(
+        "constellation_exchange_rate",
+        "oracle exchange rate of luna",
+        &["chain", "denom"]
+    )
+    .unwrap();
+    static ref REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "constellation_request_seconds",
+        "time taken by LCD/RPC requests",
+        &["chain", "call"]
+    )
+    .unwrap();
+    static ref REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "constellation_request_errors_total",
+        "LCD/RPC requests that failed",
+        &["chain", "call"]
+    )
+    .unwrap();
+    static ref NODES_REACHABLE: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_nodes_reachable",
+        "nodes that accepted a TCP connection on the last probe",
+        &["chain"]
+    )
+    .unwrap();
+    static ref NODES_WRONG_NETWORK: IntGaugeVec = register_int_gauge_vec!(
+        "constellation_nodes_wrong_network",
+        "nodes whose handshake named another network",
+        &["chain"]
+    )
+    .unwrap();
+    static ref NODE_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "constellation_node_events_total",
+        "nodes discovered, gone or on a new IP, and IPs that moved ASN or country",
+        &["chain", "event"]
+    )
+    .unwrap();
+    static ref DISCORD_SEND_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "constellation_discord_send_failures_total",
+        "discord calls that failed",
+        &["chain"]
+    )
+    .unwrap();
+}
+
+/// run an LCD/RPC request, recording how long it took and whether it failed
+pub async fn timed<F, T, E>(chain: &str, call: &str, request: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = request.await;
+    REQUEST_SECONDS
+        .with_label_values(&[chain, call])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        REQUEST_ERRORS.with_label_values(&[chain, call]).inc();
+    }
+    result
+}
+
+pub fn discord_send_failed(chain: &str) {
+    DISCORD_SEND_FAILURES.with_label_values(&[chain]).inc();
+}
+
+pub fn nodes_reachable(chain: &str, reachable: usize) {
+    NODES_REACHABLE
+        .with_label_values(&[chain])
+        .set(reachable as i64);
+}
+
+pub fn nodes_wrong_network(chain: &str, nodes: usize) {
+    NODES_WRONG_NETWORK
+        .with_label_values(&[chain])
+        .set(nodes as i64);
+}
+
+pub fn enrich_remaining(chain: &str, queue: &str, remaining: usize) {
+    ENRICH_REMAINING
+        .with_label_values(&[chain, queue])
+        .set(remaining as i64);
+}
+
+pub fn enrich_backed_off(chain: &str, queue: &str, ips: usize) {
+    ENRICH_BACKED_OFF
+        .with_label_values(&[chain, queue])
+        .set(ips as i64);
+}
+
+pub fn enrich_lookup(chain: &str, queue: &str, outcome: &str) {
+    ENRICH_LOOKUPS
+        .with_label_values(&[chain, queue, outcome])
+        .inc();
+}
+
+pub fn node_event(chain: &str, event: &str) {
+    NODE_EVENTS.with_label_values(&[chain, event]).inc();
+}
+
+pub fn validator(
+    chain: &str,
+    operator: &str,
+    moniker: &str,
+    tokens: f64,
+    abstains: u64,
+    drifts: u64,
+) {
+    let labels = [chain, operator, moniker];
+    VALIDATOR_TOKENS.with_label_values(&labels).set(tokens);
+    VALIDATOR_ABSTAINS
+        .with_label_values(&labels)
+        .set(abstains as i64);
+    VALIDATOR_DRIFTS
+        .with_label_values(&labels)
+        .set(drifts as i64);
+}
+
+pub fn validator_reward_rate(chain: &str, operator: &str, moniker: &str, rate: f64) {
+    VALIDATOR_REWARD_RATE
+        .with_label_values(&[chain, operator, moniker])
+        .set(rate);
+}
+
+pub fn exchange_rate(chain: &str, denom: &str, rate: f64) {
+    EXCHANGE_RATE.with_label_values(&[chain, denom]).set(rate);
+}
+
+/// everything registered, in the prometheus text format
+pub fn encode() -> anyhow::Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
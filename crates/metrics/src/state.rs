@@ -0,0 +1,40 @@
+use crate::metrics::{ASN_IPS, COUNTRY_IPS, NODES, NODE_IPS, PENDING_IPS};
+use constellation_state::SharedState;
+
+/// refresh the network gauges from each chain's state. called when metrics are scraped,
+/// so countries/ASNs that have gone away don't linger
+pub fn observe_states(chains: &[(String, SharedState)]) {
+    COUNTRY_IPS.reset();
+    ASN_IPS.reset();
+    for (chain, state) in chains {
+        let chain = chain.as_str();
+        let state = state.snapshot();
+        NODES
+            .with_label_values(&[chain])
+            .set(state.nodes.len() as i64);
+        NODE_IPS
+            .with_label_values(&[chain])
+            .set(state.ip_ip_addr.len() as i64);
+        PENDING_IPS
+            .with_label_values(&[chain, "geo"])
+            .set(state.new_ips_geo.len() as i64);
+        PENDING_IPS
+            .with_label_values(&[chain, "bgp"])
+            .set(state.new_ips_bgp.len() as i64);
+        for (country_id, ips) in &state.geo_country_ip {
+            let country = state
+                .geo_country
+                .get(country_id)
+                .and_then(|c| c.iso_code.clone())
+                .unwrap_or_else(|| country_id.to_string());
+            COUNTRY_IPS
+                .with_label_values(&[chain, country.as_str()])
+                .add(ips.len() as i64);
+        }
+        for (asn, ips) in &state.asn_ip {
+            ASN_IPS
+                .with_label_values(&[chain, asn.as_str()])
+                .set(ips.len() as i64);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MigrateError {
+    #[error("{0} is in a format that isn't recognised")]
+    UnknownVersion(&'static str),
+    #[error("{0} version {1} is newer than this build understands ({2})")]
+    TooNew(&'static str, u32, u32),
+    #[error("{0} has no migration from version {1}")]
+    MissingMigration(&'static str, u32),
+}
@@ -0,0 +1,4 @@
+pub mod errors;
+mod schema;
+
+pub use schema::{Migration, Report, Schema, Step};
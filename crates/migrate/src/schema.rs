@@ -0,0 +1,107 @@
+use crate::errors::MigrateError;
+use serde_json::Value;
+use std::fmt;
+
+/// upgrades a file from version `from` to `from + 1`, returning what it changed
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub upgrade: fn(&mut Value) -> anyhow::Result<Vec<String>>,
+}
+
+/// a persisted format, and how to bring old copies of it up to date
+pub struct Schema {
+    /// eg. "state", used in messages
+    pub name: &'static str,
+    pub current: u32,
+    /// work out which version a file is
+    pub detect: fn(&Value) -> Option<u32>,
+    pub migrations: &'static [Migration],
+}
+
+/// one migration that was applied
+#[derive(Debug)]
+pub struct Step {
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+    pub changes: Vec<String>,
+}
+
+/// what migrating a file did (or would do)
+#[derive(Debug)]
+pub struct Report {
+    pub name: &'static str,
+    pub from: u32,
+    pub to: u32,
+    pub steps: Vec<Step>,
+}
+
+impl Report {
+    pub fn is_current(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_current() {
+            return write!(f, "{} is version {}, nothing to do", self.name, self.from);
+        }
+        write!(f, "{} version {} -> {}", self.name, self.from, self.to)?;
+        for step in &self.steps {
+            write!(f, "\n  {} -> {}: {}", step.from, step.to, step.description)?;
+            for change in &step.changes {
+                write!(f, "\n    - {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Schema {
+    pub fn version(&self, value: &Value) -> Result<u32, MigrateError> {
+        let version = (self.detect)(value).ok_or(MigrateError::UnknownVersion(self.name))?;
+        if version > self.current {
+            return Err(MigrateError::TooNew(self.name, version, self.current));
+        }
+        Ok(version)
+    }
+
+    /// bring `value` up to the current version, one migration at a time
+    pub fn migrate(&self, mut value: Value) -> anyhow::Result<(Value, Report)> {
+        let from = self.version(&value)?;
+        let mut steps = vec![];
+        for version in from..self.current {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|m| m.from == version)
+                .ok_or(MigrateError::MissingMigration(self.name, version))?;
+            let changes = (migration.upgrade)(&mut value)?;
+            steps.push(Step {
+                from: version,
+                to: version + 1,
+                description: migration.description,
+                changes,
+            });
+        }
+        if !steps.is_empty() {
+            log::warn!(
+                "Migrated {} from version {} to {}",
+                self.name,
+                from,
+                self.current
+            );
+        }
+        Ok((
+            value,
+            Report {
+                name: self.name,
+                from,
+                to: self.current,
+                steps,
+            },
+        ))
+    }
+}
@@ -0,0 +1,8 @@
+mod messages;
+mod summary;
+
+pub use messages::{
+    MessageIpAsnChanged, MessageIpCountryChanged, MessageNodeDiscovered, MessageNodeGone,
+    MessageNodeIpChanged,
+};
+pub use summary::NodeSummaryActor;
@@ -0,0 +1,52 @@
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// a node id the address book hasn't reported before
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct MessageNodeDiscovered {
+    /// id@ip:port, as the node is keyed in the state
+    pub node: String,
+    pub id: String,
+    pub ip: String,
+    /// the address book source that reported it first
+    pub source: String,
+}
+
+/// a node every address book source has stopped reporting
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct MessageNodeGone {
+    pub node: String,
+    pub id: String,
+    pub ip: String,
+}
+
+/// a node id that has turned up on a new IP
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct MessageNodeIpChanged {
+    pub id: String,
+    pub ip: String,
+    /// the IPs it was known on before
+    pub previous: Vec<String>,
+}
+
+/// an IP that turned up in a different ASN when it was looked up again
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct MessageIpAsnChanged {
+    pub ip: String,
+    pub asn: String,
+    pub previous: String,
+}
+
+/// an IP that turned up in a different country when it was located again
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct MessageIpCountryChanged {
+    pub ip: String,
+    /// ISO codes, or the geoname id when there isn't one
+    pub country: String,
+    pub previous: String,
+}
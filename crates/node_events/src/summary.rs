@@ -0,0 +1,231 @@
+use crate::messages::{
+    MessageIpAsnChanged, MessageIpCountryChanged, MessageNodeDiscovered, MessageNodeGone,
+    MessageNodeIpChanged,
+};
+use actix::prelude::*;
+use actix_broker::{Broker, BrokerSubscribe, SystemBroker};
+use constellation_shared::messages::{MessageSendMessageEvent, SendMessageEventType};
+use constellation_shared::MessageStop;
+use std::time::Duration;
+
+/// counts node & IP events, and every period announces how many there were
+pub struct NodeSummaryActor {
+    /// the chain_id, for metrics
+    pub chain: String,
+    pub period: Duration,
+    discovered: usize,
+    gone: usize,
+    ip_changed: usize,
+    asn_changed: usize,
+    country_changed: usize,
+}
+
+impl NodeSummaryActor {
+    pub fn new(chain: &str, period: Duration) -> NodeSummaryActor {
+        NodeSummaryActor {
+            chain: chain.into(),
+            period,
+            discovered: 0,
+            gone: 0,
+            ip_changed: 0,
+            asn_changed: 0,
+            country_changed: 0,
+        }
+    }
+
+    /// eg. "12 new peers, 3 gone in the last hour". None when nothing happened
+    pub fn summary(&self) -> Option<String> {
+        let mut parts = vec![];
+        if self.discovered > 0 {
+            parts.push(format!("{} new peers", self.discovered));
+        }
+        if self.gone > 0 {
+            parts.push(format!("{} gone", self.gone));
+        }
+        if self.ip_changed > 0 {
+            parts.push(format!("{} changed IP", self.ip_changed));
+        }
+        if self.asn_changed > 0 {
+            parts.push(format!("{} IPs moved ASN", self.asn_changed));
+        }
+        if self.country_changed > 0 {
+            parts.push(format!("{} IPs moved country", self.country_changed));
+        }
+        if parts.is_empty() {
+            return None;
+        }
+        let minutes = self.period.as_secs() / 60;
+        let over = match minutes {
+            0 | 1 => "the last minute".to_string(),
+            60 => "the last hour".to_string(),
+            m if m % 60 == 0 => format!("the last {} hours", m / 60),
+            m => format!("the last {} minutes", m),
+        };
+        Some(format!("{} in {}", parts.join(", "), over))
+    }
+
+    fn announce(&mut self) {
+        if let Some(message) = self.summary() {
+            log::info!("{}", message);
+            Broker::<SystemBroker>::issue_async(MessageSendMessageEvent {
+                height: 0,
+                event_type: SendMessageEventType::ANNOUNCE,
+                message,
+                hash: None,
+            });
+        }
+        self.discovered = 0;
+        self.gone = 0;
+        self.ip_changed = 0;
+        self.asn_changed = 0;
+        self.country_changed = 0;
+    }
+}
+
+impl Actor for NodeSummaryActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_sync::<SystemBroker, MessageNodeDiscovered>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageNodeGone>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageNodeIpChanged>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageIpAsnChanged>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageIpCountryChanged>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageStop>(ctx);
+        ctx.run_interval(self.period, |act, _ctx| act.announce());
+    }
+}
+
+impl Handler<MessageNodeDiscovered> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeDiscovered, _ctx: &mut Self::Context) {
+        log::debug!("New node {} (from {})", msg.node, msg.source);
+        self.discovered += 1;
+        constellation_metrics::node_event(&self.chain, "discovered");
+    }
+}
+
+impl Handler<MessageNodeGone> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeGone, _ctx: &mut Self::Context) {
+        log::debug!("Node gone {}", msg.node);
+        self.gone += 1;
+        constellation_metrics::node_event(&self.chain, "gone");
+    }
+}
+
+impl Handler<MessageNodeIpChanged> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeIpChanged, _ctx: &mut Self::Context) {
+        log::info!(
+            "Node {} now on {} (was {})",
+            msg.id,
+            msg.ip,
+            msg.previous.join(",")
+        );
+        self.ip_changed += 1;
+        constellation_metrics::node_event(&self.chain, "ip_changed");
+    }
+}
+
+impl Handler<MessageIpAsnChanged> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageIpAsnChanged, _ctx: &mut Self::Context) {
+        log::info!(
+            "IP {} now in AS{} (was AS{})",
+            msg.ip,
+            msg.asn,
+            msg.previous
+        );
+        self.asn_changed += 1;
+        constellation_metrics::node_event(&self.chain, "ip_asn_changed");
+    }
+}
+
+impl Handler<MessageIpCountryChanged> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageIpCountryChanged, _ctx: &mut Self::Context) {
+        log::info!(
+            "IP {} now in {} (was {})",
+            msg.ip,
+            msg.country,
+            msg.previous
+        );
+        self.country_changed += 1;
+        constellation_metrics::node_event(&self.chain, "ip_country_changed");
+    }
+}
+
+impl Handler<MessageStop> for NodeSummaryActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: MessageStop, ctx: &mut Self::Context) {
+        ctx.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(minutes: u64) -> NodeSummaryActor {
+        NodeSummaryActor::new("columbus-5", Duration::from_secs(minutes * 60))
+    }
+
+    #[test]
+    fn says_nothing_when_nothing_happened() {
+        assert_eq!(summary(60).summary(), None);
+    }
+
+    #[test]
+    fn words_the_counts_and_period() {
+        let mut actor = summary(60);
+        actor.discovered = 12;
+        actor.gone = 3;
+        assert_eq!(
+            actor.summary().as_deref(),
+            Some("12 new peers, 3 gone in the last hour")
+        );
+
+        actor.ip_changed = 2;
+        actor.asn_changed = 4;
+        actor.country_changed = 5;
+        actor.period = Duration::from_secs(6 * 60 * 60);
+        assert_eq!(
+            actor.summary().as_deref(),
+            Some(
+                "12 new peers, 3 gone, 2 changed IP, 4 IPs moved ASN, 5 IPs moved country \
+                 in the last 6 hours"
+            )
+        );
+
+        let mut actor = summary(90);
+        actor.gone = 2;
+        assert_eq!(
+            actor.summary().as_deref(),
+            Some("2 gone in the last 90 minutes")
+        );
+        actor.period = Duration::from_secs(30);
+        assert_eq!(
+            actor.summary().as_deref(),
+            Some("2 gone in the last minute")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn starts_counting_again_after_announcing() {
+        let mut actor = summary(60);
+        actor.discovered = 1;
+        actor.asn_changed = 1;
+        actor.announce();
+        assert_eq!(actor.summary(), None);
+
+        actor.gone = 2;
+        assert_eq!(actor.summary().as_deref(), Some("2 gone in the last hour"));
+    }
+}
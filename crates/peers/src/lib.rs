@@ -0,0 +1,3 @@
+mod recommend;
+
+pub use recommend::{persistent_peers, recommend, Criteria, Recommendation};
@@ -0,0 +1,347 @@
+use constellation_prober::Reachability;
+use constellation_shared::state::State;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// how much a peer's score drops for each peer already picked in the same ASN, country & continent
+const ASN_WEIGHT: f64 = 1.0;
+const COUNTRY_WEIGHT: f64 = 0.5;
+const CONTINENT_WEIGHT: f64 = 0.25;
+
+/// what to pick
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Criteria {
+    /// how many peers
+    pub count: usize,
+    /// the share of probes a peer must have answered
+    pub min_uptime: f64,
+}
+
+impl Default for Criteria {
+    fn default() -> Self {
+        Criteria {
+            count: 10,
+            min_uptime: 0.5,
+        }
+    }
+}
+
+/// a peer worth adding, and why
+#[derive(Clone, Debug, Serialize)]
+pub struct Recommendation {
+    /// id@ip:port
+    pub node: String,
+    pub ip: String,
+    pub asn: Option<String>,
+    /// iso code
+    pub country: Option<String>,
+    pub continent: Option<String>,
+    pub uptime: f64,
+    pub latency_ms: Option<u64>,
+    /// uptime, less the diversity penalty when it was picked
+    pub score: f64,
+}
+
+/// up to `criteria.count` reachable nodes, one per IP, picked one at a time so each pick
+/// favours uptime but is marked down for sharing an ASN, country or continent with earlier picks.
+/// nodes whose handshake showed another network, or a key other than their id, are left out
+pub fn recommend(
+    state: &State,
+    reachability: &HashMap<String, Reachability>,
+    criteria: &Criteria,
+) -> Vec<Recommendation> {
+    let mut candidates = state
+        .nodes
+        .iter()
+        .filter_map(|(node, entry)| {
+            let reach = reachability.get(node)?;
+            if !reach.reachable || reach.uptime() < criteria.min_uptime {
+                return None;
+            }
+            if let Some(handshake) = &reach.handshake {
+                if handshake.node_info.is_some()
+                    && (handshake.wrong_network || !handshake.id_matches)
+                {
+                    return None;
+                }
+            }
+            let ip = entry.addr.ip.clone();
+            Some(Recommendation {
+                node: node.clone(),
+                asn: state.ip_asn.get(&ip).map(|a| a.asn.clone()),
+                country: state
+                    .geo_ip_country
+                    .get(&ip)
+                    .and_then(|id| state.geo_country.get(id))
+                    .and_then(|c| c.iso_code.clone()),
+                continent: state
+                    .geo_ip_continent
+                    .get(&ip)
+                    .and_then(|id| state.geo_continent.get(id))
+                    .and_then(|c| c.code.clone()),
+                ip,
+                uptime: reach.uptime(),
+                latency_ms: reach.latency_ms,
+                score: 0.0,
+            })
+        })
+        .collect::<Vec<_>>();
+    // best first, so ties go to the steadier, quicker node
+    candidates.sort_by(|a, b| {
+        b.uptime
+            .partial_cmp(&a.uptime)
+            .unwrap_or(Ordering::Equal)
+            .then(
+                a.latency_ms
+                    .unwrap_or(u64::MAX)
+                    .cmp(&b.latency_ms.unwrap_or(u64::MAX)),
+            )
+            .then(a.node.cmp(&b.node))
+    });
+
+    let mut asns: HashMap<Option<String>, usize> = HashMap::new();
+    let mut countries: HashMap<Option<String>, usize> = HashMap::new();
+    let mut continents: HashMap<Option<String>, usize> = HashMap::new();
+    let mut ips = HashSet::new();
+    let mut picked = vec![];
+    while picked.len() < criteria.count {
+        candidates.retain(|c| !ips.contains(&c.ip));
+        let mut best: Option<(usize, f64)> = None;
+        for (i, candidate) in candidates.iter().enumerate() {
+            let penalty = ASN_WEIGHT * *asns.get(&candidate.asn).unwrap_or(&0) as f64
+                + COUNTRY_WEIGHT * *countries.get(&candidate.country).unwrap_or(&0) as f64
+                + CONTINENT_WEIGHT * *continents.get(&candidate.continent).unwrap_or(&0) as f64;
+            let score = candidate.uptime / (1.0 + penalty);
+            if best
+                .map(|(_, best_score)| score > best_score)
+                .unwrap_or(true)
+            {
+                best = Some((i, score));
+            }
+        }
+        let (i, score) = match best {
+            Some(best) => best,
+            None => break,
+        };
+        let mut pick = candidates.remove(i);
+        pick.score = score;
+        *asns.entry(pick.asn.clone()).or_default() += 1;
+        *countries.entry(pick.country.clone()).or_default() += 1;
+        *continents.entry(pick.continent.clone()).or_default() += 1;
+        ips.insert(pick.ip.clone());
+        picked.push(pick);
+    }
+    picked
+}
+
+/// id@ip:port,id@ip:port as `persistent_peers` in tendermint's config.toml wants it
+pub fn persistent_peers(peers: &[Recommendation]) -> String {
+    peers
+        .iter()
+        .map(|p| p.node.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constellation_prober::{Handshake, NodeInfo};
+    use serde_json::{json, Value};
+
+    const WHEN: &str = "2021-11-02T10:15:00Z";
+
+    /// id, ip, asn, country & continent
+    type Node<'a> = (&'a str, &'a str, &'a str, &'a str, &'a str);
+
+    fn key(id: &str, ip: &str) -> String {
+        format!("{}@{}:26656", id, ip)
+    }
+
+    /// a state holding `nodes`, each located as given
+    fn state(nodes: &[Node]) -> State {
+        let mut state = serde_json::to_value(State::new().unwrap()).unwrap();
+        let places = [("DE", 1, 10), ("FR", 2, 10), ("US", 3, 11)];
+        for (iso, country, _) in places.iter() {
+            state["geo_country"][country.to_string()] = json!({
+                "geoname_id": country, "name": iso, "is_in_european_union": null, "iso_code": iso,
+                "last_updated": WHEN,
+            });
+        }
+        for (code, continent) in [("EU", 10), ("NA", 11)].iter() {
+            state["geo_continent"][continent.to_string()] = json!({
+                "geoname_id": continent, "name": code, "code": code, "last_updated": WHEN,
+            });
+        }
+        for (id, ip, asn, iso, _) in nodes {
+            let addr = json!({ "id": id, "ip": ip, "port": 26656 });
+            state["nodes"][key(id, ip)] = json!({
+                "addr": addr, "src": addr, "buckets": [], "attempts": 0, "bucket_type": 1,
+                "last_attempt": WHEN, "last_success": WHEN, "last_ban_time": WHEN,
+            });
+            state["ip_asn"][*ip] = json!({
+                "asn": asn, "range": "10.0.0.0/8", "country": "", "network": "",
+                "last_updated": WHEN,
+            });
+            let (_, country, continent) = places.iter().find(|(c, _, _)| c == iso).unwrap();
+            state["geo_ip_country"][*ip] = json!(country);
+            state["geo_ip_continent"][*ip] = json!(continent);
+        }
+        serde_json::from_value(state).unwrap()
+    }
+
+    /// answered `percent` of 100 probes, the last of them
+    fn reach(percent: u32) -> Reachability {
+        Reachability {
+            reachable: true,
+            latency_ms: Some(10),
+            probes: 100,
+            successes: percent,
+            ..Default::default()
+        }
+    }
+
+    fn handshake(id_matches: bool, wrong_network: bool, answered: bool) -> Handshake {
+        let node_info = if answered {
+            serde_json::to_value(NodeInfo::default()).unwrap()
+        } else {
+            Value::Null
+        };
+        serde_json::from_value(json!({
+            "at": WHEN,
+            "node_info": node_info,
+            "error": null,
+            "id_matches": id_matches,
+            "wrong_network": wrong_network,
+        }))
+        .unwrap()
+    }
+
+    fn picked(recommendations: &[Recommendation]) -> Vec<&str> {
+        recommendations
+            .iter()
+            .map(|r| r.node.split('@').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn spreads_picks_across_asns_countries_and_continents() {
+        let nodes: &[Node] = &[
+            ("a", "10.0.0.1", "1", "DE", "EU"),
+            ("b", "10.0.0.2", "1", "FR", "EU"),
+            ("d", "10.0.0.4", "3", "DE", "EU"),
+            ("e", "10.0.0.5", "4", "FR", "EU"),
+            ("f", "10.0.0.6", "5", "US", "NA"),
+        ];
+        let reachability = [("a", 100), ("b", 95), ("d", 90), ("e", 85), ("f", 60)]
+            .iter()
+            .zip(nodes)
+            .map(|((_, percent), (id, ip, ..))| (key(id, ip), reach(*percent)))
+            .collect();
+        let criteria = Criteria {
+            count: 10,
+            min_uptime: 0.5,
+        };
+        let recommendations = recommend(&state(nodes), &reachability, &criteria);
+
+        // by uptime alone it would be a, b, d, e, f. b shares a's ASN and continent, d its
+        // country and continent, and e only its continent
+        assert_eq!(picked(&recommendations), vec!["a", "e", "f", "d", "b"]);
+        let scores = recommendations.iter().map(|r| r.score).collect::<Vec<_>>();
+        let expected = [1.0, 0.85 / 1.25, 0.6, 0.9 / 2.0, 0.95 / 3.25];
+        for (score, expected) in scores.iter().zip(expected.iter()) {
+            assert!((score - expected).abs() < 1e-9, "{:?}", scores);
+        }
+        let first = &recommendations[0];
+        assert_eq!(first.asn.as_deref(), Some("1"));
+        assert_eq!(first.country.as_deref(), Some("DE"));
+        assert_eq!(first.continent.as_deref(), Some("EU"));
+
+        let two = recommend(
+            &state(nodes),
+            &reachability,
+            &Criteria {
+                count: 2,
+                ..criteria
+            },
+        );
+        assert_eq!(picked(&two), vec!["a", "e"]);
+    }
+
+    #[test]
+    fn picks_one_node_per_ip() {
+        let nodes: &[Node] = &[
+            ("a", "10.0.0.1", "1", "DE", "EU"),
+            ("b", "10.0.0.1", "1", "DE", "EU"),
+            ("c", "10.0.0.2", "1", "DE", "EU"),
+        ];
+        let reachability = [("a", 90), ("b", 100), ("c", 60)]
+            .iter()
+            .map(|(id, percent)| {
+                let ip = nodes.iter().find(|n| n.0 == *id).unwrap().1;
+                (key(id, ip), reach(*percent))
+            })
+            .collect();
+        let recommendations = recommend(&state(nodes), &reachability, &Criteria::default());
+        assert_eq!(picked(&recommendations), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn leaves_out_unsteady_and_unreachable_nodes() {
+        let nodes: &[Node] = &[
+            ("steady", "10.0.0.1", "1", "DE", "EU"),
+            ("flaky", "10.0.0.2", "2", "FR", "EU"),
+            ("down", "10.0.0.3", "3", "US", "NA"),
+            ("unprobed", "10.0.0.4", "4", "US", "NA"),
+        ];
+        let mut reachability = HashMap::new();
+        reachability.insert(key("steady", "10.0.0.1"), reach(50));
+        reachability.insert(key("flaky", "10.0.0.2"), reach(49));
+        reachability.insert(
+            key("down", "10.0.0.3"),
+            Reachability {
+                reachable: false,
+                ..reach(100)
+            },
+        );
+        let recommendations = recommend(&state(nodes), &reachability, &Criteria::default());
+        assert_eq!(picked(&recommendations), vec!["steady"]);
+
+        let lenient = Criteria {
+            min_uptime: 0.0,
+            ..Criteria::default()
+        };
+        let recommendations = recommend(&state(nodes), &reachability, &lenient);
+        assert_eq!(picked(&recommendations), vec!["steady", "flaky"]);
+    }
+
+    #[test]
+    fn leaves_out_nodes_on_another_network_or_with_another_key() {
+        let nodes: &[Node] = &[
+            ("good", "10.0.0.1", "1", "DE", "EU"),
+            ("bombay", "10.0.0.2", "2", "FR", "EU"),
+            ("imposter", "10.0.0.3", "3", "US", "NA"),
+            ("silent", "10.0.0.4", "4", "US", "NA"),
+        ];
+        let with = |handshake: Handshake| Reachability {
+            handshake: Some(handshake),
+            ..reach(100)
+        };
+        let mut reachability = HashMap::new();
+        reachability.insert(key("good", "10.0.0.1"), with(handshake(true, false, true)));
+        reachability.insert(key("bombay", "10.0.0.2"), with(handshake(true, true, true)));
+        reachability.insert(
+            key("imposter", "10.0.0.3"),
+            with(handshake(false, false, true)),
+        );
+        // never completed a handshake, so there's nothing to hold against it
+        reachability.insert(
+            key("silent", "10.0.0.4"),
+            with(handshake(false, false, false)),
+        );
+        let recommendations = recommend(&state(nodes), &reachability, &Criteria::default());
+        assert_eq!(picked(&recommendations), vec!["good", "silent"]);
+    }
+}
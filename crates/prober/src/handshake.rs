@@ -0,0 +1,298 @@
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Keypair;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tendermint_p2p::secret_connection::{SecretConnection, Version};
+use tendermint_proto::p2p::{DefaultNodeInfo, DefaultNodeInfoOther, ProtocolVersion};
+
+/// tendermint refuses node infos larger than this
+const MAX_NODE_INFO_SIZE: usize = 10240;
+
+/// what a peer says about itself once the secret connection is up
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NodeInfo {
+    /// derived from the key the peer proved it holds, not what it claims
+    pub id: String,
+    pub claimed_id: String,
+    pub network: String,
+    pub version: String,
+    pub moniker: String,
+    pub listen_addr: String,
+    /// hex, one byte per reactor channel
+    pub channels: String,
+    pub p2p_protocol: u64,
+    pub block_protocol: u64,
+    pub app_protocol: u64,
+    pub tx_index: String,
+    pub rpc_address: String,
+}
+
+/// the last handshake with a node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Handshake {
+    pub at: DateTime<Utc>,
+    /// from the last handshake that worked
+    pub node_info: Option<NodeInfo>,
+    pub error: Option<String>,
+    /// the peer holds the key for the id the address book has for it
+    pub id_matches: bool,
+    /// the peer is on another chain, eg. a bombay node in the columbus book
+    pub wrong_network: bool,
+}
+
+impl Handshake {
+    pub fn new(
+        id: &str,
+        network: &str,
+        result: &Result<NodeInfo, String>,
+        previous: Option<&Handshake>,
+        now: DateTime<Utc>,
+    ) -> Handshake {
+        match result {
+            Ok(info) => Handshake {
+                at: now,
+                node_info: Some(info.clone()),
+                error: None,
+                id_matches: info.id == id,
+                wrong_network: info.network != network,
+            },
+            Err(e) => Handshake {
+                at: now,
+                node_info: previous.and_then(|p| p.node_info.clone()),
+                error: Some(e.clone()),
+                id_matches: previous.map(|p| p.id_matches).unwrap_or_default(),
+                wrong_network: previous.map(|p| p.wrong_network).unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// a tendermint node id: the first 20 bytes of the sha256 of its ed25519 public key, in hex
+pub fn node_id(public_key: &[u8]) -> String {
+    hex::encode(&Sha256::digest(public_key)[..20])
+}
+
+/// open a secret connection to `address` with a throwaway key, and swap node infos.
+/// `network` is what we claim to be on. blocking, each read & write is limited to `timeout`
+pub fn handshake(address: &str, network: &str, timeout: Duration) -> Result<NodeInfo, String> {
+    let socket_address = address
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", address))?;
+    let stream = TcpStream::connect_timeout(&socket_address, timeout).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    let identity = Keypair::generate(&mut rand::rngs::OsRng);
+    let our_id = node_id(identity.public.as_bytes());
+    let mut connection = SecretConnection::new(stream, identity, Version::V0_34)
+        .map_err(|e| format!("secret connection: {}", e))?;
+    let their_id = node_id(&connection.remote_pubkey().to_bytes());
+
+    let ours = DefaultNodeInfo {
+        protocol_version: Some(ProtocolVersion {
+            p2p: 8,
+            block: 11,
+            app: 0,
+        }),
+        default_node_id: our_id,
+        listen_addr: "tcp://0.0.0.0:26656".into(),
+        network: network.into(),
+        version: "constellation".into(),
+        channels: vec![],
+        moniker: "constellation".into(),
+        other: Some(DefaultNodeInfoOther {
+            tx_index: "off".into(),
+            rpc_address: "".into(),
+        }),
+    };
+    connection
+        .write_all(&ours.encode_length_delimited_to_vec())
+        .and_then(|_| connection.flush())
+        .map_err(|e| format!("sending node info: {}", e))?;
+    let theirs =
+        read_node_info(&mut connection).map_err(|e| format!("reading node info: {}", e))?;
+
+    let protocol = theirs.protocol_version.unwrap_or_default();
+    let other = theirs.other.unwrap_or_default();
+    Ok(NodeInfo {
+        id: their_id,
+        claimed_id: theirs.default_node_id,
+        network: theirs.network,
+        version: theirs.version,
+        moniker: theirs.moniker,
+        listen_addr: theirs.listen_addr,
+        channels: hex::encode(&theirs.channels),
+        p2p_protocol: protocol.p2p,
+        block_protocol: protocol.block,
+        app_protocol: protocol.app,
+        tx_index: other.tx_index,
+        rpc_address: other.rpc_address,
+    })
+}
+
+/// a uvarint length, then the protobuf
+fn read_node_info<R: Read>(reader: &mut R) -> anyhow::Result<DefaultNodeInfo> {
+    let mut length = 0usize;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > MAX_NODE_INFO_SIZE {
+        anyhow::bail!("{} bytes is too long for a node info", length)
+    }
+    let mut buf = vec![0u8; length];
+    reader.read_exact(&mut buf)?;
+    Ok(DefaultNodeInfo::decode(buf.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn their_info(moniker: &str) -> DefaultNodeInfo {
+        DefaultNodeInfo {
+            protocol_version: Some(ProtocolVersion {
+                p2p: 8,
+                block: 11,
+                app: 0,
+            }),
+            default_node_id: "3a7c1ad3f8e5b2c9d4e6f7a8b9c0d1e2f3a4b5c6".into(),
+            listen_addr: "tcp://0.0.0.0:26656".into(),
+            network: "columbus-5".into(),
+            version: "0.34.14".into(),
+            channels: vec![0x40, 0x20, 0x21],
+            moniker: moniker.into(),
+            other: Some(DefaultNodeInfoOther {
+                tx_index: "on".into(),
+                rpc_address: "tcp://127.0.0.1:26657".into(),
+            }),
+        }
+    }
+
+    #[test]
+    fn reads_a_length_delimited_node_info() {
+        let short = DefaultNodeInfo {
+            network: "columbus-5".into(),
+            moniker: "node".into(),
+            ..Default::default()
+        };
+        let long = their_info(&"m".repeat(300));
+        // short enough for a one byte length, and long enough to need two
+        assert!(short.encoded_len() < 0x80 && long.encoded_len() >= 0x80);
+        for sent in &[short, long] {
+            let mut bytes = sent.encode_length_delimited_to_vec();
+            assert_eq!(bytes[0] & 0x80 != 0, sent.encoded_len() >= 0x80);
+            // what follows the node info is left for the connection
+            bytes.extend_from_slice(b"next");
+            let mut reader = Cursor::new(bytes);
+            let info = read_node_info(&mut reader).unwrap();
+            assert_eq!(&info, sent);
+            let mut rest = vec![];
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, b"next");
+        }
+    }
+
+    #[test]
+    fn refuses_an_oversized_node_info() {
+        // 10241 as a uvarint, with nothing after it: the length alone is enough to refuse it
+        let too_long = MAX_NODE_INFO_SIZE + 1;
+        let bytes = vec![
+            (too_long & 0x7f) as u8 | 0x80,
+            ((too_long >> 7) & 0x7f) as u8,
+        ];
+        let error = read_node_info(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(error.to_string().contains("too long"), "{}", error);
+
+        let largest = their_info(&"m".repeat(MAX_NODE_INFO_SIZE - 200));
+        assert!(largest.encoded_len() <= MAX_NODE_INFO_SIZE);
+        let bytes = largest.encode_length_delimited_to_vec();
+        assert!(read_node_info(&mut Cursor::new(bytes)).is_ok());
+    }
+
+    #[test]
+    fn fails_on_a_cut_short_node_info() {
+        let bytes = their_info("node").encode_length_delimited_to_vec();
+        assert!(read_node_info(&mut Cursor::new(&bytes[..bytes.len() - 1])).is_err());
+        assert!(read_node_info(&mut Cursor::new(vec![0x80])).is_err());
+    }
+
+    fn node_info(id: &str, network: &str) -> NodeInfo {
+        NodeInfo {
+            id: id.into(),
+            claimed_id: id.into(),
+            network: network.into(),
+            moniker: "node".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checks_the_id_and_network() {
+        let now = Utc::now();
+        let ok = Handshake::new(
+            "abc",
+            "columbus-5",
+            &Ok(node_info("abc", "columbus-5")),
+            None,
+            now,
+        );
+        assert!(ok.id_matches);
+        assert!(!ok.wrong_network);
+        assert!(ok.error.is_none());
+
+        let other = Handshake::new(
+            "abc",
+            "columbus-5",
+            &Ok(node_info("def", "bombay-12")),
+            None,
+            now,
+        );
+        assert!(!other.id_matches);
+        assert!(other.wrong_network);
+    }
+
+    #[test]
+    fn keeps_what_the_last_handshake_found_when_one_fails() {
+        let earlier = Utc::now() - chrono::Duration::hours(1);
+        let previous = Handshake::new(
+            "abc",
+            "columbus-5",
+            &Ok(node_info("def", "bombay-12")),
+            None,
+            earlier,
+        );
+        let now = Utc::now();
+        let failed = Handshake::new(
+            "abc",
+            "columbus-5",
+            &Err("connection reset".into()),
+            Some(&previous),
+            now,
+        );
+        assert_eq!(failed.at, now);
+        assert_eq!(failed.error.as_deref(), Some("connection reset"));
+        assert_eq!(failed.node_info.unwrap().network, "bombay-12");
+        assert!(!failed.id_matches);
+        assert!(failed.wrong_network);
+
+        // with nothing to go on, nothing is held against the node
+        let first = Handshake::new("abc", "columbus-5", &Err("refused".into()), None, now);
+        assert!(first.node_info.is_none());
+        assert!(!first.wrong_network);
+        assert!(!first.id_matches);
+    }
+}
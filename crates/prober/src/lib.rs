@@ -0,0 +1,9 @@
+mod handshake;
+mod probe;
+mod reachability;
+mod task;
+
+pub use handshake::{handshake, node_id, Handshake, NodeInfo};
+pub use probe::{probe_tcp, socket_address};
+pub use reachability::{load_reachability, save_reachability, AppReachability, Reachability};
+pub use task::{run, ProberSettings};
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time;
+
+/// host:port, with IPv6 addresses in brackets
+pub fn socket_address(ip: &str, port: impl std::fmt::Display) -> String {
+    if ip.contains(':') && !ip.starts_with('[') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
+
+/// open (and drop) a TCP connection, returning how long the connect took
+pub async fn probe_tcp(address: &str, timeout: Duration) -> Result<Duration, String> {
+    let start = Instant::now();
+    match time::timeout(timeout, TcpStream::connect(address)).await {
+        Ok(Ok(_stream)) => Ok(start.elapsed()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {}ms", timeout.as_millis())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reachability;
+    use chrono::Utc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn probes_open_and_closed_ports() {
+        let timeout = Duration::from_millis(500);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().to_string();
+        // bind then drop, so the port is (almost certainly) closed
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let mut up = Reachability::default();
+        up.record(&probe_tcp(&open, timeout).await, Utc::now());
+        assert!(up.reachable);
+        assert!(up.latency_ms.is_some());
+
+        let mut down = Reachability::default();
+        for _ in 0..2 {
+            down.record(&probe_tcp(&closed, timeout).await, Utc::now());
+        }
+        assert!(!down.reachable);
+        assert_eq!(down.consecutive_failures, 2);
+        assert!(down.last_success.is_none());
+    }
+
+    #[test]
+    fn brackets_ipv6() {
+        assert_eq!(socket_address("10.0.0.1", 26656), "10.0.0.1:26656");
+        assert_eq!(socket_address("2001:db8::1", 26656), "[2001:db8::1]:26656");
+        assert_eq!(
+            socket_address("[2001:db8::1]", 26656),
+            "[2001:db8::1]:26656"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::handshake::Handshake;
+use chrono::{DateTime, Utc};
+use constellation_state::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// keyed as in `State::nodes` (id@ip:port)
+pub type AppReachability = Arc<Mutex<HashMap<String, Reachability>>>;
+
+/// how a node answered the last time it was probed
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reachability {
+    pub reachable: bool,
+    /// how long the last successful connect took
+    pub latency_ms: Option<u64>,
+    /// probes that have failed since the last success
+    pub consecutive_failures: u32,
+    /// every probe, and those that connected, since the node was first probed
+    pub probes: u32,
+    pub successes: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_probe: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// when handshakes are on, what the node said about itself
+    pub handshake: Option<Handshake>,
+}
+
+impl Reachability {
+    pub fn record(&mut self, result: &Result<Duration, String>, now: DateTime<Utc>) {
+        self.last_probe = Some(now);
+        self.probes += 1;
+        match result {
+            Ok(latency) => {
+                self.reachable = true;
+                self.successes += 1;
+                self.latency_ms = Some(latency.as_millis() as u64);
+                self.consecutive_failures = 0;
+                self.last_success = Some(now);
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.reachable = false;
+                self.consecutive_failures += 1;
+                self.last_error = Some(e.clone());
+            }
+        }
+    }
+
+    /// the share of probes that connected
+    pub fn uptime(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            self.successes as f64 / self.probes as f64
+        }
+    }
+}
+
+/// a missing file is nothing probed yet
+pub fn load_reachability(file: &str) -> anyhow::Result<HashMap<String, Reachability>> {
+    if !Path::new(file).exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(serde_json::from_reader(File::open(file)?)?)
+}
+
+/// the lock is only held while the results are serialised, not while they are written
+pub fn save_reachability(reachability: &AppReachability, file: &str) -> anyhow::Result<()> {
+    let json = {
+        let reach = reachability.lock().unwrap_or_else(PoisonError::into_inner);
+        serde_json::to_vec(&*reach)?
+    };
+    write_atomic(file, &json)
+}
@@ -0,0 +1,129 @@
+use crate::handshake::{handshake, Handshake};
+use crate::probe::{probe_tcp, socket_address};
+use crate::reachability::{save_reachability, AppReachability};
+use chrono::Utc;
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::PoisonError;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time;
+
+/// how the prober runs
+#[derive(Clone, Debug)]
+pub struct ProberSettings {
+    /// where the results are saved after each round
+    pub file: String,
+    pub period: Duration,
+    /// most nodes probed at once
+    pub concurrency: usize,
+    pub timeout: Duration,
+    /// ask reachable nodes for their node info, and check it against their id and `chain`
+    pub handshakes: bool,
+    pub chain: String,
+}
+
+/// every period, try to connect to every node, at most `concurrency` at a time.
+/// nodes sharing an address are probed once. returns once `shutdown` turns true
+pub async fn run(
+    state: SharedState,
+    reachability: AppReachability,
+    settings: ProberSettings,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let ProberSettings {
+        file,
+        period,
+        concurrency,
+        timeout,
+        handshakes,
+        chain,
+    } = settings;
+    let mut interval = time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        let mut by_address: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        {
+            let the_state = state.snapshot();
+            for (node, entry) in &the_state.nodes {
+                by_address
+                    .entry(socket_address(&entry.addr.ip, &entry.addr.port))
+                    .or_default()
+                    .push((node.clone(), entry.addr.id.clone()));
+            }
+        }
+        let addresses = by_address.len();
+        let results = stream::iter(by_address)
+            .map(|(address, nodes)| {
+                let chain = chain.clone();
+                async move {
+                    let result = probe_tcp(&address, timeout).await;
+                    let node_info = if handshakes && result.is_ok() {
+                        let blocking = tokio::task::spawn_blocking(move || {
+                            handshake(&address, &chain, timeout)
+                        });
+                        Some(blocking.await.unwrap_or_else(|e| Err(e.to_string())))
+                    } else {
+                        None
+                    };
+                    (nodes, result, node_info)
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let now = Utc::now();
+        let mut reachable = 0;
+        let mut wrong_network = 0;
+        {
+            let mut reach = reachability.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut probed = HashSet::new();
+            for (nodes, result, node_info) in &results {
+                for (node, id) in nodes {
+                    let entry = reach.entry(node.clone()).or_default();
+                    entry.record(result, now);
+                    if let Some(node_info) = node_info {
+                        let handshake =
+                            Handshake::new(id, &chain, node_info, entry.handshake.as_ref(), now);
+                        if handshake.node_info.is_some() && !handshake.id_matches {
+                            log::warn!("{} answered with a different node id", node);
+                        }
+                        if handshake.wrong_network {
+                            wrong_network += 1;
+                        }
+                        entry.handshake = Some(handshake);
+                    }
+                    probed.insert(node.clone());
+                    if result.is_ok() {
+                        reachable += 1;
+                    }
+                }
+            }
+            // nodes retention has since dropped
+            reach.retain(|node, _| probed.contains(node));
+        }
+        log::info!(
+            "Probed {} addresses. {} of the nodes on them are reachable",
+            addresses,
+            reachable
+        );
+        if wrong_network > 0 {
+            log::warn!("{} nodes are not on {}", wrong_network, chain);
+        }
+        if let Err(e) = save_reachability(&reachability, &file) {
+            log::error!("Unable to save {} {}", file, e);
+        }
+        constellation_metrics::nodes_reachable(&chain, reachable);
+        if handshakes {
+            constellation_metrics::nodes_wrong_network(&chain, wrong_network);
+        }
+        status.success(addresses);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::record::Record;
+use actix::prelude::*;
+use actix_broker::{BrokerSubscribe, SystemBroker};
+use chrono::Utc;
+use constellation_node_events::{
+    MessageIpAsnChanged, MessageIpCountryChanged, MessageNodeDiscovered, MessageNodeGone,
+    MessageNodeIpChanged,
+};
+use constellation_shared::messages::{
+    MessageBlockEventExchangeRate, MessageBlockEventLiveness, MessageBlockEventReward,
+    MessageBlockHeight, MessagePriceAbstain, MessagePriceDrift, MessageSendMessageEvent,
+    MessageValidator, MessageValidatorEvent, MessageValidatorStakedTotal,
+};
+use constellation_shared::{MessageStop, MessageTick};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+/// writes every broker message to a JSONL file, one `Record` per line
+pub struct RecorderActor {
+    pub chain: String,
+    pub file: String,
+    writer: BufWriter<File>,
+}
+
+impl RecorderActor {
+    /// recordings are appended to, so a restart doesn't lose what was there
+    pub fn create(file: &str, chain: &str) -> anyhow::Result<RecorderActor> {
+        let f = OpenOptions::new().create(true).append(true).open(file)?;
+        Ok(RecorderActor {
+            chain: chain.into(),
+            file: file.into(),
+            writer: BufWriter::new(f),
+        })
+    }
+
+    fn record<M: Serialize>(&mut self, message_type: &str, msg: &M) {
+        let result = serde_json::to_value(msg)
+            .map(|message| Record {
+                at: Utc::now(),
+                chain: self.chain.clone(),
+                message_type: message_type.into(),
+                message,
+            })
+            .and_then(|record| serde_json::to_writer(&mut self.writer, &record))
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                self.writer.write_all(b"\n")?;
+                Ok(self.writer.flush()?)
+            });
+        if let Err(e) = result {
+            log::error!("Unable to record {} to {} {}", message_type, self.file, e)
+        }
+    }
+}
+
+// one handler per recorded message type, all the same. MessageStop is handled below, as it
+// also stops the recorder
+macro_rules! record_messages {
+    ($($msg:ident),*) => {
+        impl Actor for RecorderActor {
+            type Context = Context<Self>;
+
+            fn started(&mut self, ctx: &mut Self::Context) {
+                log::info!("Recording broker messages to {}", self.file);
+                $(self.subscribe_sync::<SystemBroker, $msg>(ctx);)*
+                self.subscribe_sync::<SystemBroker, MessageStop>(ctx);
+            }
+        }
+        $(
+            impl Handler<$msg> for RecorderActor {
+                type Result = ();
+
+                fn handle(&mut self, msg: $msg, _ctx: &mut Self::Context) {
+                    self.record(stringify!($msg), &msg)
+                }
+            }
+        )*
+    };
+}
+
+record_messages!(
+    MessageValidator,
+    MessageValidatorEvent,
+    MessageValidatorStakedTotal,
+    MessageSendMessageEvent,
+    MessagePriceAbstain,
+    MessagePriceDrift,
+    MessageBlockEventLiveness,
+    MessageBlockEventReward,
+    MessageBlockEventExchangeRate,
+    MessageBlockHeight,
+    MessageTick,
+    MessageNodeDiscovered,
+    MessageNodeGone,
+    MessageNodeIpChanged,
+    MessageIpAsnChanged,
+    MessageIpCountryChanged
+);
+
+impl Handler<MessageStop> for RecorderActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageStop, ctx: &mut Self::Context) {
+        self.record("MessageStop", &msg);
+        log::info!("Recorder stopping");
+        ctx.stop()
+    }
+}
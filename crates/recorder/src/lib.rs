@@ -0,0 +1,6 @@
+pub mod actor;
+mod record;
+mod replay;
+
+pub use record::Record;
+pub use replay::replay;
@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// one broker message, as a line of the recording
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    /// the chain whose broker carried the message
+    pub chain: String,
+    /// message type name, eg. MessageValidator
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub message: serde_json::Value,
+}
@@ -0,0 +1,105 @@
+use crate::record::Record;
+use actix_broker::{Broker, SystemBroker};
+use constellation_node_events::{
+    MessageIpAsnChanged, MessageIpCountryChanged, MessageNodeDiscovered, MessageNodeGone,
+    MessageNodeIpChanged,
+};
+use constellation_shared::messages::{
+    MessageBlockEventExchangeRate, MessageBlockEventLiveness, MessageBlockEventReward,
+    MessageBlockHeight, MessagePriceAbstain, MessagePriceDrift, MessageSendMessageEvent,
+    MessageValidator, MessageValidatorEvent, MessageValidatorStakedTotal,
+};
+use constellation_shared::MessageTick;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// what the actors say about what they were told. replaying the input has them say it again
+const OUTPUTS: &[&str] = &["MessageSendMessageEvent", "MessageValidatorEvent"];
+
+/// feed a recording back into the broker, keeping the gaps between messages.
+/// `speed` 1.0 is as recorded, 10.0 is ten times faster, 0 is as fast as possible.
+/// if `chain` is given, only that chain's messages are replayed, and the actors' own output is
+/// only replayed with `outputs`. a recording spanning a restart has the stop of the run before
+/// it part way through, so `MessageStop` never is.
+/// returns the number of messages replayed
+pub async fn replay(
+    file: &str,
+    speed: f64,
+    chain: Option<&str>,
+    outputs: bool,
+) -> anyhow::Result<usize> {
+    let f = tokio::fs::File::open(file).await?;
+    let mut lines = BufReader::new(f).lines();
+    let mut last_at = None;
+    let mut count: usize = 0;
+    let mut line_no: usize = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_no += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                log::warn!("{}:{} unreadable - skipped {}", file, line_no, e);
+                continue;
+            }
+        };
+        if let Some(chain) = chain {
+            if record.chain != chain {
+                continue;
+            }
+        }
+        let output = OUTPUTS.contains(&record.message_type.as_str());
+        if record.message_type == "MessageStop" || (output && !outputs) {
+            log::debug!("{}:{} {} - skipped", file, line_no, record.message_type);
+            continue;
+        }
+        if let Some(last_at) = last_at {
+            if speed > 0.0 {
+                let gap = (record.at - last_at).to_std().unwrap_or_default();
+                tokio::time::sleep(Duration::from_secs_f64(gap.as_secs_f64() / speed)).await;
+            }
+        }
+        last_at = Some(record.at);
+        let message_type = record.message_type.clone();
+        if let Err(e) = issue(record) {
+            log::warn!("{}:{} {} - skipped {}", file, line_no, message_type, e);
+            continue;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn issue(record: Record) -> anyhow::Result<()> {
+    macro_rules! issue_messages {
+        ($($msg:ident),*) => {
+            match record.message_type.as_str() {
+                $(stringify!($msg) => {
+                    Broker::<SystemBroker>::issue_async(serde_json::from_value::<$msg>(record.message)?)
+                })*
+                other => log::warn!("Unknown message type {} - skipped", other),
+            }
+        };
+    }
+    issue_messages!(
+        MessageValidator,
+        MessageValidatorEvent,
+        MessageValidatorStakedTotal,
+        MessageSendMessageEvent,
+        MessagePriceAbstain,
+        MessagePriceDrift,
+        MessageBlockEventLiveness,
+        MessageBlockEventReward,
+        MessageBlockEventExchangeRate,
+        MessageBlockHeight,
+        MessageTick,
+        MessageNodeDiscovered,
+        MessageNodeGone,
+        MessageNodeIpChanged,
+        MessageIpAsnChanged,
+        MessageIpCountryChanged
+    );
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use actix::prelude::*;
+use actix_broker::{Broker, BrokerSubscribe, SystemBroker};
+use constellation_node_events::{MessageNodeDiscovered, MessageNodeGone, MessageNodeIpChanged};
+use constellation_shared::messages::{MessageSendMessageEvent, MessageValidatorEvent};
+use constellation_shared::MessageStop;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RECORDING: &str = r#"{"at":"2021-11-01T10:00:00Z","chain":"columbus","type":"MessageNodeDiscovered","message":{"node":"a@10.0.0.1:26656","id":"a","ip":"10.0.0.1","source":"lcd"}}
+{"at":"2021-11-01T10:00:01Z","chain":"columbus","type":"MessageSendMessageEvent","message":{}}
+{"at":"2021-11-01T10:00:02Z","chain":"columbus","type":"MessageStop","message":{}}
+{"at":"2021-11-01T10:00:03Z","chain":"bombay","type":"MessageNodeGone","message":{"node":"b@10.0.0.2:26656","id":"b","ip":"10.0.0.2"}}
+not a record
+
+{"at":"2021-11-01T10:00:04Z","chain":"columbus","type":"MessageNodeIpChanged","message":{"id":"a","ip":"10.0.0.3","previous":["10.0.0.1"]}}
+{"at":"2021-11-01T10:00:05Z","chain":"columbus","type":"MessageValidatorEvent","message":{}}
+{"at":"2021-11-01T10:00:06Z","chain":"columbus","type":"MessageNodeGone","message":{"node":"a@10.0.0.3:26656","id":"a","ip":"10.0.0.3"}}
+"#;
+
+/// notes what arrives on the broker, in order
+struct Subscriber(Arc<Mutex<Vec<String>>>);
+
+impl Subscriber {
+    fn saw(&self, what: String) {
+        self.0.lock().unwrap().push(what);
+    }
+}
+
+impl Actor for Subscriber {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_sync::<SystemBroker, MessageNodeDiscovered>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageNodeGone>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageNodeIpChanged>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageStop>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageSendMessageEvent>(ctx);
+        self.subscribe_sync::<SystemBroker, MessageValidatorEvent>(ctx);
+    }
+}
+
+impl Handler<MessageNodeDiscovered> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeDiscovered, _ctx: &mut Self::Context) {
+        self.saw(format!("discovered {}", msg.node));
+    }
+}
+
+impl Handler<MessageNodeGone> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeGone, _ctx: &mut Self::Context) {
+        self.saw(format!("gone {}", msg.node));
+    }
+}
+
+impl Handler<MessageNodeIpChanged> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageNodeIpChanged, _ctx: &mut Self::Context) {
+        self.saw(format!("ip changed {} {}", msg.id, msg.ip));
+    }
+}
+
+impl Handler<MessageStop> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, _msg: MessageStop, _ctx: &mut Self::Context) {
+        self.saw("stop".into());
+    }
+}
+
+impl Handler<MessageSendMessageEvent> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, _msg: MessageSendMessageEvent, _ctx: &mut Self::Context) {
+        self.saw("send message".into());
+    }
+}
+
+impl Handler<MessageValidatorEvent> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, _msg: MessageValidatorEvent, _ctx: &mut Self::Context) {
+        self.saw("validator event".into());
+    }
+}
+
+#[actix_rt::test]
+async fn replays_inputs_in_order() {
+    let file = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+    fs::write(&file, RECORDING).unwrap();
+    let seen = Arc::new(Mutex::new(vec![]));
+    Subscriber(seen.clone()).start();
+    // let it subscribe
+    actix_rt::time::sleep(Duration::from_millis(50)).await;
+
+    let count =
+        constellation_recorder::replay(file.to_str().unwrap(), 0.0, Some("columbus"), false)
+            .await
+            .unwrap();
+    fs::remove_file(&file).unwrap();
+    assert_eq!(count, 3);
+
+    // the broker keeps its order, so once this arrives everything replayed has
+    Broker::<SystemBroker>::issue_async(MessageNodeGone {
+        node: "end".into(),
+        id: "end".into(),
+        ip: "end".into(),
+    });
+    for _ in 0..100 {
+        if seen.lock().unwrap().last().map(|s| s.as_str()) == Some("gone end") {
+            break;
+        }
+        actix_rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "discovered a@10.0.0.1:26656",
+            "ip changed a 10.0.0.3",
+            "gone a@10.0.0.3:26656",
+            "gone end",
+        ]
+    );
+}
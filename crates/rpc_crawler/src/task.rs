@@ -1,31 +1,51 @@
-use constellation_shared::state::AppState;
+use constellation_address_book::CrawledPeers;
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
+use std::sync::PoisonError;
 use std::time::Duration;
 use terra_rust_api::Terra;
+use tokio::sync::watch;
 use tokio::time;
 
+/// ask the RPC node who it is connected to, every period. returns once `shutdown` turns true
 pub async fn run(
-    state: AppState,
+    state: SharedState,
     period: Duration,
     chain_id: String,
     lcd_endpoint: String,
     rpc_endpoint: String,
+    crawled: CrawledPeers,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
 ) {
     log::info!("{} {}", lcd_endpoint, rpc_endpoint);
     let mut interval = time::interval(period);
     loop {
         let terra = Terra::lcd_client_no_tx(&lcd_endpoint, &chain_id);
-        match run_task(&state, &terra, &rpc_endpoint).await {
-            Ok(_) => {}
+        match run_task(&state, &terra, &chain_id, &rpc_endpoint, &crawled).await {
+            Ok(connections) => status.success(connections),
             Err(e) => {
-                log::error!("RPC Crawler: {}", e)
+                log::error!("RPC Crawler: {}", e);
+                status.error(e)
             }
         }
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
     }
 }
-pub async fn run_task(_state: &AppState, terra: &Terra, rpc_endpoint: &str) -> anyhow::Result<()> {
+/// returns the number of connections the RPC node has. its public peers are left in `crawled`,
+/// for the address book to pick up
+pub async fn run_task(
+    _state: &SharedState,
+    terra: &Terra,
+    chain_id: &str,
+    rpc_endpoint: &str,
+    crawled: &CrawledPeers,
+) -> anyhow::Result<usize> {
     let rpc = terra.rpc(rpc_endpoint);
-    match rpc.net_info().await {
+    match constellation_metrics::timed(chain_id, "net_info", rpc.net_info()).await {
         Ok(net_info) => {
             let open_rpc = net_info
                 .peers
@@ -75,12 +95,10 @@ pub async fn run_task(_state: &AppState, terra: &Terra, rpc_endpoint: &str) -> a
             if !open_peer.is_empty() {
                 log::info!("found {} open peers", open_peer.len());
             }
-            log::info!("{} connections", net_info.peers.len())
-        }
-        Err(e) => {
-            log::error!("RPC: {} {}", rpc_endpoint, e);
+            *crawled.lock().unwrap_or_else(PoisonError::into_inner) = open_peer;
+            log::info!("{} connections", net_info.peers.len());
+            Ok(net_info.peers.len())
         }
+        Err(e) => Err(anyhow::anyhow!("RPC: {} {}", rpc_endpoint, e)),
     }
-
-    Ok(())
 }
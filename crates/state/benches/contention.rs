@@ -0,0 +1,165 @@
+use constellation_shared::state::{GeoID, State};
+use constellation_state::SharedState;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// IPs in the state, as on a large chain
+const IPS: usize = 50_000;
+/// web requests being served at once
+const READERS: usize = 4;
+/// IPs a geo-like writer updates per batch
+const BATCH: usize = 500;
+
+/// the old `Arc<Mutex<State>>` and `SharedState`, each with the same readers serialising a large
+/// map and a writer updating it in the background. what is measured is one more read, and one
+/// more batch of writes, made while that goes on.
+/// cargo bench -p constellation-state --bench contention
+fn contention(c: &mut Criterion) {
+    let addrs = (0..IPS)
+        .map(|i| format!("10.{}.{}.{}", (i >> 16) & 0xff, (i >> 8) & 0xff, i & 0xff))
+        .collect::<Vec<_>>();
+    let mut seed = State::new().unwrap();
+    for (i, ip) in addrs.iter().enumerate() {
+        locate(&mut seed, ip, (i % 200) as GeoID);
+    }
+    let batch = addrs[..BATCH].to_vec();
+    let mut group = c.benchmark_group("contention");
+    group.sample_size(20);
+
+    // as the geo task did: lock once per IP
+    {
+        let state = Arc::new(Mutex::new(seed.clone()));
+        let write = |state: &Mutex<State>, batch: &[String], pass: GeoID| {
+            for ip in batch {
+                locate(
+                    &mut state.lock().unwrap_or_else(PoisonError::into_inner),
+                    ip,
+                    pass,
+                );
+            }
+        };
+        let read = |state: &Mutex<State>| {
+            serde_json::to_vec(
+                &state
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .geo_ip_country,
+            )
+            .unwrap()
+            .len()
+        };
+        let _load = {
+            let (w, r, addrs) = (state.clone(), state.clone(), addrs.clone());
+            Load::start(
+                move |pass| {
+                    for batch in addrs.chunks(BATCH) {
+                        write(&w, batch, pass)
+                    }
+                },
+                move || {
+                    read(&r);
+                },
+            )
+        };
+        group.bench_function("mutex/read", |b| b.iter(|| read(&state)));
+        group.bench_function("mutex/write", |b| b.iter(|| write(&state, &batch, 1)));
+    }
+
+    // one update per batch, readers serialise a snapshot
+    {
+        let state = SharedState::new(seed);
+        let write = |state: &SharedState, batch: &[String], pass: GeoID| {
+            state.update(|the_state| {
+                for ip in batch {
+                    locate(the_state, ip, pass);
+                }
+            })
+        };
+        let read = |state: &SharedState| {
+            serde_json::to_vec(&state.snapshot().geo_ip_country)
+                .unwrap()
+                .len()
+        };
+        let _load = {
+            let (w, r) = (state.clone(), state.clone());
+            Load::start(
+                move |pass| {
+                    for batch in addrs.chunks(BATCH) {
+                        write(&w, batch, pass)
+                    }
+                },
+                move || {
+                    read(&r);
+                },
+            )
+        };
+        group.bench_function("shared/read", |b| b.iter(|| read(&state)));
+        group.bench_function("shared/write", |b| b.iter(|| write(&state, &batch, 1)));
+    }
+    group.finish();
+}
+
+/// the index updates geo makes for one IP
+fn locate(state: &mut State, ip: &str, country: GeoID) {
+    if let Some(old) = state.geo_ip_country.insert(ip.to_string(), country) {
+        if let Some(ips) = state.geo_country_ip.get_mut(&old) {
+            ips.remove(ip);
+        }
+    }
+    state
+        .geo_country_ip
+        .entry(country)
+        .or_default()
+        .insert(ip.to_string());
+    state.new_ips_geo.remove(ip);
+}
+
+/// `READERS` threads reading, and one writing a pass over every IP at a time, until dropped
+struct Load {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Load {
+    fn start<W, R>(write: W, read: R) -> Load
+    where
+        W: Fn(GeoID) + Send + 'static,
+        R: Fn() + Send + Sync + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let read = Arc::new(read);
+        let mut threads = (0..READERS)
+            .map(|_| {
+                let (stop, read) = (stop.clone(), read.clone());
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        read();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let writer_stop = stop.clone();
+        threads.push(thread::spawn(move || {
+            let mut pass: GeoID = 0;
+            while !writer_stop.load(Ordering::Relaxed) {
+                write(pass);
+                pass = (pass + 1) % 200;
+            }
+        }));
+        Load { stop, threads }
+    }
+}
+
+impl Drop for Load {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// replace `file` with `bytes`, so that a crash part way through leaves either the old
+/// contents or the new ones, never a mix of the two
+pub fn write_atomic(file: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    write_atomic_with(file, bytes, || Ok(()))
+}
+
+/// as `write_atomic`, calling `before_rename` once the new contents are safely on disk but have
+/// yet to replace the old ones (eg. to move the old file aside). if it fails, `file` is left as
+/// it was
+pub fn write_atomic_with<F>(file: &str, bytes: &[u8], before_rename: F) -> anyhow::Result<()>
+where
+    F: FnOnce() -> anyhow::Result<()>,
+{
+    let tmp_file = format!("{}.tmp", file);
+    {
+        let mut f = File::create(&tmp_file)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+    }
+    before_rename()?;
+    fs::rename(&tmp_file, file)?;
+    sync_dir(file);
+    Ok(())
+}
+
+/// make the rename itself durable. not all platforms let a directory be opened, so this is best effort
+fn sync_dir(file: &str) {
+    let dir = match Path::new(file).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(d) = OpenOptions::new().read(true).open(dir) {
+        let _ = d.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_and_leaves_no_temp_file() {
+        let file = std::env::temp_dir()
+            .join(format!("constellation-write-atomic-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        write_atomic(&file, b"old").unwrap();
+        write_atomic(&file, b"new").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+        assert!(!Path::new(&format!("{}.tmp", file)).exists());
+
+        // a failure before the rename keeps the old contents
+        assert!(write_atomic_with(&file, b"newer", || anyhow::bail!("no room")).is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+        let _ = fs::remove_file(format!("{}.tmp", file));
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,11 @@
+mod file;
+mod prune;
+mod seen;
+mod shared;
+mod task;
+
+pub use file::{write_atomic, write_atomic_with};
+pub use prune::{prune, Compaction};
+pub use seen::{AppSightings, Seen, Sightings};
+pub use shared::SharedState;
+pub use task::run;
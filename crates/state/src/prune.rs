@@ -0,0 +1,516 @@
+use crate::seen::Sightings;
+use chrono::{DateTime, Duration, Utc};
+use constellation_shared::state::{GeoID, State};
+use constellation_store::{Batch, Table};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+
+/// what a prune removed
+#[derive(Debug, Default)]
+pub struct Compaction {
+    pub nodes: usize,
+    pub ips: usize,
+    /// reverse index entries (eg. a country's IP list) left empty, and so dropped
+    pub index_entries: usize,
+    /// ASNs, cities, countries & continents no remaining IP refers to
+    pub unreferenced: usize,
+    /// nodes & IPs still being kept
+    pub nodes_kept: usize,
+    pub ips_kept: usize,
+}
+
+impl Compaction {
+    pub fn is_empty(&self) -> bool {
+        self.nodes == 0 && self.ips == 0 && self.index_entries == 0 && self.unreferenced == 0
+    }
+}
+
+impl fmt::Display for Compaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed {} nodes, {} IPs, {} empty index entries and {} unreferenced ASNs/places. {} nodes and {} IPs kept",
+            self.nodes,
+            self.ips,
+            self.index_entries,
+            self.unreferenced,
+            self.nodes_kept,
+            self.ips_kept
+        )
+    }
+}
+
+/// drop every node & IP that hasn't been seen within `retention`, from the state and all of its
+/// reverse indexes. an IP is only dropped once no remaining node uses it, and an ASN or place
+/// once no remaining IP does. the matching store changes are added to `batch`
+pub fn prune(
+    state: &mut State,
+    sightings: &mut Sightings,
+    retention: Duration,
+    now: DateTime<Utc>,
+    batch: &mut Batch,
+) -> Compaction {
+    let cutoff = now - retention;
+    let mut compaction = Compaction::default();
+
+    let stale_nodes: HashSet<String> = sightings
+        .nodes
+        .iter()
+        .filter(|(_, seen)| seen.last_seen < cutoff)
+        .map(|(node, _)| node.clone())
+        .collect();
+    compaction.nodes = prune_keys(&mut state.nodes, Table::Nodes, &stale_nodes, batch);
+    prune_members(&mut state.new_nodes, Table::NewNodes, &stale_nodes, batch);
+    compaction.index_entries += prune_sets(
+        &mut state.id_ip_addr,
+        Table::IdIpAddr,
+        |addr| stale_nodes.contains(&addr.to_string()),
+        batch,
+    );
+    compaction.index_entries += prune_sets(
+        &mut state.ip_ip_addr,
+        Table::IpIpAddr,
+        |addr| stale_nodes.contains(&addr.to_string()),
+        batch,
+    );
+    sightings
+        .nodes
+        .retain(|node, _| !stale_nodes.contains(node));
+
+    let stale_ips: HashSet<String> = sightings
+        .ips
+        .iter()
+        .filter(|(ip, seen)| seen.last_seen < cutoff && !state.ip_ip_addr.contains_key(*ip))
+        .map(|(ip, _)| ip.clone())
+        .collect();
+    prune_members(&mut state.new_ips_bgp, Table::NewIpsBgp, &stale_ips, batch);
+    prune_members(&mut state.new_ips_geo, Table::NewIpsGeo, &stale_ips, batch);
+    compaction.ips = stale_ips.len();
+    prune_keys(&mut state.ip_asn, Table::IpAsn, &stale_ips, batch);
+    prune_keys(&mut state.geo_ip_city, Table::GeoIpCity, &stale_ips, batch);
+    prune_keys(
+        &mut state.geo_ip_country,
+        Table::GeoIpCountry,
+        &stale_ips,
+        batch,
+    );
+    prune_keys(
+        &mut state.geo_ip_continent,
+        Table::GeoIpContinent,
+        &stale_ips,
+        batch,
+    );
+    let gone = |ip: &String| stale_ips.contains(ip);
+    compaction.index_entries += prune_sets(&mut state.asn_ip, Table::AsnIp, gone, batch);
+    compaction.index_entries += prune_sets(&mut state.geo_city_ip, Table::GeoCityIp, gone, batch);
+    compaction.index_entries +=
+        prune_sets(&mut state.geo_country_ip, Table::GeoCountryIp, gone, batch);
+    compaction.index_entries += prune_sets(
+        &mut state.geo_continent_ip,
+        Table::GeoContinentIp,
+        gone,
+        batch,
+    );
+    sightings.ips.retain(|ip, _| !stale_ips.contains(ip));
+    compaction.unreferenced = prune_unreferenced(state, batch);
+
+    compaction.nodes_kept = state.nodes.len();
+    compaction.ips_kept = sightings.ips.len();
+    compaction
+}
+
+/// drop the ASNs & places nothing refers to any more. cities go first, as they refer to
+/// their country & continent. returns how many were dropped
+fn prune_unreferenced(state: &mut State, batch: &mut Batch) -> usize {
+    let asns: HashSet<String> = state
+        .ip_asn
+        .values()
+        .map(|mapping| mapping.asn.clone())
+        .chain(state.asn_ip.keys().cloned())
+        .collect();
+    let mut dropped =
+        prune_keys_where(&mut state.asn, Table::Asn, |asn| !asns.contains(asn), batch);
+
+    let cities: HashSet<GeoID> = state
+        .geo_ip_city
+        .values()
+        .chain(state.geo_city_ip.keys())
+        .copied()
+        .collect();
+    dropped += prune_keys_where(
+        &mut state.geo_city,
+        Table::GeoCity,
+        |id| !cities.contains(id),
+        batch,
+    );
+
+    let countries: HashSet<GeoID> = state
+        .geo_ip_country
+        .values()
+        .chain(state.geo_country_ip.keys())
+        .copied()
+        .chain(state.geo_city.values().map(|city| city.country))
+        .collect();
+    dropped += prune_keys_where(
+        &mut state.geo_country,
+        Table::GeoCountry,
+        |id| !countries.contains(id),
+        batch,
+    );
+
+    let continents: HashSet<GeoID> = state
+        .geo_ip_continent
+        .values()
+        .chain(state.geo_continent_ip.keys())
+        .copied()
+        .chain(state.geo_city.values().map(|city| city.continent))
+        .collect();
+    dropped += prune_keys_where(
+        &mut state.geo_continent,
+        Table::GeoContinent,
+        |id| !continents.contains(id),
+        batch,
+    );
+    dropped
+}
+
+fn prune_keys_where<K, V, F>(
+    map: &mut HashMap<K, V>,
+    table: Table,
+    gone: F,
+    batch: &mut Batch,
+) -> usize
+where
+    K: ToString + Eq + Hash,
+    F: Fn(&K) -> bool,
+{
+    let before = map.len();
+    map.retain(|key, _| {
+        if gone(key) {
+            batch.remove(table, &key.to_string());
+            false
+        } else {
+            true
+        }
+    });
+    before - map.len()
+}
+
+fn prune_keys<V>(
+    map: &mut HashMap<String, V>,
+    table: Table,
+    gone: &HashSet<String>,
+    batch: &mut Batch,
+) -> usize {
+    prune_keys_where(map, table, |key| gone.contains(key), batch)
+}
+
+fn prune_members(
+    set: &mut HashSet<String>,
+    table: Table,
+    gone: &HashSet<String>,
+    batch: &mut Batch,
+) {
+    set.retain(|member| {
+        if gone.contains(member) {
+            batch.remove(table, member);
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// take the gone values out of each set in a reverse index, dropping any set left empty.
+/// returns how many were dropped
+fn prune_sets<K, V, F>(
+    index: &mut HashMap<K, HashSet<V>>,
+    table: Table,
+    gone: F,
+    batch: &mut Batch,
+) -> usize
+where
+    K: ToString + Eq + Hash,
+    V: Serialize + Eq + Hash,
+    F: Fn(&V) -> bool,
+{
+    let before = index.len();
+    index.retain(|key, set| {
+        let members = set.len();
+        set.retain(|v| !gone(v));
+        if set.is_empty() {
+            batch.remove(table, &key.to_string());
+            false
+        } else {
+            if set.len() != members {
+                batch.put(table, &key.to_string(), set);
+            }
+            true
+        }
+    });
+    before - index.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seen::Seen;
+    use constellation_store::{SledStore, StateStore};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    const WHEN: &str = "2021-11-02T10:15:00Z";
+
+    /// an address book entry, as tendermint writes them
+    fn node(id: &str, ip: &str) -> Value {
+        let addr = json!({ "id": id, "ip": ip, "port": 26656 });
+        json!({
+            "addr": addr,
+            "src": addr,
+            "buckets": [],
+            "attempts": 0,
+            "bucket_type": 1,
+            "last_attempt": WHEN,
+            "last_success": WHEN,
+            "last_ban_time": WHEN,
+        })
+    }
+
+    /// nodes a & c (on 10.0.0.1 & 10.0.0.3) have gone quiet. b (10.0.0.2) and d (10.0.0.3) haven't.
+    /// 10.0.0.1 is in London, on AS1. 10.0.0.2 is in France, on AS2
+    fn state() -> State {
+        let mut state = serde_json::to_value(State::new().unwrap()).unwrap();
+        let nodes = [
+            ("a", "10.0.0.1"),
+            ("b", "10.0.0.2"),
+            ("c", "10.0.0.3"),
+            ("d", "10.0.0.3"),
+        ];
+        let mut by_id = serde_json::Map::new();
+        let mut by_ip = serde_json::Map::new();
+        for (id, ip) in nodes.iter() {
+            let entry = node(id, ip);
+            state["nodes"][format!("{}@{}:26656", id, ip)] = entry.clone();
+            by_id.insert(id.to_string(), json!([entry["addr"]]));
+            by_ip
+                .entry(ip.to_string())
+                .or_insert_with(|| json!([]))
+                .as_array_mut()
+                .unwrap()
+                .push(entry["addr"].clone());
+        }
+        state["id_ip_addr"] = Value::Object(by_id);
+        state["ip_ip_addr"] = Value::Object(by_ip);
+        state["new_nodes"] = json!(["a@10.0.0.1:26656"]);
+        state["new_ips_bgp"] = json!(["10.0.0.1"]);
+        state["new_ips_geo"] = json!(["10.0.0.1", "10.0.0.2"]);
+        let asn = |asn: &str| json!({ "asn": asn, "country": "", "net": "", "desc": asn, "last_updated": WHEN });
+        let mapping = |asn: &str| json!({ "asn": asn, "range": "10.0.0.0/8", "country": "", "network": "", "last_updated": WHEN });
+        state["asn"] = json!({ "1": asn("1"), "2": asn("2") });
+        state["ip_asn"] = json!({ "10.0.0.1": mapping("1"), "10.0.0.2": mapping("2") });
+        state["asn_ip"] = json!({ "1": ["10.0.0.1"], "2": ["10.0.0.2"] });
+        state["geo_city"] = json!({ "2643743": {
+            "geoname_id": 2643743, "name": "London", "country": 2635167, "continent": 6255148,
+            "last_updated": WHEN,
+        }});
+        let country = |id: u64, name: &str, iso: &str| {
+            json!({ "geoname_id": id, "name": name, "is_in_european_union": null, "iso_code": iso,
+                "last_updated": WHEN })
+        };
+        state["geo_country"] = json!({
+            "2635167": country(2635167, "United Kingdom", "GB"),
+            "3017382": country(3017382, "France", "FR"),
+        });
+        state["geo_continent"] = json!({ "6255148": {
+            "geoname_id": 6255148, "name": "Europe", "code": "EU", "last_updated": WHEN,
+        }});
+        state["geo_ip_city"] = json!({ "10.0.0.1": 2643743 });
+        state["geo_ip_country"] = json!({ "10.0.0.1": 2635167, "10.0.0.2": 3017382 });
+        state["geo_ip_continent"] = json!({ "10.0.0.1": 6255148, "10.0.0.2": 6255148 });
+        state["geo_city_ip"] = json!({ "2643743": ["10.0.0.1"] });
+        state["geo_country_ip"] = json!({ "2635167": ["10.0.0.1"], "3017382": ["10.0.0.2"] });
+        state["geo_continent_ip"] = json!({ "6255148": ["10.0.0.1", "10.0.0.2"] });
+        serde_json::from_value(state).unwrap()
+    }
+
+    fn sightings(now: DateTime<Utc>) -> Sightings {
+        let seen = |days| Seen {
+            first_seen: now - Duration::days(30),
+            last_seen: now - Duration::days(days),
+            sources: BTreeMap::new(),
+        };
+        let mut sightings = Sightings::default();
+        for (node, days) in [
+            ("a@10.0.0.1:26656", 10),
+            ("b@10.0.0.2:26656", 1),
+            ("c@10.0.0.3:26656", 10),
+            ("d@10.0.0.3:26656", 1),
+        ]
+        .iter()
+        {
+            sightings.nodes.insert(node.to_string(), seen(*days));
+        }
+        for (ip, days) in [("10.0.0.1", 10), ("10.0.0.2", 1), ("10.0.0.3", 10)].iter() {
+            sightings.ips.insert(ip.to_string(), seen(*days));
+        }
+        sightings
+    }
+
+    /// everything an index or mapping refers to is still there
+    fn assert_nothing_dangles(state: &State, sightings: &Sightings) {
+        for node in state.nodes.keys() {
+            assert!(sightings.nodes.contains_key(node), "{} unsighted", node);
+        }
+        for node in &state.new_nodes {
+            assert!(state.nodes.contains_key(node), "new node {} gone", node);
+        }
+        for addrs in state.id_ip_addr.values().chain(state.ip_ip_addr.values()) {
+            assert!(!addrs.is_empty());
+            for addr in addrs {
+                assert!(state.nodes.contains_key(&addr.to_string()), "{} gone", addr);
+            }
+        }
+        let ips = |set: &HashSet<String>| {
+            assert!(!set.is_empty());
+            for ip in set {
+                assert!(sightings.ips.contains_key(ip), "{} unsighted", ip);
+            }
+        };
+        state.asn_ip.values().for_each(ips);
+        state.geo_city_ip.values().for_each(ips);
+        state.geo_country_ip.values().for_each(ips);
+        state.geo_continent_ip.values().for_each(ips);
+        ips(&state
+            .new_ips_bgp
+            .union(&state.new_ips_geo)
+            .cloned()
+            .collect());
+        for (ip, mapping) in &state.ip_asn {
+            assert!(sightings.ips.contains_key(ip), "{} unsighted", ip);
+            assert!(
+                state.asn.contains_key(&mapping.asn),
+                "AS{} gone",
+                mapping.asn
+            );
+        }
+        for id in state.geo_ip_city.values() {
+            assert!(state.geo_city.contains_key(id), "city {} gone", id);
+        }
+        for id in state.geo_ip_country.values() {
+            assert!(state.geo_country.contains_key(id), "country {} gone", id);
+        }
+        for id in state.geo_ip_continent.values() {
+            assert!(
+                state.geo_continent.contains_key(id),
+                "continent {} gone",
+                id
+            );
+        }
+    }
+
+    fn keys<K: ToString, V>(map: &HashMap<K, V>) -> Vec<String> {
+        let mut keys = map.keys().map(|k| k.to_string()).collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn prunes_without_leaving_anything_dangling() {
+        let now = Utc::now();
+        let mut state = state();
+        let mut sightings = sightings(now);
+        assert_nothing_dangles(&state, &sightings);
+
+        let mut batch = Batch::default();
+        let compaction = prune(
+            &mut state,
+            &mut sightings,
+            Duration::days(7),
+            now,
+            &mut batch,
+        );
+        assert_nothing_dangles(&state, &sightings);
+
+        assert_eq!(compaction.nodes, 2);
+        assert_eq!(compaction.ips, 1);
+        // id_ip_addr a & c, ip_ip_addr 10.0.0.1, AS1's IPs, London's IPs and the UK's IPs
+        assert_eq!(compaction.index_entries, 6);
+        // AS1, London & the UK
+        assert_eq!(compaction.unreferenced, 3);
+        assert_eq!(compaction.nodes_kept, 2);
+        assert_eq!(compaction.ips_kept, 2);
+
+        assert_eq!(
+            keys(&state.nodes),
+            vec!["b@10.0.0.2:26656", "d@10.0.0.3:26656"]
+        );
+        // 10.0.0.3 is kept for d, though it was last sighted as long ago as c
+        assert_eq!(keys(&state.ip_ip_addr), vec!["10.0.0.2", "10.0.0.3"]);
+        assert_eq!(keys(&sightings.ips), vec!["10.0.0.2", "10.0.0.3"]);
+        assert!(state.new_nodes.is_empty());
+        assert!(state.new_ips_bgp.is_empty());
+        assert_eq!(keys(&state.asn), vec!["2"]);
+        assert!(state.geo_city.is_empty());
+        assert_eq!(keys(&state.geo_country), vec!["3017382"]);
+        // still in Europe through France
+        assert_eq!(keys(&state.geo_continent), vec!["6255148"]);
+        assert_eq!(
+            state.geo_continent_ip[&6255148],
+            vec!["10.0.0.2".to_string()].into_iter().collect()
+        );
+
+        // nothing more to do
+        let mut batch = Batch::default();
+        let again = prune(
+            &mut state,
+            &mut sightings,
+            Duration::days(7),
+            now,
+            &mut batch,
+        );
+        assert!(again.is_empty(), "{}", again);
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn prunes_the_store_the_same_as_the_state() {
+        let dir = std::env::temp_dir().join(format!("constellation-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = SledStore::open(dir.to_str().unwrap()).unwrap();
+        let now = Utc::now();
+        let mut state = state();
+        let mut sightings = sightings(now);
+        let store: &dyn StateStore = &store;
+        store.import(&state).unwrap();
+
+        let mut batch = Batch::default();
+        prune(
+            &mut state,
+            &mut sightings,
+            Duration::days(7),
+            now,
+            &mut batch,
+        );
+        store.apply(batch).unwrap();
+
+        let stored = store.load().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(keys(&stored.nodes), keys(&state.nodes));
+        assert_eq!(keys(&stored.id_ip_addr), keys(&state.id_ip_addr));
+        assert_eq!(stored.ip_ip_addr, state.ip_ip_addr);
+        assert_eq!(stored.new_nodes, state.new_nodes);
+        assert_eq!(stored.new_ips_bgp, state.new_ips_bgp);
+        assert_eq!(stored.new_ips_geo, state.new_ips_geo);
+        assert_eq!(keys(&stored.asn), keys(&state.asn));
+        assert_eq!(keys(&stored.ip_asn), keys(&state.ip_asn));
+        assert_eq!(stored.asn_ip, state.asn_ip);
+        assert_eq!(keys(&stored.geo_city), keys(&state.geo_city));
+        assert_eq!(keys(&stored.geo_country), keys(&state.geo_country));
+        assert_eq!(keys(&stored.geo_continent), keys(&state.geo_continent));
+        assert_eq!(stored.geo_ip_country, state.geo_ip_country);
+        assert_eq!(stored.geo_country_ip, state.geo_country_ip);
+        assert_eq!(stored.geo_continent_ip, state.geo_continent_ip);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::file::write_atomic;
+use chrono::{DateTime, Utc};
+use constellation_shared::state::State;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub type AppSightings = Arc<Mutex<Sightings>>;
+
+/// when something was first & last in the address book
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Seen {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// for nodes, the address book sources that reported it and when each last did
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Seen {
+    fn new(now: DateTime<Utc>) -> Seen {
+        Seen {
+            first_seen: now,
+            last_seen: now,
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+/// first/last seen for every node (keyed as in `State::nodes`) and IP.
+/// kept next to the state, as the shared `State` has nowhere to put it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sightings {
+    pub nodes: HashMap<String, Seen>,
+    pub ips: HashMap<String, Seen>,
+}
+
+impl Sightings {
+    pub fn saw_node(&mut self, node: &str, ip: &str, source: &str, now: DateTime<Utc>) {
+        let seen = self
+            .nodes
+            .entry(node.to_string())
+            .or_insert_with(|| Seen::new(now));
+        seen.last_seen = now;
+        seen.sources.insert(source.to_string(), now);
+        self.ips
+            .entry(ip.to_string())
+            .or_insert_with(|| Seen::new(now))
+            .last_seen = now;
+    }
+
+    /// anything in the state that has never been sighted (eg. from before sightings were kept)
+    /// is treated as seen now, so it gets a full retention period. returns how many were added
+    pub fn backfill(&mut self, state: &State, now: DateTime<Utc>) -> usize {
+        let mut added = 0;
+        for node in state.nodes.keys() {
+            if !self.nodes.contains_key(node) {
+                self.nodes.insert(node.clone(), Seen::new(now));
+                added += 1;
+            }
+        }
+        for ip in state.ip_ip_addr.keys() {
+            if !self.ips.contains_key(ip) {
+                self.ips.insert(ip.clone(), Seen::new(now));
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// a missing file is an empty set of sightings
+    pub fn load(file: &str) -> anyhow::Result<Sightings> {
+        if !Path::new(file).exists() {
+            return Ok(Sightings::default());
+        }
+        Ok(serde_json::from_reader(File::open(file)?)?)
+    }
+
+    /// saved alongside each checkpoint of the state
+    pub fn save(&self, file: &str) -> anyhow::Result<()> {
+        write_atomic(file, &serde_json::to_vec(self)?)
+    }
+}
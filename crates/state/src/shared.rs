@@ -0,0 +1,44 @@
+use constellation_shared::state::State;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// a chain's state, shared by the tasks that fill it in and everything that reads it.
+/// readers take a snapshot (an immutable copy) and never wait on a writer. a writer changes a
+/// copy of the latest snapshot and publishes it when done, so each update costs a copy of the
+/// state, and writers should batch their changes. a writer that panics publishes nothing
+#[derive(Clone)]
+pub struct SharedState {
+    current: Arc<RwLock<Arc<State>>>,
+    /// writers take turns, so none of them loses another's changes
+    writer: Arc<Mutex<()>>,
+}
+
+impl SharedState {
+    pub fn new(state: State) -> SharedState {
+        SharedState {
+            current: Arc::new(RwLock::new(Arc::new(state))),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// the latest published state. it doesn't change underneath the caller
+    pub fn snapshot(&self) -> Arc<State> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// change the state, and publish the result. readers carry on with the previous snapshot until `f` returns
+    pub fn update<R, F: FnOnce(&mut State) -> R>(&self, f: F) -> R {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut next = self.snapshot();
+        let result = f(Arc::make_mut(&mut next));
+        let previous = {
+            let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+            std::mem::replace(&mut *current, next)
+        };
+        // freeing a large state can take a while, so it happens outside the lock
+        drop(previous);
+        result
+    }
+}
@@ -0,0 +1,50 @@
+use crate::prune::prune;
+use crate::seen::AppSightings;
+use crate::shared::SharedState;
+use chrono::Utc;
+use constellation_status::ModuleReporter;
+use constellation_store::{Batch, Store};
+use std::sync::PoisonError;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time;
+
+/// every period, drop whatever hasn't been seen in `retention`. returns once `shutdown` turns true
+pub async fn run(
+    state: SharedState,
+    seen: AppSightings,
+    period: Duration,
+    retention: chrono::Duration,
+    store: Option<Store>,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        let mut batch = Batch::default();
+        let compaction = state.update(|the_state| {
+            let mut sightings = seen.lock().unwrap_or_else(PoisonError::into_inner);
+            prune(the_state, &mut sightings, retention, Utc::now(), &mut batch)
+        });
+        if compaction.is_empty() {
+            log::info!(
+                "Compaction: nothing older than {} days",
+                retention.num_days()
+            );
+        } else {
+            log::info!("Compaction: {}", compaction);
+        }
+        if let Some(store) = &store {
+            if let Err(e) = store.apply(batch) {
+                log::error!("Unable to store compaction {}", e);
+                status.error(format!("store: {}", e));
+                continue;
+            }
+        }
+        status.success(compaction.nodes + compaction.ips);
+    }
+}
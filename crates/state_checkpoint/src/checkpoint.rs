@@ -0,0 +1,303 @@
+use crate::errors::CheckpointError;
+use crate::migrate::STATE;
+use constellation_migrate::Report;
+use constellation_shared::state::{State, StateVersion};
+use constellation_state::write_atomic_with;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// first line of a checkpoint. followed by the crc32 & length of the JSON after it
+const HEADER: &str = "constellation-checkpoint-v1";
+
+/// `state.json` is generation 0, `state.json.1` the one before it, and so on
+pub fn generation_file(checkpoint_file: &str, generation: u32) -> String {
+    if generation == 0 {
+        checkpoint_file.to_string()
+    } else {
+        format!("{}.{}", checkpoint_file, generation)
+    }
+}
+
+/// serialises the same as `StateVersion`, without needing a copy of the state to wrap
+#[derive(Serialize)]
+enum StateVersionRef<'a> {
+    StateVersion1(&'a State),
+}
+
+/// write the state out, shuffling the older generations along only once it is safely on disk.
+/// at most `generations` files are kept
+pub fn write(state: &State, checkpoint_file: &str, generations: u32) -> anyhow::Result<()> {
+    let json = serde_json::to_vec(&StateVersionRef::StateVersion1(state))?;
+    let mut bytes =
+        format!("{} {:08x} {}\n", HEADER, crc32fast::hash(&json), json.len()).into_bytes();
+    bytes.extend_from_slice(&json);
+    write_atomic_with(checkpoint_file, &bytes, || {
+        for generation in (1..generations.max(1)).rev() {
+            let older = generation_file(checkpoint_file, generation - 1);
+            if Path::new(&older).exists() {
+                fs::rename(&older, generation_file(checkpoint_file, generation))?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// read a single checkpoint file, checking it hasn't been cut short or mangled,
+/// and migrating it from older versions
+pub fn read(file: &str) -> Result<State, CheckpointError> {
+    let (state, report) = parse(file)?;
+    if !report.is_current() {
+        log::warn!("Checkpoint {}: {}", file, report);
+    }
+    Ok(state)
+}
+
+/// what reading the checkpoint would migrate, without writing anything back
+pub fn check(file: &str) -> Result<Report, CheckpointError> {
+    parse(file).map(|(_, report)| report)
+}
+
+fn parse(file: &str) -> Result<(State, Report), CheckpointError> {
+    let json = payload(file)?;
+    let value =
+        serde_json::from_slice(&json).map_err(|e| CheckpointError::Corrupt(file.into(), e))?;
+    let (value, report) = STATE
+        .migrate(value)
+        .map_err(|e| CheckpointError::Migrate(file.into(), e.to_string()))?;
+    match serde_json::from_value(value) {
+        Ok(StateVersion::StateVersion1(state)) => Ok((state, report)),
+        Err(e) => Err(CheckpointError::Corrupt(file.into(), e)),
+    }
+}
+
+/// the JSON in a checkpoint. files from before checksums were added are taken as they are
+fn payload(file: &str) -> Result<Vec<u8>, CheckpointError> {
+    let bytes = fs::read(file).map_err(|e| CheckpointError::Unreadable(file.into(), e))?;
+    if !bytes.starts_with(HEADER.as_bytes()) {
+        log::warn!("Checkpoint {} has no checksum, reading it as is", file);
+        return Ok(bytes);
+    }
+    let newline = bytes
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(|| CheckpointError::BadHeader(file.into()))?;
+    let header = String::from_utf8_lossy(&bytes[..newline]);
+    let (crc, len) =
+        parse_header(&header).ok_or_else(|| CheckpointError::BadHeader(file.into()))?;
+    let json = &bytes[newline + 1..];
+    if json.len() != len {
+        return Err(CheckpointError::Truncated(file.into(), json.len(), len));
+    }
+    if crc32fast::hash(json) != crc {
+        return Err(CheckpointError::ChecksumMismatch(file.into()));
+    }
+    Ok(json.to_vec())
+}
+
+/// restore the newest generation that reads back cleanly
+pub fn restore(checkpoint_file: &str, generations: u32) -> Result<State, CheckpointError> {
+    let mut found = false;
+    for generation in 0..generations.max(1) {
+        let file = generation_file(checkpoint_file, generation);
+        if !Path::new(&file).exists() {
+            continue;
+        }
+        found = true;
+        match read(&file) {
+            Ok(state) => {
+                if generation == 0 {
+                    log::info!("Restored state from checkpoint {}", file);
+                } else {
+                    log::warn!(
+                        "!!! Restored state from OLDER checkpoint {} (generation {}). newer ones were unusable",
+                        file,
+                        generation
+                    );
+                }
+                return Ok(state);
+            }
+            Err(e) => log::error!("!!! Checkpoint unusable: {}", e),
+        }
+    }
+    if found {
+        Err(CheckpointError::NoValidGeneration(checkpoint_file.into()))
+    } else {
+        Err(CheckpointError::NotFound(checkpoint_file.into()))
+    }
+}
+
+fn parse_header(header: &str) -> Option<(u32, usize)> {
+    let mut parts = header.split_whitespace();
+    if parts.next() != Some(HEADER) {
+        return None;
+    }
+    let crc = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let len = parts.next()?.parse().ok()?;
+    Some((crc, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// a directory of its own for each test, removed again when dropped
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Dir {
+            let dir = std::env::temp_dir().join(format!(
+                "constellation-checkpoint-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Dir(dir)
+        }
+
+        fn file(&self) -> String {
+            self.0.join("state.json").to_string_lossy().to_string()
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut files = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn state(ip: &str) -> State {
+        let mut state = State::new().unwrap();
+        state.new_ips_bgp.insert(ip.into());
+        state
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let dir = Dir::new("read");
+        let written = state("10.0.0.1");
+        write(&written, &dir.file(), 3).unwrap();
+        assert_eq!(
+            payload(&dir.file()).unwrap(),
+            serde_json::to_vec(&StateVersionRef::StateVersion1(&written)).unwrap()
+        );
+        assert!(read(&dir.file()).unwrap().new_ips_bgp.contains("10.0.0.1"));
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let dir = Dir::new("truncated");
+        write(&state("10.0.0.1"), &dir.file(), 3).unwrap();
+        let bytes = fs::read(dir.file()).unwrap();
+        fs::write(dir.file(), &bytes[..bytes.len() - 5]).unwrap();
+        match payload(&dir.file()) {
+            Err(CheckpointError::Truncated(_, got, expected)) => assert_eq!(got + 5, expected),
+            other => panic!("expected Truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_a_checksum_mismatch() {
+        let dir = Dir::new("crc");
+        write(&state("10.0.0.1"), &dir.file(), 3).unwrap();
+        let text = fs::read_to_string(dir.file()).unwrap();
+        fs::write(dir.file(), text.replace("10.0.0.1", "10.0.0.9")).unwrap();
+        assert!(matches!(
+            payload(&dir.file()),
+            Err(CheckpointError::ChecksumMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_a_bad_header() {
+        let dir = Dir::new("header");
+        for bad in [
+            format!("{} zz 2\n{{}}", HEADER),
+            format!("{} 00000000\n{{}}", HEADER),
+            format!("{} 00000000 2", HEADER),
+            format!("{}-v9 00000000 2\n{{}}", HEADER),
+        ]
+        .iter()
+        {
+            fs::write(dir.file(), bad).unwrap();
+            assert!(
+                matches!(payload(&dir.file()), Err(CheckpointError::BadHeader(_))),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn takes_a_file_without_a_header_as_it_is() {
+        let dir = Dir::new("legacy");
+        fs::write(dir.file(), b"{\"legacy\":true}").unwrap();
+        assert_eq!(payload(&dir.file()).unwrap(), b"{\"legacy\":true}".to_vec());
+    }
+
+    #[test]
+    fn restores_the_previous_generation_when_the_newest_is_corrupt() {
+        let dir = Dir::new("restore");
+        write(&state("10.0.0.1"), &dir.file(), 3).unwrap();
+        write(&state("10.0.0.2"), &dir.file(), 3).unwrap();
+        assert!(restore(&dir.file(), 3)
+            .unwrap()
+            .new_ips_bgp
+            .contains("10.0.0.2"));
+
+        let bytes = fs::read(dir.file()).unwrap();
+        fs::write(dir.file(), &bytes[..bytes.len() / 2]).unwrap();
+        let restored = restore(&dir.file(), 3).unwrap();
+        assert!(restored.new_ips_bgp.contains("10.0.0.1"));
+        assert!(!restored.new_ips_bgp.contains("10.0.0.2"));
+
+        fs::write(generation_file(&dir.file(), 1), b"").unwrap();
+        assert!(matches!(
+            restore(&dir.file(), 3),
+            Err(CheckpointError::NoValidGeneration(_))
+        ));
+    }
+
+    #[test]
+    fn restores_nothing_when_there_is_no_checkpoint() {
+        let dir = Dir::new("missing");
+        assert!(matches!(
+            restore(&dir.file(), 3),
+            Err(CheckpointError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn keeps_exactly_the_configured_generations() {
+        let dir = Dir::new("rotate");
+        for i in 1..=5 {
+            write(&state(&format!("10.0.0.{}", i)), &dir.file(), 3).unwrap();
+        }
+        assert_eq!(
+            dir.files(),
+            vec!["state.json", "state.json.1", "state.json.2"]
+        );
+        for (generation, ip) in [(0, "10.0.0.5"), (1, "10.0.0.4"), (2, "10.0.0.3")].iter() {
+            let file = generation_file(&dir.file(), *generation);
+            assert!(read(&file).unwrap().new_ips_bgp.contains(*ip), "{}", file);
+        }
+
+        // a single generation is just the file itself
+        let dir = Dir::new("single");
+        for i in 1..=3 {
+            write(&state(&format!("10.0.0.{}", i)), &dir.file(), 1).unwrap();
+        }
+        assert_eq!(dir.files(), vec!["state.json"]);
+    }
+}
@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Checkpoint {0} not found")]
+    NotFound(String),
+    #[error("Checkpoint {0} unable to be read {1}")]
+    Unreadable(String, std::io::Error),
+    #[error("Checkpoint {0} has a malformed header")]
+    BadHeader(String),
+    #[error("Checkpoint {0} is truncated. {1} bytes of {2}")]
+    Truncated(String, usize, usize),
+    #[error("Checkpoint {0} failed its checksum")]
+    ChecksumMismatch(String),
+    #[error("Checkpoint {0} is not valid state {1}")]
+    Corrupt(String, serde_json::Error),
+    #[error("No usable generation of checkpoint {0}")]
+    NoValidGeneration(String),
+    #[error("Checkpoint {0} unable to be migrated {1}")]
+    Migrate(String, String),
+}
@@ -1,3 +1,8 @@
+mod checkpoint;
+pub mod errors;
+mod migrate;
 mod task;
 
-pub use task::run;
+pub use checkpoint::{check, generation_file, read, restore};
+pub use migrate::STATE;
+pub use task::{run, run_store, save, save_sightings};
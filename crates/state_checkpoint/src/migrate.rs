@@ -0,0 +1,41 @@
+use constellation_migrate::{Migration, Schema};
+use serde_json::{json, Value};
+
+/// state.json. version 0 is a bare `State`, from before it was wrapped in `StateVersion`
+pub const STATE: Schema = Schema {
+    name: "state",
+    current: 1,
+    detect,
+    migrations: &[Migration {
+        from: 0,
+        description: "wrap the unversioned state in StateVersion1",
+        upgrade: v0_to_v1,
+    }],
+};
+
+fn detect(value: &Value) -> Option<u32> {
+    let fields = value.as_object()?;
+    if fields.len() == 1 {
+        if let Some(version) = fields
+            .keys()
+            .next()
+            .and_then(|key| key.strip_prefix("StateVersion"))
+        {
+            return version.parse().ok();
+        }
+    }
+    if fields.contains_key("nodes") {
+        return Some(0);
+    }
+    None
+}
+
+fn v0_to_v1(value: &mut Value) -> anyhow::Result<Vec<String>> {
+    let nodes = value["nodes"].as_object().map(|n| n.len()).unwrap_or(0);
+    let state = value.take();
+    *value = json!({ "StateVersion1": state });
+    Ok(vec![format!(
+        "state (with {} nodes) moved under StateVersion1",
+        nodes
+    )])
+}
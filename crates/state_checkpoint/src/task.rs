@@ -1,25 +1,87 @@
+use crate::checkpoint;
 use chrono::Utc;
-use constellation_shared::state::AppState;
+use constellation_shared::state::State;
+use constellation_state::{AppSightings, SharedState};
+use constellation_status::ModuleReporter;
+use constellation_store::Store;
+use std::sync::PoisonError;
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time;
-pub async fn run(state: AppState, period: Duration, checkpoint_file: String) {
+
+/// save a checkpoint every period. returns once `shutdown` turns true, leaving the final
+/// checkpoint to whoever stopped it
+pub async fn run(
+    state: SharedState,
+    seen: AppSightings,
+    period: Duration,
+    checkpoint_file: String,
+    seen_file: String,
+    generations: u32,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
     let mut interval = time::interval(period);
 
     loop {
-        let mut state_c = { state.lock().unwrap().clone() };
-
-        let now = Utc::now();
-        state_c.last_saved = now;
-        match state_c.save(&checkpoint_file) {
-            Ok(_) => {
-                let mut the_state = state.lock().unwrap();
-                the_state.last_saved = now;
+        match save(&state, &checkpoint_file, generations)
+            .and_then(|_| save_sightings(&seen, &seen_file))
+        {
+            Ok(_) => status.success(1),
+            Err(e) => {
+                log::error!("Unable to save checkpoint file {} {}", checkpoint_file, e);
+                status.error(e)
             }
+        }
+
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+/// flush the state db, which the other modules have been writing to as they go.
+/// returns once `shutdown` turns true
+pub async fn run_store(
+    store: Store,
+    seen: AppSightings,
+    period: Duration,
+    seen_file: String,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = time::interval(period);
+
+    loop {
+        match store
+            .flush()
+            .and_then(|_| save_sightings(&seen, &seen_file))
+        {
+            Ok(_) => status.success(1),
             Err(e) => {
-                log::error!("Unable to save checkpoint file {} {}", checkpoint_file, e)
+                log::error!("Unable to flush state db/save {} {}", seen_file, e);
+                status.error(e)
             }
         }
 
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
     }
 }
+
+/// write the current state out to the checkpoint file, keeping `generations` older copies
+pub fn save(state: &SharedState, checkpoint_file: &str, generations: u32) -> anyhow::Result<()> {
+    // the time saved only matters to the file, so it is set on a copy rather than published
+    let mut saved = State::clone(&state.snapshot());
+    saved.last_saved = Utc::now();
+    checkpoint::write(&saved, checkpoint_file, generations)
+}
+
+/// write out when each node & IP was first and last seen
+pub fn save_sightings(seen: &AppSightings, seen_file: &str) -> anyhow::Result<()> {
+    let sightings = { seen.lock().unwrap_or_else(PoisonError::into_inner).clone() };
+    sightings.save(seen_file)
+}
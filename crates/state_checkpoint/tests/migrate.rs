@@ -0,0 +1,91 @@
+use chrono::{TimeZone, Utc};
+use constellation_state_checkpoint::{check, read, STATE};
+use std::fs;
+
+/// every version of state.json that has been written, holding the same state
+fn fixtures() -> Vec<String> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// the version a fixture was written as, from its name (state-v<version>...)
+fn version(file: &str) -> u32 {
+    let name = file.rsplit('/').next().unwrap();
+    name.trim_start_matches("state-v")
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn every_fixture_migrates_to_the_current_version() {
+    let files = fixtures();
+    assert!(files.len() >= 3, "fixtures missing from {:?}", files);
+    for file in &files {
+        let report = check(file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        assert_eq!(report.from, version(file), "{}", file);
+        assert_eq!(report.to, STATE.current, "{}", file);
+        assert_eq!(
+            report.steps.len() as u32,
+            STATE.current - version(file),
+            "{}",
+            file
+        );
+    }
+}
+
+#[test]
+fn every_fixture_keeps_its_fields() {
+    for file in fixtures() {
+        let state = read(&file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        assert!(state.nodes.is_empty(), "{}", file);
+        assert!(state.new_ips_bgp.contains("10.0.0.2"), "{}", file);
+        assert_eq!(
+            state.geo_ip_city.get("10.0.0.1"),
+            Some(&2643743),
+            "{}",
+            file
+        );
+        assert_eq!(
+            state.geo_ip_country.get("10.0.0.1"),
+            Some(&2635167),
+            "{}",
+            file
+        );
+        assert_eq!(
+            state.geo_ip_continent.get("10.0.0.1"),
+            Some(&6255148),
+            "{}",
+            file
+        );
+        assert_eq!(
+            state
+                .geo_country
+                .get(&2635167)
+                .and_then(|c| c.iso_code.as_deref()),
+            Some("GB"),
+            "{}",
+            file
+        );
+        assert!(state.geo_city.contains_key(&2643743), "{}", file);
+        assert!(state.geo_continent.contains_key(&6255148), "{}", file);
+        assert!(
+            state.geo_country_ip[&2635167].contains("10.0.0.1"),
+            "{}",
+            file
+        );
+        assert_eq!(
+            state.last_saved,
+            Utc.ymd(2021, 11, 2).and_hms(10, 16, 0),
+            "{}",
+            file
+        );
+    }
+}
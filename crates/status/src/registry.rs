@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// how a module's loop has been going
+#[derive(Clone, Debug, Serialize)]
+pub struct ModuleStatus {
+    pub chain: String,
+    pub module: String,
+    /// how often the module is expected to finish an iteration
+    pub period_secs: u64,
+    pub registered: DateTime<Utc>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub successes: u64,
+    pub errors: u64,
+    /// how many things (nodes, IPs, validators..) the last successful iteration dealt with
+    pub items: usize,
+}
+
+impl ModuleStatus {
+    /// healthy if the module has succeeded within the last `intervals` periods.
+    /// a module that hasn't succeeded yet gets the same allowance from when it was registered
+    pub fn is_healthy(&self, intervals: u32, now: DateTime<Utc>) -> bool {
+        let allowance = chrono::Duration::seconds((self.period_secs * intervals as u64) as i64);
+        let since = self.last_success.unwrap_or(self.registered);
+        now - since <= allowance
+    }
+}
+
+/// result of a readiness check
+#[derive(Clone, Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// the modules that aren't keeping up
+    pub unhealthy: Vec<ModuleStatus>,
+}
+
+/// shared by every module (on every chain), so the web server can report on them
+#[derive(Clone, Debug, Default)]
+pub struct StatusRegistry {
+    modules: Arc<Mutex<BTreeMap<String, ModuleStatus>>>,
+}
+
+impl StatusRegistry {
+    /// register a module that should finish an iteration every `period`, and get the handle it reports with
+    pub fn reporter(&self, chain: &str, module: &str, period: Duration) -> ModuleReporter {
+        let key = format!("{}/{}", chain, module);
+        self.modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                key.clone(),
+                ModuleStatus {
+                    chain: chain.into(),
+                    module: module.into(),
+                    period_secs: period.as_secs(),
+                    registered: Utc::now(),
+                    last_success: None,
+                    last_error: None,
+                    last_error_at: None,
+                    successes: 0,
+                    errors: 0,
+                    items: 0,
+                },
+            );
+        ModuleReporter {
+            key,
+            registry: self.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<ModuleStatus> {
+        self.modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// ready when every registered module has succeeded within `intervals` of its period
+    pub fn readiness(&self, intervals: u32) -> Readiness {
+        let now = Utc::now();
+        let unhealthy = self
+            .modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|status| !status.is_healthy(intervals, now))
+            .cloned()
+            .collect::<Vec<_>>();
+        Readiness {
+            ready: unhealthy.is_empty(),
+            unhealthy,
+        }
+    }
+
+    fn update<F: FnOnce(&mut ModuleStatus)>(&self, key: &str, f: F) {
+        if let Some(status) = self
+            .modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(key)
+        {
+            f(status)
+        }
+    }
+}
+
+/// what a module uses to say how it is going
+#[derive(Clone, Debug)]
+pub struct ModuleReporter {
+    key: String,
+    registry: StatusRegistry,
+}
+
+impl ModuleReporter {
+    /// an iteration finished, having dealt with `items` things
+    pub fn success(&self, items: usize) {
+        self.registry.update(&self.key, |status| {
+            status.last_success = Some(Utc::now());
+            status.successes += 1;
+            status.items = items;
+        })
+    }
+
+    /// something went wrong. it only counts against the module's health if it stops iterations succeeding
+    pub fn error<E: Display>(&self, error: E) {
+        self.registry.update(&self.key, |status| {
+            status.last_error = Some(error.to_string());
+            status.last_error_at = Some(Utc::now());
+            status.errors += 1;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(period_secs: u64, registered: DateTime<Utc>) -> ModuleStatus {
+        ModuleStatus {
+            chain: "columbus-5".into(),
+            module: "bgp".into(),
+            period_secs,
+            registered,
+            last_success: None,
+            last_error: None,
+            last_error_at: None,
+            successes: 0,
+            errors: 0,
+            items: 0,
+        }
+    }
+
+    #[test]
+    fn healthy_up_to_the_last_interval() {
+        let now = Utc::now();
+        let mut status = status(60, now - chrono::Duration::hours(1));
+        status.last_success = Some(now - chrono::Duration::seconds(180));
+        assert!(status.is_healthy(3, now));
+        assert!(!status.is_healthy(3, now + chrono::Duration::seconds(1)));
+        assert!(!status.is_healthy(2, now));
+    }
+
+    #[test]
+    fn allows_a_new_module_from_when_it_registered() {
+        let now = Utc::now();
+        let status = status(60, now - chrono::Duration::seconds(120));
+        assert!(status.is_healthy(2, now));
+        assert!(!status.is_healthy(2, now + chrono::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn reports_readiness_despite_a_poisoned_lock() {
+        let registry = StatusRegistry::default();
+        registry
+            .reporter("columbus-5", "bgp", Duration::from_secs(60))
+            .success(1);
+        registry.reporter("columbus-5", "geo", Duration::from_secs(0));
+        let modules = registry.modules.clone();
+        let _ = std::thread::spawn(move || {
+            let _held = modules.lock().unwrap();
+            panic!("poisoning the registry");
+        })
+        .join();
+
+        // geo has no allowance, so it is overdue as soon as any time passes
+        std::thread::sleep(Duration::from_millis(5));
+        let readiness = registry.readiness(3);
+        assert!(!readiness.ready);
+        assert_eq!(
+            readiness
+                .unhealthy
+                .iter()
+                .map(|status| status.module.as_str())
+                .collect::<Vec<_>>(),
+            vec!["geo"]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod sled_store;
+mod store;
+mod table;
+
+pub use sled_store::SledStore;
+pub use store::{Batch, StateStore, Store};
+pub use table::Table;
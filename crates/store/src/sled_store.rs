@@ -0,0 +1,60 @@
+use crate::store::StateStore;
+use crate::table::Table;
+
+/// `State` in an embedded sled database, one tree per table
+pub struct SledStore {
+    db: sled::Db,
+}
+
+impl SledStore {
+    pub fn open(path: &str) -> anyhow::Result<SledStore> {
+        Ok(SledStore {
+            db: sled::open(path)?,
+        })
+    }
+
+    fn tree(&self, table: Table) -> anyhow::Result<sled::Tree> {
+        Ok(self.db.open_tree(table.name())?)
+    }
+}
+
+impl StateStore for SledStore {
+    fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.tree(table)?.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn put_raw(&self, table: Table, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        self.tree(table)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, table: Table, key: &str) -> anyhow::Result<()> {
+        self.tree(table)?.remove(key)?;
+        Ok(())
+    }
+
+    fn scan(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let mut entries = vec![];
+        for entry in self.tree(table)?.iter() {
+            let (key, value) = entry?;
+            entries.push((String::from_utf8_lossy(&key).to_string(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn len(&self, table: Table) -> anyhow::Result<usize> {
+        Ok(self.tree(table)?.len())
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        for table in Table::ALL.iter() {
+            self.tree(*table)?.clear()?;
+        }
+        self.flush()
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use crate::table::Table;
+use constellation_shared::state::State;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+pub type Store = Arc<dyn StateStore>;
+
+/// somewhere to keep `State` a key at a time, so only what changed gets written
+pub trait StateStore: Send + Sync {
+    fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    fn put_raw(&self, table: Table, key: &str, value: &[u8]) -> anyhow::Result<()>;
+    fn remove(&self, table: Table, key: &str) -> anyhow::Result<()>;
+    /// every key & value in the table, in key order
+    fn scan(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+    fn len(&self, table: Table) -> anyhow::Result<usize>;
+    /// drop everything. used for a clean start
+    fn clear(&self) -> anyhow::Result<()>;
+    /// make sure everything written so far is on disk
+    fn flush(&self) -> anyhow::Result<()>;
+
+    fn is_empty(&self) -> anyhow::Result<bool> {
+        for table in Table::ALL.iter() {
+            if self.len(*table)? > 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// write a batch of changes. returns how many there were
+    fn apply(&self, batch: Batch) -> anyhow::Result<usize> {
+        if let Some(e) = batch.error {
+            return Err(e.into());
+        }
+        let count = batch.changes.len();
+        for change in batch.changes {
+            match change {
+                Change::Put(table, key, value) => self.put_raw(table, &key, &value)?,
+                Change::Remove(table, key) => self.remove(table, &key)?,
+            }
+        }
+        Ok(count)
+    }
+}
+
+enum Change {
+    Put(Table, String, Vec<u8>),
+    Remove(Table, String),
+}
+
+/// changes gathered while holding the state lock, written once it is released
+#[derive(Default)]
+pub struct Batch {
+    changes: Vec<Change>,
+    error: Option<serde_json::Error>,
+}
+
+impl Batch {
+    pub fn put<V: Serialize>(&mut self, table: Table, key: &str, value: &V) {
+        match serde_json::to_vec(value) {
+            Ok(value) => self.changes.push(Change::Put(table, key.into(), value)),
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(e)
+                }
+            }
+        }
+    }
+
+    /// add a member to a set table
+    pub fn insert(&mut self, table: Table, key: &str) {
+        self.changes.push(Change::Put(table, key.into(), vec![]))
+    }
+
+    pub fn remove(&mut self, table: Table, key: &str) {
+        self.changes.push(Change::Remove(table, key.into()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.error.is_none()
+    }
+}
+
+impl dyn StateStore {
+    /// point lookup of a single entry
+    pub fn get<V: DeserializeOwned>(&self, table: Table, key: &str) -> anyhow::Result<Option<V>> {
+        match self.get_raw(table, key)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, table: Table, key: &str) -> anyhow::Result<bool> {
+        Ok(self.get_raw(table, key)?.is_some())
+    }
+
+    /// copy a whole `State` in, eg. from an existing state.json. returns the number of entries written
+    pub fn import(&self, state: &State) -> anyhow::Result<usize> {
+        let mut batch = Batch::default();
+        if let Value::Object(fields) = serde_json::to_value(state)? {
+            for (field, value) in fields {
+                let table = Table::ALL
+                    .iter()
+                    .find(|t| t.name() == field && **t != Table::Meta);
+                match (table, value) {
+                    (Some(table), Value::Array(members)) if table.is_set() => {
+                        for member in members {
+                            match member {
+                                Value::String(key) => batch.insert(*table, &key),
+                                other => batch.insert(*table, &other.to_string()),
+                            }
+                        }
+                    }
+                    (Some(table), Value::Object(entries)) if !table.is_set() => {
+                        for (key, entry) in entries {
+                            batch.put(*table, &key, &entry)
+                        }
+                    }
+                    (_, value) => batch.put(Table::Meta, &field, &value),
+                }
+            }
+        }
+        self.apply(batch)
+    }
+
+    /// read the whole `State` back
+    pub fn load(&self) -> anyhow::Result<State> {
+        let mut state = serde_json::to_value(State::new()?)?;
+        let fields = state
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("State is not a JSON object"))?;
+        for table in Table::ALL.iter().filter(|t| **t != Table::Meta) {
+            let entries = self.scan(*table)?;
+            let value = if table.is_set() {
+                Value::Array(
+                    entries
+                        .into_iter()
+                        .map(|(key, _)| Value::String(key))
+                        .collect(),
+                )
+            } else {
+                let mut map = serde_json::Map::new();
+                for (key, entry) in entries {
+                    map.insert(key, serde_json::from_slice(&entry)?);
+                }
+                Value::Object(map)
+            };
+            fields.insert(table.name().into(), value);
+        }
+        for (field, value) in self.scan(Table::Meta)? {
+            fields.insert(field, serde_json::from_slice(&value)?);
+        }
+        Ok(serde_json::from_value(state)?)
+    }
+}
@@ -0,0 +1,78 @@
+/// a collection in `State`. each is kept in its own tree, keyed the same way as in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Table {
+    Nodes,
+    NewNodes,
+    NewIpsBgp,
+    NewIpsGeo,
+    IdIpAddr,
+    IpIpAddr,
+    Asn,
+    IpAsn,
+    AsnIp,
+    GeoCity,
+    GeoCountry,
+    GeoContinent,
+    GeoIpCity,
+    GeoIpCountry,
+    GeoIpContinent,
+    GeoCityIp,
+    GeoCountryIp,
+    GeoContinentIp,
+    /// everything in `State` that isn't a collection (eg. last_saved), keyed by field name
+    Meta,
+}
+
+impl Table {
+    pub const ALL: [Table; 19] = [
+        Table::Nodes,
+        Table::NewNodes,
+        Table::NewIpsBgp,
+        Table::NewIpsGeo,
+        Table::IdIpAddr,
+        Table::IpIpAddr,
+        Table::Asn,
+        Table::IpAsn,
+        Table::AsnIp,
+        Table::GeoCity,
+        Table::GeoCountry,
+        Table::GeoContinent,
+        Table::GeoIpCity,
+        Table::GeoIpCountry,
+        Table::GeoIpContinent,
+        Table::GeoCityIp,
+        Table::GeoCountryIp,
+        Table::GeoContinentIp,
+        Table::Meta,
+    ];
+
+    /// the `State` field the table holds
+    pub fn name(&self) -> &'static str {
+        match self {
+            Table::Nodes => "nodes",
+            Table::NewNodes => "new_nodes",
+            Table::NewIpsBgp => "new_ips_bgp",
+            Table::NewIpsGeo => "new_ips_geo",
+            Table::IdIpAddr => "id_ip_addr",
+            Table::IpIpAddr => "ip_ip_addr",
+            Table::Asn => "asn",
+            Table::IpAsn => "ip_asn",
+            Table::AsnIp => "asn_ip",
+            Table::GeoCity => "geo_city",
+            Table::GeoCountry => "geo_country",
+            Table::GeoContinent => "geo_continent",
+            Table::GeoIpCity => "geo_ip_city",
+            Table::GeoIpCountry => "geo_ip_country",
+            Table::GeoIpContinent => "geo_ip_continent",
+            Table::GeoCityIp => "geo_city_ip",
+            Table::GeoCountryIp => "geo_country_ip",
+            Table::GeoContinentIp => "geo_continent_ip",
+            Table::Meta => "meta",
+        }
+    }
+
+    /// sets are stored as keys with no value
+    pub fn is_set(&self) -> bool {
+        matches!(self, Table::NewNodes | Table::NewIpsBgp | Table::NewIpsGeo)
+    }
+}
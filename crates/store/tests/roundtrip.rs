@@ -0,0 +1,114 @@
+use constellation_shared::state::{GeoID, State};
+use constellation_store::{Batch, SledStore, Store, Table};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// a sled store in its own directory, removed again when dropped
+struct Scratch {
+    path: std::path::PathBuf,
+    store: Store,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let path = std::env::temp_dir().join(format!(
+            "constellation-store-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let store = Arc::new(SledStore::open(path.to_str().unwrap()).unwrap());
+        Scratch { path, store }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn asn(id: &str, name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "asn": id,
+        "country": "US",
+        "net": "arin",
+        "desc": name,
+        "last_updated": "2021-10-01T00:00:00Z",
+    })
+}
+
+#[test]
+fn imports_and_loads_a_state() {
+    let scratch = Scratch::new("import");
+    let mut state = State::new().unwrap();
+    state.new_ips_geo.insert("10.0.0.1".into());
+    state.new_ips_geo.insert("10.0.0.2".into());
+    state.geo_ip_country.insert("10.0.0.3".into(), 7 as GeoID);
+    state
+        .geo_country_ip
+        .entry(7 as GeoID)
+        .or_default()
+        .insert("10.0.0.3".into());
+    state
+        .asn_ip
+        .entry("13335".into())
+        .or_default()
+        .insert("10.0.0.3".into());
+
+    let written = scratch.store.import(&state).unwrap();
+    assert!(written >= 5);
+    assert!(!scratch.store.is_empty().unwrap());
+
+    let loaded = scratch.store.load().unwrap();
+    assert_eq!(loaded.new_ips_geo, state.new_ips_geo);
+    assert_eq!(loaded.geo_ip_country, state.geo_ip_country);
+    assert_eq!(loaded.geo_country_ip, state.geo_country_ip);
+    assert_eq!(loaded.asn_ip, state.asn_ip);
+    assert!(loaded.nodes.is_empty());
+    assert_eq!(loaded.last_saved, state.last_saved);
+}
+
+#[test]
+fn applies_batches_and_looks_entries_up() {
+    let scratch = Scratch::new("batch");
+    let store = &scratch.store;
+
+    let mut batch = Batch::default();
+    assert!(batch.is_empty());
+    batch.put(Table::Asn, "13335", &asn("13335", "CLOUDFLARENET"));
+    batch.put(Table::Asn, "15169", &asn("15169", "GOOGLE"));
+    batch.insert(Table::NewIpsBgp, "10.0.0.1");
+    assert!(!batch.is_empty());
+    assert_eq!(store.apply(batch).unwrap(), 3);
+
+    let found: Option<serde_json::Value> = store.get(Table::Asn, "13335").unwrap();
+    assert_eq!(found, Some(asn("13335", "CLOUDFLARENET")));
+    let missing: Option<serde_json::Value> = store.get(Table::Asn, "64512").unwrap();
+    assert_eq!(missing, None);
+    assert!(store.contains(Table::NewIpsBgp, "10.0.0.1").unwrap());
+    assert!(!store.contains(Table::NewIpsBgp, "10.0.0.2").unwrap());
+    assert_eq!(store.len(Table::Asn).unwrap(), 2);
+
+    let mut batch = Batch::default();
+    batch.remove(Table::Asn, "15169");
+    batch.remove(Table::NewIpsBgp, "10.0.0.1");
+    batch.put(Table::Asn, "13335", &asn("13335", "CLOUDFLARE"));
+    assert_eq!(store.apply(batch).unwrap(), 3);
+
+    let found: Option<serde_json::Value> = store.get(Table::Asn, "13335").unwrap();
+    assert_eq!(found, Some(asn("13335", "CLOUDFLARE")));
+    assert!(!store.contains(Table::Asn, "15169").unwrap());
+    assert!(!store.contains(Table::NewIpsBgp, "10.0.0.1").unwrap());
+
+    // what the batches left is what a load sees
+    let loaded = store.load().unwrap();
+    assert_eq!(
+        loaded.asn.keys().cloned().collect::<HashSet<_>>(),
+        vec!["13335".to_string()].into_iter().collect()
+    );
+    assert!(loaded.new_ips_bgp.is_empty());
+
+    store.clear().unwrap();
+    assert!(store.is_empty().unwrap());
+}
@@ -1,2 +1,4 @@
+mod migrate;
 mod validator;
-pub use validator::ValidatorActor;
+pub use migrate::VALIDATOR;
+pub use validator::{ValidatorActor, ValidatorSettings};
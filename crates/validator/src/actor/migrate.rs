@@ -0,0 +1,46 @@
+use constellation_migrate::{Migration, Schema};
+use serde_json::{Map, Value};
+
+/// validator.json. version 1 is the actor as it was saved before files carried a version
+pub const VALIDATOR: Schema = Schema {
+    name: "validator",
+    current: 2,
+    detect,
+    migrations: &[Migration {
+        from: 1,
+        description: "add a version, and the maps older files didn't have",
+        upgrade: v1_to_v2,
+    }],
+};
+
+fn detect(value: &Value) -> Option<u32> {
+    let fields = value.as_object()?;
+    match fields.get("version") {
+        Some(version) => version.as_u64().map(|v| v as u32),
+        None if fields.contains_key("validators") => Some(1),
+        None => None,
+    }
+}
+
+fn v1_to_v2(value: &mut Value) -> anyhow::Result<Vec<String>> {
+    let fields = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("validator file is not an object"))?;
+    let mut changes = vec![];
+    for map in &[
+        "moniker",
+        "cons_pub",
+        "cons",
+        "rewards",
+        "rewards_cumulative",
+        "rates",
+    ] {
+        if !fields.contains_key(*map) {
+            fields.insert(map.to_string(), Value::Object(Map::new()));
+            changes.push(format!("{} added (empty)", map));
+        }
+    }
+    fields.insert("version".into(), Value::from(2));
+    changes.push("version set to 2".into());
+    Ok(changes)
+}
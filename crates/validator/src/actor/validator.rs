@@ -4,7 +4,9 @@ use chrono::{DateTime, Timelike, Utc};
 use std::collections::HashMap;
 use terra_rust_api::client::client_types::{terra_datetime_format, terra_opt_datetime_format};
 //use rust_decimal::Decimal;
+use crate::actor::VALIDATOR;
 use crate::BrokerType;
+use constellation_migrate::Report;
 use constellation_shared::messages::{
     MessageBlockEventExchangeRate, MessageBlockEventLiveness, MessageBlockEventReward,
     MessageBlockHeight,
@@ -14,11 +16,16 @@ use constellation_shared::messages::{
     MessageValidatorEvent, MessageValidatorStakedTotal, SendMessageEventType,
 };
 use constellation_shared::{MessageStop, MessageTick};
-use rust_decimal::prelude::FromPrimitive;
+use constellation_state::write_atomic;
+use constellation_status::ModuleReporter;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::hash_map::Entry;
 use std::ops::{Div, Mul};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use terra_rust_api::staking_types;
 use terra_rust_api::tendermint_types;
 use terra_rust_api::Terra;
@@ -36,6 +43,65 @@ pub struct ValidatorDetails {
     pub abstains: u64,
     pub drifts: u64,
 }
+/// tunables for the validator actor. these come from config, and are not persisted
+#[derive(Clone, Debug)]
+pub struct ValidatorSettings {
+    /// where the actor state is saved
+    pub file: String,
+    /// how often the actor is saved, if anything has changed
+    pub save_every: Duration,
+    /// where save failures are reported
+    pub status: Option<ModuleReporter>,
+    /// send an abstain notification every N abstains
+    pub abstain_alert_every: u64,
+    /// fraction a validator's reward rate can stray from the average before it is reported
+    pub reward_slip: Decimal,
+}
+impl Default for ValidatorSettings {
+    fn default() -> Self {
+        ValidatorSettings {
+            file: "validator.json".into(),
+            save_every: Duration::from_secs(60),
+            status: None,
+            abstain_alert_every: 10,
+            reward_slip: Decimal::from_f64(0.05).unwrap_or_default(),
+        }
+    }
+}
+/// push a validator's numbers out to the metrics registry
+fn observe_validator(chain: &str, v: &ValidatorDetails) {
+    constellation_metrics::validator(
+        chain,
+        &v.validator.operator_address,
+        &v.validator.description.moniker,
+        v.validator.tokens as f64,
+        v.abstains,
+        v.drifts,
+    );
+}
+/// a saved actor that can't be used is moved out of the way (rather than overwritten),
+/// and the actor bootstraps from the LCD instead
+fn set_aside(file: &str, e: anyhow::Error) {
+    if !Path::new(file).exists() {
+        log::info!("{} not found. bootstrapping from the LCD", file);
+        return;
+    }
+    let aside = format!("{}.unusable", file);
+    match std::fs::rename(file, &aside) {
+        Ok(_) => log::error!(
+            "!!! {} unusable ({}). moved to {}, bootstrapping from the LCD",
+            file,
+            e,
+            aside
+        ),
+        Err(re) => log::error!(
+            "!!! {} unusable ({}), and couldn't be moved aside ({}). bootstrapping from the LCD",
+            file,
+            e,
+            re
+        ),
+    }
+}
 struct MergedValidatorLists {
     pub validator_details: HashMap<String, ValidatorDetails>,
     pub monikers: HashMap<String, String>,
@@ -56,53 +122,138 @@ pub struct ValidatorActor {
     pub rates: HashMap<String, Decimal>,
     pub lcd: String,
     pub chain: String,
+    #[serde(skip)]
+    pub settings: ValidatorSettings,
+    /// something changed since the last save
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    last_saved: Option<Instant>,
 }
 impl ValidatorActor {
-    pub async fn create(clean: bool, lcd: &str, chain: &str) -> anyhow::Result<ValidatorActor> {
+    pub async fn create(
+        clean: bool,
+        lcd: &str,
+        chain: &str,
+        settings: ValidatorSettings,
+    ) -> anyhow::Result<ValidatorActor> {
         if clean {
             log::info!("Validator Actor starting up clean");
-            let terra = Terra::lcd_client_no_tx(lcd, chain);
-            match terra.staking().validators().await {
-                Ok(validator_result) => match terra.tendermint().validatorsets_full().await {
-                    Ok(tendermint_result) => {
-                        log::info!(
-                            "Have validator/tendermint list kickstart v:{} t:{}",
-                            validator_result.result.len(),
-                            tendermint_result.result.validators.len()
-                        );
-                        let merged_validator_lists = ValidatorActor::from_validator_list(
-                            validator_result.height,
-                            validator_result.result,
-                            tendermint_result.result.validators,
-                        );
-                        Ok(ValidatorActor {
-                            last_height: 0,
-                            last_tick: None,
-                            validators: merged_validator_lists.validator_details,
-                            moniker: merged_validator_lists.monikers,
-                            cons_pub: merged_validator_lists.cons_pub,
-                            cons: merged_validator_lists.cons,
-                            rewards: Default::default(),
-                            rewards_cumulative: Default::default(),
-                            rates: Default::default(),
-                            lcd: lcd.into(),
-                            chain: chain.into(),
-                        })
-                    }
-                    Err(e) => {
-                        log::error!("validator sets {}", e);
-                        Err(e)
+        } else {
+            match ValidatorActor::load(&settings.file) {
+                Ok((mut va, report)) => {
+                    if !report.is_current() {
+                        log::warn!("{}: {}", settings.file, report);
                     }
-                },
+                    va.settings = settings;
+                    return Ok(va);
+                }
+                Err(e) => set_aside(&settings.file, e),
+            }
+        }
+        let terra = Terra::lcd_client_no_tx(lcd, chain);
+        match constellation_metrics::timed(chain, "validators", terra.staking().validators()).await
+        {
+            Ok(validator_result) => match constellation_metrics::timed(
+                chain,
+                "validatorsets_full",
+                terra.tendermint().validatorsets_full(),
+            )
+            .await
+            {
+                Ok(tendermint_result) => {
+                    log::info!(
+                        "Have validator/tendermint list kickstart v:{} t:{}",
+                        validator_result.result.len(),
+                        tendermint_result.result.validators.len()
+                    );
+                    let merged_validator_lists = ValidatorActor::from_validator_list(
+                        validator_result.height,
+                        validator_result.result,
+                        tendermint_result.result.validators,
+                    );
+                    Ok(ValidatorActor {
+                        last_height: 0,
+                        last_tick: None,
+                        validators: merged_validator_lists.validator_details,
+                        moniker: merged_validator_lists.monikers,
+                        cons_pub: merged_validator_lists.cons_pub,
+                        cons: merged_validator_lists.cons,
+                        rewards: Default::default(),
+                        rewards_cumulative: Default::default(),
+                        rates: Default::default(),
+                        lcd: lcd.into(),
+                        chain: chain.into(),
+                        settings,
+                        dirty: true,
+                        last_saved: None,
+                    })
+                }
                 Err(e) => {
-                    log::error!("staking validators {}", e);
+                    log::error!("validator sets {}", e);
                     Err(e)
                 }
+            },
+            Err(e) => {
+                log::error!("staking validators {}", e);
+                Err(e)
+            }
+        }
+    }
+    /// an actor that knows of no validators yet. they arrive with MessageValidator,
+    /// which is what happens when a recording is replayed
+    pub fn empty(lcd: &str, chain: &str, settings: ValidatorSettings) -> ValidatorActor {
+        ValidatorActor {
+            last_height: 0,
+            last_tick: None,
+            validators: Default::default(),
+            moniker: Default::default(),
+            cons_pub: Default::default(),
+            cons: Default::default(),
+            rewards: Default::default(),
+            rewards_cumulative: Default::default(),
+            rates: Default::default(),
+            lcd: lcd.into(),
+            chain: chain.into(),
+            settings,
+            dirty: false,
+            last_saved: None,
+        }
+    }
+    /// read a saved actor, migrating it if it was written by an older version
+    pub fn load(file: &str) -> anyhow::Result<(ValidatorActor, Report)> {
+        let value: Value = serde_json::from_reader(std::fs::File::open(file)?)?;
+        let (value, report) = VALIDATOR.migrate(value)?;
+        Ok((serde_json::from_value(value)?, report))
+    }
+    /// write the actor out, so it can be picked up again on restart
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut value = serde_json::to_value(&self)?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("version".into(), Value::from(VALIDATOR.current));
+        }
+        write_atomic(&self.settings.file, &serde_json::to_vec(&value)?)
+    }
+    /// save if something has changed and it has been `save_every` since the last save (or `force`)
+    fn persist(&mut self, force: bool) {
+        let due = self
+            .last_saved
+            .map(|at| at.elapsed() >= self.settings.save_every)
+            .unwrap_or(true);
+        if !self.dirty || !(due || force) {
+            return;
+        }
+        match self.save() {
+            Ok(_) => {
+                self.dirty = false;
+                self.last_saved = Some(Instant::now());
+            }
+            Err(e) => {
+                log::error!("Unable to save {} {}", self.settings.file, e);
+                if let Some(status) = &self.settings.status {
+                    status.error(format!("save {}: {}", self.settings.file, e));
+                }
             }
-        } else {
-            let va: ValidatorActor =
-                serde_json::from_reader(std::fs::File::open("validator.json")?)?;
-            Ok(va)
         }
     }
     fn from_validator_list(
@@ -174,6 +325,7 @@ impl Handler<MessageStop> for ValidatorActor {
 
     fn handle(&mut self, _msg: MessageStop, ctx: &mut Self::Context) {
         log::info!("Validator Actor Stopping");
+        self.persist(true);
         ctx.stop()
     }
 }
@@ -182,6 +334,7 @@ impl Handler<MessageValidator> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessageValidator, _ctx: &mut Self::Context) {
+        self.dirty = true;
         log::debug!(
             "Validator Updated MSG {}/{}",
             msg.validator.description.moniker,
@@ -197,7 +350,8 @@ impl Handler<MessageValidator> for ValidatorActor {
                 v.last_updated_block = height;
                 v.last_updated_date = now;
                 v.validator = msg.validator.clone();
-                v.tendermint_account = msg.tendermint.map(|v| v.address)
+                v.tendermint_account = msg.tendermint.map(|v| v.address);
+                observe_validator(&self.chain, v);
             }
             Entry::Vacant(e) => {
                 let v = ValidatorDetails {
@@ -210,6 +364,7 @@ impl Handler<MessageValidator> for ValidatorActor {
                     abstains: 0,
                     drifts: 0,
                 };
+                observe_validator(&self.chain, &v);
                 e.insert(v);
             }
         }
@@ -225,6 +380,7 @@ impl Handler<MessagePriceAbstain> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessagePriceAbstain, _ctx: &mut Self::Context) {
+        self.dirty = true;
         let height = msg.height;
         self.last_height = height;
         let now = Utc::now();
@@ -234,6 +390,7 @@ impl Handler<MessagePriceAbstain> for ValidatorActor {
                 v.abstains += 1;
                 v.last_updated_block = height;
                 v.last_updated_date = now;
+                observe_validator(&self.chain, v);
                 //   e.into_mut();
                 let message = format!(
                     "abstained from voting for denominations:{} Abstains:{}",
@@ -243,7 +400,7 @@ impl Handler<MessagePriceAbstain> for ValidatorActor {
                     v.abstains,
                     //  msg.txhash,
                 );
-                if v.abstains % 10 == 0 {
+                if v.abstains % self.settings.abstain_alert_every == 0 {
                     Broker::<SystemBroker>::issue_async(MessageValidatorEvent {
                         height,
                         operator_address: msg.operator_address.clone(),
@@ -265,6 +422,7 @@ impl Handler<MessagePriceDrift> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessagePriceDrift, _ctx: &mut Self::Context) {
+        self.dirty = true;
         let now = Utc::now();
         let height = msg.height;
         self.last_height = height;
@@ -274,6 +432,7 @@ impl Handler<MessagePriceDrift> for ValidatorActor {
                 v.drifts += 1;
                 v.last_updated_block = height;
                 v.last_updated_date = now;
+                observe_validator(&self.chain, v);
                 let message = format!("{} price drift submitted {:.4} too far away from Average:{:.4}/ Weighted:{:.4} Drifts:{}",    
                                       msg.denom,
                                       msg.submitted,
@@ -305,6 +464,7 @@ impl Handler<MessageBlockEventLiveness> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessageBlockEventLiveness, _ctx: &mut Self::Context) {
+        self.dirty = true;
         log::debug!("Liveness {}", msg.tendermint_address);
         let height = msg.height;
         self.last_height = height;
@@ -334,6 +494,7 @@ impl Handler<MessageBlockEventReward> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessageBlockEventReward, _ctx: &mut Self::Context) {
+        self.dirty = true;
         let height = msg.height;
         self.last_height = height;
         let mut block_reward: Decimal = Decimal::from(0);
@@ -369,8 +530,14 @@ impl Handler<MessageBlockEventExchangeRate> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessageBlockEventExchangeRate, _ctx: &mut Self::Context) {
+        self.dirty = true;
         self.last_height = msg.height;
         //log::info!("Exchange rate {}/{}", msg.denom, msg.exchange_rate);
+        constellation_metrics::exchange_rate(
+            &self.chain,
+            &msg.denom,
+            msg.exchange_rate.to_f64().unwrap_or_default(),
+        );
         self.rates.insert(msg.denom, msg.exchange_rate);
     }
 }
@@ -378,13 +545,14 @@ impl Handler<MessageBlockHeight> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, _msg: MessageBlockHeight, _ctx: &mut Self::Context) {
-        serde_json::to_writer(std::fs::File::create("validator.json").unwrap(), &self).unwrap();
+        self.persist(false)
     }
 }
 impl Handler<MessageTick> for ValidatorActor {
     type Result = ();
 
     fn handle(&mut self, msg: MessageTick, _ctx: &mut Self::Context) {
+        self.dirty = true;
         if let Some(validator) = self
             .validators
             .get("terravaloper12g4nkvsjjnl0t7fvq3hdcw7y8dc9fq69nyeu9q")
@@ -427,6 +595,12 @@ impl Handler<MessageTick> for ValidatorActor {
                         rate *= Decimal::from(24); // daily
                                                    // todo rate should be aggregated as tokens change
                         validator_rate.push((validator.0.clone(), rate));
+                        constellation_metrics::validator_reward_rate(
+                            &self.chain,
+                            validator.0,
+                            &validator_deets.validator.description.moniker,
+                            rate.to_f64().unwrap_or_default(),
+                        );
                         let message = format!(
                             "{} - Generated Rewards of {:0.2} luna over ~{:.2} tokens - Daily Rate {:0.8}",
                             validator_deets.validator.description.moniker, rewards.div(Decimal::from(1_000_000)), tokens.div(1_000_000), rate
@@ -467,7 +641,8 @@ impl Handler<MessageTick> for ValidatorActor {
         if !validator_rate.is_empty() {
             let average_rate: Decimal = validator_rate.iter().map(|f| f.1).sum::<Decimal>()
                 / Decimal::from(validator_rate.len());
-            let slip = average_rate.mul(Decimal::from_f64(0.05).unwrap());
+            let slip = average_rate.mul(self.settings.reward_slip);
+            let allowance = self.settings.reward_slip.mul(Decimal::from(100));
             let mut top = validator_rate
                 .iter()
                 .filter(|r| r.1 > (average_rate + slip))
@@ -484,9 +659,11 @@ impl Handler<MessageTick> for ValidatorActor {
                 height: self.last_height,
                 event_type: SendMessageEventType::ANNOUNCE,
                 message: format!(
-                    "Average Rate = {:0.8} #over allowance of 5% = {} #below allowance of 5% {}",
+                    "Average Rate = {:0.8} #over allowance of {:0.0}% = {} #below allowance of {:0.0}% {}",
                     average_rate,
+                    allowance,
                     top.len(),
+                    allowance,
                     bottom.len()
                 ),
                 hash: None,
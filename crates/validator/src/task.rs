@@ -1,30 +1,50 @@
 use actix_broker::{Broker, SystemBroker};
 use constellation_shared::messages::MessageValidator;
-use constellation_shared::state::AppState;
+use constellation_state::SharedState;
+use constellation_status::ModuleReporter;
 use std::collections::HashMap;
 use std::time::Duration;
 use terra_rust_api::client::tendermint_types;
 use terra_rust_api::Terra;
+use tokio::sync::watch;
 use tokio::time;
 
-pub async fn run(_state: AppState, period: Duration, chain_id: String, lcd_endpoint: String) {
+/// send every validator on the broker, every period. returns once `shutdown` turns true
+pub async fn run(
+    _state: SharedState,
+    period: Duration,
+    chain_id: String,
+    lcd_endpoint: String,
+    status: ModuleReporter,
+    mut shutdown: watch::Receiver<bool>,
+) {
     log::info!("Validator task starting");
     let mut interval = time::interval(period);
     loop {
         let mut tendermint: HashMap<String, tendermint_types::Validator> = Default::default();
         log::info!("Attempting validator refresh");
         let terra = Terra::lcd_client_no_tx(&lcd_endpoint, &chain_id);
-        let tendermint_validator_set = terra.tendermint().validatorsets_full().await;
+        let tendermint_validator_set = constellation_metrics::timed(
+            &chain_id,
+            "validatorsets_full",
+            terra.tendermint().validatorsets_full(),
+        )
+        .await;
         match tendermint_validator_set {
             Ok(tendermint_list) => {
                 tendermint_list.result.validators.iter().for_each(|v| {
                     tendermint.insert(v.pub_key.value.clone(), v.clone());
                 });
             }
-            Err(e) => log::error!("Can't obtain tendermint validator set {}", e),
+            Err(e) => {
+                log::error!("Can't obtain tendermint validator set {}", e);
+                status.error(e)
+            }
         }
 
-        match terra.staking().validators().await {
+        match constellation_metrics::timed(&chain_id, "validators", terra.staking().validators())
+            .await
+        {
             Ok(validator_result) => {
                 log::info!("sending {} update messages", validator_result.result.len());
                 validator_result.result.iter().for_each(|v| {
@@ -34,11 +54,18 @@ pub async fn run(_state: AppState, period: Duration, chain_id: String, lcd_endpo
                         validator: v.clone(),
                         tendermint: tendermint.get(&v.consensus_pubkey.value).cloned(),
                     });
-                })
+                });
+                status.success(validator_result.result.len());
+            }
+            Err(e) => {
+                log::error!("can't obtain validators {}", e);
+                status.error(e)
             }
-            Err(e) => log::error!("can't obtain validators {}", e),
         }
 
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
     }
 }
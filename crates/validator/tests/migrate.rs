@@ -0,0 +1,83 @@
+use constellation_validator::actor::{ValidatorActor, VALIDATOR};
+use rust_decimal::Decimal;
+use std::fs;
+use std::str::FromStr;
+
+/// every version of validator.json that has been written
+fn fixtures() -> Vec<String> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// the version a fixture was written as, from its name (validator-v<version>.json)
+fn version(file: &str) -> u32 {
+    let name = file.rsplit('/').next().unwrap();
+    name.trim_start_matches("validator-v")
+        .trim_end_matches(".json")
+        .parse()
+        .unwrap()
+}
+
+fn decimal(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+#[test]
+fn every_fixture_migrates_to_the_current_version() {
+    let files = fixtures();
+    assert!(files.len() >= 2, "fixtures missing from {:?}", files);
+    for file in &files {
+        let (_, report) = ValidatorActor::load(file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        assert_eq!(report.from, version(file), "{}", file);
+        assert_eq!(report.to, VALIDATOR.current, "{}", file);
+        assert_eq!(
+            report.is_current(),
+            version(file) == VALIDATOR.current,
+            "{}",
+            file
+        );
+    }
+}
+
+#[test]
+fn every_fixture_keeps_its_fields() {
+    for file in fixtures() {
+        let (actor, _) = ValidatorActor::load(&file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        assert_eq!(actor.last_height, 5210345, "{}", file);
+        assert_eq!(actor.last_tick, None, "{}", file);
+        assert_eq!(actor.lcd, "https://lcd.terra.dev", "{}", file);
+        assert_eq!(actor.chain, "columbus-5", "{}", file);
+        assert_eq!(
+            actor.rewards.get("terravaloper1example"),
+            Some(&decimal("0.0123")),
+            "{}",
+            file
+        );
+    }
+}
+
+#[test]
+fn maps_older_files_lacked_are_added_empty() {
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/validator-v1.json");
+    let (actor, report) = ValidatorActor::load(file).unwrap();
+    assert_eq!(report.steps.len(), 1);
+    assert!(report.steps[0]
+        .changes
+        .iter()
+        .any(|change| change == "rates added (empty)"));
+    assert!(actor.rewards_cumulative.is_empty());
+    assert!(actor.rates.is_empty());
+
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/validator-v2.json");
+    let (actor, _) = ValidatorActor::load(file).unwrap();
+    assert_eq!(
+        actor.rewards_cumulative.get("terravaloper1example"),
+        Some(&decimal("1.75"))
+    );
+    assert_eq!(actor.rates.get("uusd"), Some(&decimal("48.123")));
+}
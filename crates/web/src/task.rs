@@ -1,28 +1,65 @@
 //use actix_web::dev::Server;
+use actix_web::error::ErrorInternalServerError;
 use actix_web::{middleware, web, App, Error as AWError, HttpRequest, HttpResponse, HttpServer};
+use constellation_enrich::{AppEnrichment, Change};
+use constellation_peers::{persistent_peers, Criteria, Recommendation};
+use constellation_prober::{AppReachability, Reachability};
 use constellation_shared::state::{
-    AppState, GeoCity, GeoContinent, GeoCountry, GeoID, IpAsnMapping, ASN,
+    GeoCity, GeoContinent, GeoCountry, GeoID, IpAsnMapping, State, ASN,
 };
+use constellation_state::{AppSightings, Seen, SharedState};
+use constellation_status::{ModuleStatus, StatusRegistry};
+use constellation_store::{Store, Table};
 
 /// VERSION number of package
 pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 /// NAME of package
 pub const NAME: Option<&'static str> = option_env!("CARGO_PKG_NAME");
 use actix_web::dev::Server;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::collections::HashSet;
-//use std::sync::mpsc;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, PoisonError};
 use terra_rust_api::addressbook::{NodeAddr, NodeIDIPPort};
+use tokio::sync::watch;
 
+/// a chain the web server shows
+#[derive(Clone)]
+pub struct WebChain {
+    /// used in the path, /chain/<name>/...
+    pub name: String,
+    pub chain_id: String,
+    pub state: SharedState,
+    /// with the sled backend, where single entries are looked up
+    pub store: Option<Store>,
+    /// when each node was seen, and by which address book sources
+    pub seen: AppSightings,
+    /// how each node answered the prober
+    pub reachability: AppReachability,
+    /// what the bgp & geo lookups remember, including how each IP's answer has changed
+    pub enrichment: AppEnrichment,
+}
+
+/// serve the state of each chain under /chain/<name>/...
+/// the first chain is also served at the top level, as it was when there was only one
 pub async fn run(
-    state: AppState,
+    chains: Vec<WebChain>,
     // _tx: mpsc::Sender<Server>,
     name: &'static str,
     version: &'static str,
+    bind: String,
+    status: StatusRegistry,
+    ready_intervals: u32,
+    mut shutdown: watch::Receiver<bool>,
 ) {
+    let chain_names = ChainNames(chains.iter().map(|c| c.name.clone()).collect());
+    let metric_states = MetricStates(
+        chains
+            .iter()
+            .map(|c| (c.chain_id.clone(), c.state.clone()))
+            .collect(),
+    );
     // srv is server controller type, `dev::Server`
-    let local = tokio::task::LocalSet::new();
-
     let srv = HttpServer::new(move || {
         let version_string = format!(
             "{}/{} Web:{}/{}",
@@ -31,36 +68,63 @@ pub async fn run(
             NAME.unwrap_or("WEB"),
             VERSION.unwrap_or("dev")
         );
-        App::new()
-            .app_data(state.clone())
+        let mut app = App::new()
+            .app_data(chain_names.clone())
+            .app_data(status.clone())
+            .app_data(metric_states.clone())
+            .app_data(ReadyIntervals(ready_intervals))
             // enable logger
             .wrap(middleware::Logger::default())
             .wrap(middleware::DefaultHeaders::new().add(("X-Version", version_string)))
             .service(web::resource("/").to(|| async { "Hello world!" }))
-            .service(web::resource("/city").route(web::get().to(cities)))
-            .service(web::resource("/city/{id:\\d+}").route(web::get().to(city_detail)))
-            .service(web::resource("/country").route(web::get().to(countries)))
-            .service(web::resource("/country/{id:\\d+}").route(web::get().to(country_detail)))
-            .service(web::resource("/continent").route(web::get().to(continent)))
-            .service(web::resource("/continent/{id:\\d+}").route(web::get().to(continent_detail)))
-            .service(web::resource("/asn").route(web::get().to(asns)))
-            .service(web::resource("/asn/{asn:\\d+}").route(web::get().to(asn_detail)))
-            .service(web::resource("/node").route(web::get().to(nodes)))
-            .service(web::resource("/node/{node:\\w+}").route(web::get().to(node_detail)))
-            .service(
-                web::resource("/ip/{ip:\\d+\\.\\d+\\.\\d+\\.\\d+}").route(web::get().to(ip_detail)),
-            )
+            .service(web::resource("/chains").route(web::get().to(chain_list)))
+            .service(web::resource("/health").route(web::get().to(health)))
+            .service(web::resource("/ready").route(web::get().to(ready)))
+            .service(web::resource("/metrics").route(web::get().to(metrics)));
+        for chain in &chains {
+            app = app.service(
+                web::scope(&format!("/chain/{}", chain.name))
+                    .app_data(chain.state.clone())
+                    .app_data(chain.store.clone())
+                    .app_data(chain.seen.clone())
+                    .app_data(chain.reachability.clone())
+                    .app_data(chain.enrichment.clone())
+                    .configure(state_routes),
+            );
+        }
+        if let Some(chain) = chains.first() {
+            app = app
+                .app_data(chain.state.clone())
+                .app_data(chain.store.clone())
+                .app_data(chain.seen.clone())
+                .app_data(chain.reachability.clone())
+                .app_data(chain.enrichment.clone())
+                .configure(state_routes);
+        }
+        app
     })
-    .bind("0.0.0.0:8080");
+    .bind(&bind);
     match srv {
         Ok(server) => {
             let s: Server = server.run();
-            //   let _ = tx.send(s);
-            local.spawn_local(s);
-            // s.await?;
+            let handle = s.handle();
+            tokio::pin!(s);
+            // wait on the server, so whoever spawned us knows when it goes away
+            let result = tokio::select! {
+                result = &mut s => result,
+                _ = shutdown.changed() => {
+                    log::info!("webserver shutting down");
+                    // the server needs to keep being polled while in-flight requests finish
+                    let (_, result) = tokio::join!(handle.stop(true), &mut s);
+                    result
+                }
+            };
+            if let Err(e) = result {
+                log::error!("webserver stopped {}", e);
+            }
         }
         Err(e) => {
-            panic!("Fail to start webserver {}", e);
+            panic!("Fail to start webserver on {} {}", bind, e);
         }
     }
 
@@ -69,8 +133,81 @@ pub async fn run(
     // Ok(())
 }
 
+/// the routes that show a chain's state
+fn state_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/city").route(web::get().to(cities)))
+        .service(web::resource("/city/{id:\\d+}").route(web::get().to(city_detail)))
+        .service(web::resource("/country").route(web::get().to(countries)))
+        .service(web::resource("/country/{id:\\d+}").route(web::get().to(country_detail)))
+        .service(web::resource("/continent").route(web::get().to(continent)))
+        .service(web::resource("/continent/{id:\\d+}").route(web::get().to(continent_detail)))
+        .service(web::resource("/asn").route(web::get().to(asns)))
+        .service(web::resource("/asn/{asn:\\d+}").route(web::get().to(asn_detail)))
+        .service(web::resource("/node").route(web::get().to(nodes)))
+        .service(web::resource("/node/reachability").route(web::get().to(reachability)))
+        .service(web::resource("/node/recommend").route(web::get().to(recommend)))
+        .service(web::resource("/node/{node:\\w+}").route(web::get().to(node_detail)))
+        .service(web::resource("/ip/{ip:[0-9a-fA-F:.]+}").route(web::get().to(ip_detail)));
+}
+
+#[derive(Clone, Serialize)]
+struct ChainNames(Vec<String>);
+
+async fn chain_list(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    let chains = req.app_data::<ChainNames>().unwrap();
+    Ok(HttpResponse::Ok().json(chains))
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    modules: Vec<ModuleStatus>,
+}
+/// liveness. if we can answer, we are alive. the module detail is for humans
+async fn health(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    let status = req.app_data::<StatusRegistry>().unwrap();
+    Ok(HttpResponse::Ok().json(Health {
+        status: "ok",
+        modules: status.snapshot(),
+    }))
+}
+
+#[derive(Clone, Copy)]
+struct ReadyIntervals(u32);
+
+/// readiness. 503 unless every running module has made progress recently
+async fn ready(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    let status = req.app_data::<StatusRegistry>().unwrap();
+    let intervals = req.app_data::<ReadyIntervals>().unwrap();
+    let readiness = status.readiness(intervals.0);
+    if readiness.ready {
+        Ok(HttpResponse::Ok().json(readiness))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(readiness))
+    }
+}
+
+/// (chain_id, state) of every chain, for the network gauges
+#[derive(Clone)]
+struct MetricStates(Vec<(String, SharedState)>);
+
+/// prometheus scrape endpoint
+async fn metrics(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    let states = req.app_data::<MetricStates>().unwrap();
+    constellation_metrics::observe_states(&states.0);
+    match constellation_metrics::encode() {
+        Ok(body) => Ok(HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body)),
+        Err(e) => {
+            log::error!("Unable to encode metrics {}", e);
+            Ok(HttpResponse::InternalServerError().body("unable to encode metrics"))
+        }
+    }
+}
+
 async fn cities(req: HttpRequest) -> Result<HttpResponse, AWError> {
-    let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
     Ok(HttpResponse::Ok().json(&r.geo_city))
 }
 
@@ -82,7 +219,7 @@ struct CityIP<'a> {
 async fn city_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req.match_info().get("id").unwrap_or("0").parse::<GeoID>() {
         Ok(id) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+            let r = req.app_data::<SharedState>().unwrap().snapshot();
             let city = r.geo_city.get(&id);
             let city_ip = r.geo_city_ip.get(&id);
             Ok(HttpResponse::Ok().json(CityIP { city, ip: city_ip }))
@@ -92,7 +229,7 @@ async fn city_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
 }
 
 async fn countries(req: HttpRequest) -> Result<HttpResponse, AWError> {
-    let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
     Ok(HttpResponse::Ok().json(&r.geo_country))
 }
 
@@ -104,7 +241,7 @@ struct CountryIP<'a> {
 async fn country_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req.match_info().get("id").unwrap_or("0").parse::<GeoID>() {
         Ok(id) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+            let r = req.app_data::<SharedState>().unwrap().snapshot();
             let country = r.geo_country.get(&id);
             let country_ip = r.geo_country_ip.get(&id);
             Ok(HttpResponse::Ok().json(CountryIP {
@@ -116,7 +253,7 @@ async fn country_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     }
 }
 async fn continent(req: HttpRequest) -> Result<HttpResponse, AWError> {
-    let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
     Ok(HttpResponse::Ok().json(&r.geo_continent))
 }
 
@@ -128,7 +265,7 @@ struct ContinentIP<'a> {
 async fn continent_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req.match_info().get("id").unwrap_or("0").parse::<GeoID>() {
         Ok(id) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+            let r = req.app_data::<SharedState>().unwrap().snapshot();
             let continent = r.geo_continent.get(&id);
             let continent_ip = r.geo_continent_ip.get(&id);
             Ok(HttpResponse::Ok().json(ContinentIP {
@@ -140,38 +277,134 @@ async fn continent_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     }
 }
 
+/// where the detail handlers look single entries up: the chain's store when it has one,
+/// otherwise a snapshot of its state
+enum Entries {
+    Store(Store),
+    State(Arc<State>),
+}
+
+impl Entries {
+    fn of(req: &HttpRequest) -> Entries {
+        match req.app_data::<Option<Store>>() {
+            Some(Some(store)) => Entries::Store(store.clone()),
+            _ => Entries::State(req.app_data::<SharedState>().unwrap().snapshot()),
+        }
+    }
+
+    /// the entry under `key` in `table`, which `in_state` finds in the state
+    fn get<V, F>(&self, table: Table, key: &str, in_state: F) -> Result<Option<V>, AWError>
+    where
+        V: DeserializeOwned + Clone,
+        F: FnOnce(&State) -> Option<&V>,
+    {
+        match self {
+            Entries::Store(store) => store.get(table, key).map_err(ErrorInternalServerError),
+            Entries::State(state) => Ok(in_state(state).cloned()),
+        }
+    }
+}
+
 async fn asns(req: HttpRequest) -> Result<HttpResponse, AWError> {
-    let r = req.app_data::<AppState>().unwrap().lock().unwrap();
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
     Ok(HttpResponse::Ok().json(&r.asn))
 }
 
 #[derive(Serialize)]
-struct ASNDetail<'a> {
-    asn: Option<&'a ASN>,
-    ip: Option<&'a HashSet<String>>,
+struct ASNDetail {
+    asn: Option<ASN>,
+    ip: Option<HashSet<String>>,
 }
 async fn asn_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req.match_info().get("asn").unwrap_or("0").parse::<usize>() {
         Ok(id) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
-            let asn = r.asn.get(&id.to_string());
-            let asn_ip = r.asn_ip.get(&id.to_string());
+            let entries = Entries::of(&req);
+            let id = id.to_string();
+            let asn = entries.get(Table::Asn, &id, |r| r.asn.get(&id))?;
+            let asn_ip = entries.get(Table::AsnIp, &id, |r| r.asn_ip.get(&id))?;
             Ok(HttpResponse::Ok().json(ASNDetail { asn, ip: asn_ip }))
         }
         Err(_e) => Ok(HttpResponse::NotAcceptable().body("bad asn")),
     }
 }
 
+/// a node, along with how it answered the prober
+#[derive(Serialize)]
+struct ProbedNode<'a> {
+    #[serde(flatten)]
+    node: &'a NodeAddr,
+    reachability: Option<Reachability>,
+}
+
+/// join each node (keyed id@ip:port) with its reachability, copied out so the prober isn't kept waiting
+fn probed<'a>(
+    req: &HttpRequest,
+    nodes: impl Iterator<Item = (&'a String, &'a NodeAddr)>,
+) -> Vec<(&'a String, ProbedNode<'a>)> {
+    let reach = req
+        .app_data::<AppReachability>()
+        .map(|reach| reach.lock().unwrap_or_else(PoisonError::into_inner));
+    nodes
+        .map(|(key, node)| {
+            let reachability = reach.as_ref().and_then(|reach| reach.get(key).cloned());
+            (key, ProbedNode { node, reachability })
+        })
+        .collect()
+}
+
 async fn nodes(req: HttpRequest) -> Result<HttpResponse, AWError> {
-    let r = req.app_data::<AppState>().unwrap().lock().unwrap();
-    Ok(HttpResponse::Ok().json(&r.nodes))
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
+    let nodes = probed(&req, r.nodes.iter())
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    Ok(HttpResponse::Ok().json(nodes))
+}
+
+/// every probed node, keyed id@ip:port. copied out first, so the prober isn't kept waiting
+async fn reachability(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    let sorted = req
+        .app_data::<AppReachability>()
+        .unwrap()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(node, reach)| (node.clone(), reach.clone()))
+        .collect::<BTreeMap<_, _>>();
+    Ok(HttpResponse::Ok().json(sorted))
+}
+
+#[derive(Serialize)]
+struct Recommended {
+    /// ready for tendermint's config.toml
+    persistent_peers: String,
+    peers: Vec<Recommendation>,
+}
+/// healthy, diverse peers. ?count=20&min_uptime=0.9
+async fn recommend(
+    req: HttpRequest,
+    criteria: web::Query<Criteria>,
+) -> Result<HttpResponse, AWError> {
+    let r = req.app_data::<SharedState>().unwrap().snapshot();
+    let reach = req
+        .app_data::<AppReachability>()
+        .unwrap()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let peers = constellation_peers::recommend(&r, &reach, &criteria);
+    Ok(HttpResponse::Ok().json(Recommended {
+        persistent_peers: persistent_peers(&peers),
+        peers,
+    }))
 }
 
 #[derive(Serialize)]
-struct NodeDetail {
+struct NodeDetail<'a> {
     id: String,
     id_ip_port: HashSet<NodeIDIPPort>,
-    nodes: Vec<NodeAddr>,
+    nodes: Vec<ProbedNode<'a>>,
+    /// per id@ip:port, when it was seen and which sources reported it
+    seen: BTreeMap<String, Seen>,
 }
 async fn node_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req
@@ -181,19 +414,35 @@ async fn node_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
         .parse::<String>()
     {
         Ok(node) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
-            let empty: HashSet<NodeIDIPPort> = HashSet::new();
-            let id_ip_port = r.id_ip_addr.get(&node).unwrap_or(&empty);
-            let mut nodes: Vec<NodeAddr> = vec![];
-            id_ip_port.iter().for_each(|f| {
-                if let Some(n) = r.nodes.get(&f.to_string()) {
-                    nodes.push(n.clone())
+            let entries = Entries::of(&req);
+            let id_ip_port: HashSet<NodeIDIPPort> = entries
+                .get(Table::IdIpAddr, &node, |r| r.id_ip_addr.get(&node))?
+                .unwrap_or_default();
+            let mut found = vec![];
+            for f in &id_ip_port {
+                let key = f.to_string();
+                if let Some(n) = entries.get(Table::Nodes, &key, |r| r.nodes.get(&key))? {
+                    found.push((key, n));
+                }
+            }
+            let nodes = probed(&req, found.iter().map(|(key, n)| (key, n)))
+                .into_iter()
+                .map(|(_, node)| node)
+                .collect();
+            let mut seen = BTreeMap::new();
+            if let Some(sightings) = req.app_data::<AppSightings>() {
+                let sightings = sightings.lock().unwrap_or_else(PoisonError::into_inner);
+                for f in &id_ip_port {
+                    if let Some(s) = sightings.nodes.get(&f.to_string()) {
+                        seen.insert(f.to_string(), s.clone());
+                    }
                 }
-            });
+            }
             Ok(HttpResponse::Ok().json(NodeDetail {
                 id: node,
-                id_ip_port: id_ip_port.clone(),
+                id_ip_port,
                 nodes,
+                seen,
             }))
         }
         Err(_e) => Ok(HttpResponse::NotAcceptable().body("bad id")),
@@ -207,54 +456,78 @@ struct IPDetail {
     nodes: Vec<NodeAddr>,
     asn_ip: Option<IpAsnMapping>,
     asn: Option<ASN>,
+    /// every ASN announcing the IP's prefix. `asn_ip` has the first
+    origins: Vec<String>,
     city: Option<GeoCity>,
     country: Option<GeoCountry>,
     continent: Option<GeoContinent>,
+    /// ASN & country changes seen when the IP was looked up again, oldest first
+    history: Vec<Change>,
 }
 async fn ip_detail(req: HttpRequest) -> Result<HttpResponse, AWError> {
     match req.match_info().get("ip").unwrap_or("0").parse::<String>() {
         Ok(ip) => {
-            let r = req.app_data::<AppState>().unwrap().lock().unwrap();
-
-            let empty: HashSet<NodeIDIPPort> = HashSet::new();
-            let ip_id_port = r.ip_ip_addr.get(&ip).unwrap_or(&empty);
+            let entries = Entries::of(&req);
+            let ip_id_port: HashSet<NodeIDIPPort> = entries
+                .get(Table::IpIpAddr, &ip, |r| r.ip_ip_addr.get(&ip))?
+                .unwrap_or_default();
             let mut nodes: Vec<NodeAddr> = vec![];
-            ip_id_port.iter().for_each(|f| {
-                if let Some(n) = r.nodes.get(&f.to_string()) {
-                    nodes.push(n.clone())
+            for f in &ip_id_port {
+                let key = f.to_string();
+                if let Some(n) = entries.get(Table::Nodes, &key, |r| r.nodes.get(&key))? {
+                    nodes.push(n)
                 }
-            });
-            let city = match r.geo_ip_city.get(&ip) {
-                Some(geoid) => r.geo_city.get(geoid),
-                None => None,
-            }
-            .cloned();
-            let country = match r.geo_ip_country.get(&ip) {
-                Some(geoid) => r.geo_country.get(geoid),
-                None => None,
             }
-            .cloned();
-            let continent = match r.geo_ip_continent.get(&ip) {
-                Some(geoid) => r.geo_continent.get(geoid),
+            let city = match entries.get(Table::GeoIpCity, &ip, |r| r.geo_ip_city.get(&ip))? {
+                Some(geoid) => entries.get(Table::GeoCity, &geoid.to_string(), |r| {
+                    r.geo_city.get(&geoid)
+                })?,
                 None => None,
-            }
-            .cloned();
-            let asn_ip = r.ip_asn.get(&ip).cloned();
+            };
+            let country =
+                match entries.get(Table::GeoIpCountry, &ip, |r| r.geo_ip_country.get(&ip))? {
+                    Some(geoid) => entries.get(Table::GeoCountry, &geoid.to_string(), |r| {
+                        r.geo_country.get(&geoid)
+                    })?,
+                    None => None,
+                };
+            let continent =
+                match entries.get(Table::GeoIpContinent, &ip, |r| r.geo_ip_continent.get(&ip))? {
+                    Some(geoid) => entries.get(Table::GeoContinent, &geoid.to_string(), |r| {
+                        r.geo_continent.get(&geoid)
+                    })?,
+                    None => None,
+                };
+            let asn_ip = entries.get(Table::IpAsn, &ip, |r| r.ip_asn.get(&ip))?;
             let asn = match &asn_ip {
-                Some(aa) => r.asn.get(&aa.asn),
+                Some(aa) => entries.get(Table::Asn, &aa.asn, |r| r.asn.get(&aa.asn))?,
                 None => None,
-            }
-            .cloned();
+            };
+            let (origins, history) = match req.app_data::<AppEnrichment>() {
+                Some(e) => {
+                    let e = e.lock().unwrap_or_else(PoisonError::into_inner);
+                    (
+                        asn_ip
+                            .as_ref()
+                            .map(|a| e.origins(&ip, &a.asn))
+                            .unwrap_or_default(),
+                        e.history.get(&ip).cloned().unwrap_or_default(),
+                    )
+                }
+                None => (asn_ip.iter().map(|a| a.asn.clone()).collect(), vec![]),
+            };
 
             Ok(HttpResponse::Ok().json(IPDetail {
                 asn_ip,
                 asn,
+                origins,
                 city,
                 country,
                 continent,
                 ip,
-                ip_id_port: ip_id_port.clone(),
+                ip_id_port,
                 nodes,
+                history,
             }))
         }
         Err(_e) => Ok(HttpResponse::NotAcceptable().body("bad ip")),
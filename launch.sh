@@ -1,4 +1,4 @@
 #!/usr/bin/env bash
 cd /mnt/disks/data/terrad/constellation
-/home/user/.cargo/bin/cargo run --release --features private -- 
+exec /home/user/.cargo/bin/cargo run --release --features private -- 
 #--clean-start true
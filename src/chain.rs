@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_broker::{Broker, SystemBroker};
+use chrono::Utc;
+use constellation_address_book::CrawledPeers;
+use constellation_discord::actor::DiscordRoute;
+use constellation_enrich::{AppEnrichment, Enrichment};
+use constellation_prober::{load_reachability, AppReachability};
+use constellation_shared::state::State;
+use constellation_shared::MessageStop;
+use constellation_state::{AppSightings, SharedState, Sightings};
+use constellation_state_checkpoint::errors::CheckpointError;
+use constellation_status::StatusRegistry;
+use constellation_store::{SledStore, Store};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::{ChainProfile, Config, StateBackend};
+use crate::modules::{self, ModuleContext, ModuleRegistry, ModuleScope};
+use crate::supervisor::{until_stopped, Supervisor};
+
+/// a chain being watched on its own thread.
+/// each chain gets its own actix system, so its broker only carries that chain's messages
+pub struct ChainHandle {
+    pub name: String,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl ChainHandle {
+    /// ask the chain's modules to stop. it reports back on the `finished` channel it was spawned with
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// what a chain collects, and where it is kept
+#[derive(Clone)]
+pub struct ChainState {
+    pub state: SharedState,
+    /// with the sled backend
+    pub store: Option<Store>,
+    pub seen: AppSightings,
+    /// peers the RPC crawler found, for the address book
+    pub crawled: CrawledPeers,
+    /// how each node answered the prober
+    pub reachability: AppReachability,
+    /// what the bgp & geo lookups remember between runs
+    pub enrichment: AppEnrichment,
+    /// the chain's discord actor, for the process wide gateway to pass discord's events to
+    pub discord: DiscordRoute,
+}
+
+/// load the chain's state, or start afresh
+pub fn restore_state(
+    config: &Config,
+    profile: &ChainProfile,
+    clean: bool,
+) -> anyhow::Result<ChainState> {
+    let (state_data, store) = restore_state_data(config, profile, clean)?;
+    let mut sightings = if clean {
+        Sightings::default()
+    } else {
+        Sightings::load(&profile.seen_file).unwrap_or_else(|e| {
+            log::error!("Seen file {} unable to be read ({})", profile.seen_file, e);
+            Sightings::default()
+        })
+    };
+    let backfilled = sightings.backfill(&state_data, Utc::now());
+    if backfilled > 0 {
+        log::info!(
+            "{} nodes/IPs had no first/last seen. treating them as seen now",
+            backfilled
+        );
+    }
+    let reachability = if clean {
+        HashMap::new()
+    } else {
+        load_reachability(&profile.reachability_file).unwrap_or_else(|e| {
+            log::error!(
+                "Reachability file {} unable to be read ({})",
+                profile.reachability_file,
+                e
+            );
+            HashMap::new()
+        })
+    };
+    let enrichment = if clean {
+        Enrichment::default()
+    } else {
+        Enrichment::load(&profile.enrichment_file).unwrap_or_else(|e| {
+            log::error!(
+                "Enrichment file {} unable to be read ({})",
+                profile.enrichment_file,
+                e
+            );
+            Enrichment::default()
+        })
+    };
+    Ok(ChainState {
+        state: SharedState::new(state_data),
+        store,
+        seen: Arc::new(Mutex::new(sightings)),
+        crawled: CrawledPeers::default(),
+        reachability: Arc::new(Mutex::new(reachability)),
+        enrichment: Arc::new(Mutex::new(enrichment)),
+        discord: DiscordRoute::default(),
+    })
+}
+
+fn restore_state_data(
+    config: &Config,
+    profile: &ChainProfile,
+    clean: bool,
+) -> anyhow::Result<(State, Option<Store>)> {
+    let generations = config.checkpoint.generations;
+    match config.state.backend {
+        StateBackend::Json => {
+            let state_data = if clean {
+                State::new()?
+            } else {
+                restore_checkpoint(profile, generations)?
+            };
+            Ok((state_data, None))
+        }
+        StateBackend::Sled => {
+            let store: Store = Arc::new(SledStore::open(&profile.state_db)?);
+            if clean {
+                store.clear()?;
+            } else if store.is_empty()? && Path::new(&profile.state_file).exists() {
+                log::info!("Importing {} into {}", profile.state_file, profile.state_db);
+                let imported = store.import(&restore_checkpoint(profile, generations)?)?;
+                store.flush()?;
+                log::info!("Imported {} entries from {}", imported, profile.state_file);
+            }
+            Ok((store.load()?, Some(store)))
+        }
+    }
+}
+
+/// the newest usable checkpoint, or an empty state
+fn restore_checkpoint(profile: &ChainProfile, generations: u32) -> anyhow::Result<State> {
+    match constellation_state_checkpoint::restore(&profile.state_file, generations) {
+        Ok(state) => Ok(state),
+        Err(e @ CheckpointError::NotFound(_)) => {
+            log::info!("{}. starting new", e);
+            Ok(State::new()?)
+        }
+        Err(e) => {
+            log::error!("!!! {}. starting with an EMPTY state", e);
+            Ok(State::new()?)
+        }
+    }
+}
+
+/// start the chain's modules on a new thread. when they have all stopped, the chain's name
+/// and how it went is sent on `finished`
+pub fn spawn(
+    config: Config,
+    profile: ChainProfile,
+    chain_state: ChainState,
+    order: Vec<&'static str>,
+    clean: bool,
+    status: StatusRegistry,
+    finished: mpsc::UnboundedSender<(String, anyhow::Result<()>)>,
+) -> std::io::Result<ChainHandle> {
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let name = profile.name.clone();
+    std::thread::Builder::new()
+        .name(format!("chain-{}", name))
+        .spawn(move || {
+            let name = profile.name.clone();
+            let system = actix_rt::System::new();
+            let result = system.block_on(run(
+                config,
+                profile,
+                chain_state,
+                order,
+                clean,
+                status,
+                stop_rx,
+            ));
+            let _ = finished.send((name, result));
+        })?;
+    Ok(ChainHandle {
+        name,
+        stop: Some(stop_tx),
+    })
+}
+
+async fn run(
+    config: Config,
+    profile: ChainProfile,
+    chain_state: ChainState,
+    order: Vec<&'static str>,
+    clean: bool,
+    status: StatusRegistry,
+    mut stop: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    log::info!("Chain {} ({}) starting", profile.name, profile.chain_id);
+    let mut registry = modules::registry();
+    let mut supervisor = Supervisor::new(&profile.name, config.supervisor.policy());
+    {
+        let period = Duration::from_secs(config.tick.period_secs);
+        supervisor.spawn("tick", move |shutdown| {
+            until_stopped(constellation_shared::run(period), shutdown)
+        })?;
+    }
+    let mut ctx = ModuleContext {
+        config,
+        chain: profile.clone(),
+        state: chain_state.state.clone(),
+        chains: vec![(profile, chain_state.clone())],
+        store: chain_state.store,
+        seen: chain_state.seen,
+        crawled: chain_state.crawled,
+        reachability: chain_state.reachability,
+        enrichment: chain_state.enrichment,
+        discord: chain_state.discord,
+        status,
+        supervisor,
+        clean,
+    };
+
+    let mut stopping = false;
+    let started = tokio::select! {
+        started = registry.start(&order, ModuleScope::Chain, &mut ctx) => started,
+        _ = &mut stop => {
+            stopping = true;
+            Ok(())
+        }
+    };
+    if started.is_ok() && !stopping {
+        tokio::select! {
+            _ = ctx.supervisor.join() => log::error!("Chain {} all modules have stopped", ctx.chain.name),
+            _ = &mut stop => {},
+        }
+    }
+    shutdown(&mut registry, &mut ctx).await;
+    log::info!("Chain {} stopped", ctx.chain.name);
+    started
+}
+
+/// stop modules (last started goes first) so they can persist what they have, then tell
+/// any remaining actors to stop
+pub async fn shutdown(registry: &mut ModuleRegistry, ctx: &mut ModuleContext) {
+    ctx.supervisor.stop("tick", Duration::from_secs(0)).await;
+    registry.stop(ctx).await;
+    Broker::<SystemBroker>::issue_async(MessageStop {});
+    let grace = ctx.config.shutdown.deadline();
+    ctx.supervisor.stop_all(grace).await;
+}
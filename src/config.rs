@@ -0,0 +1,1245 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use constellation_enrich::Refresh;
+use serde::Deserialize;
+use url::Url;
+
+use crate::errors::ConstellationError;
+use crate::supervisor::RestartPolicy;
+
+/// config file used when one isn't specified. It is fine for this one to be missing
+pub const DEFAULT_CONFIG_FILE: &str = "constellation.toml";
+
+/// Constellation settings.
+/// layered as: built-in defaults <- config file <- environment <- command line
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub chain: ChainConfig,
+    /// chains to monitor side by side. when empty, the single chain described by `chain` is used
+    pub chains: Vec<ChainProfileConfig>,
+    pub state: StateConfig,
+    pub tick: TickConfig,
+    pub supervisor: SupervisorConfig,
+    pub shutdown: ShutdownConfig,
+    pub address_book: AddressBookConfig,
+    pub bgp: BgpConfig,
+    pub checkpoint: CheckpointConfig,
+    pub retention: RetentionConfig,
+    pub node_summary: NodeSummaryConfig,
+    pub recorder: RecorderConfig,
+    pub geo: GeoConfig,
+    pub rpc: PeriodicConfig,
+    pub prober: ProberConfig,
+    pub websocket: ModuleConfig,
+    pub oracle: ModuleConfig,
+    pub price: ModuleConfig,
+    pub validator: ValidatorConfig,
+    pub discord: DiscordConfig,
+    pub web: WebConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    pub chain_id: String,
+    pub lcd: String,
+    pub rpc: String,
+    pub fcd: String,
+}
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            chain_id: "columbus-5".into(),
+            lcd: "https://lcd.terra.dev".into(),
+            rpc: "http://public-node.terra.dev:26657".into(),
+            fcd: "https://fcd.terra.dev".into(),
+        }
+    }
+}
+
+/// one entry in `[[chains]]`. anything left out is taken from the top level settings,
+/// with the files and discord channels namespaced by the profile name
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainProfileConfig {
+    /// used in logs, web paths (/chain/<name>/...) and discord channel names. defaults to chain_id
+    pub name: Option<String>,
+    pub chain_id: Option<String>,
+    pub lcd: Option<String>,
+    pub rpc: Option<String>,
+    pub fcd: Option<String>,
+    pub address_book: Option<String>,
+    pub address_book_sources: Option<Vec<String>>,
+    pub state_file: Option<String>,
+    pub state_db: Option<String>,
+    pub seen_file: Option<String>,
+    pub reachability_file: Option<String>,
+    pub enrichment_file: Option<String>,
+    pub validator_file: Option<String>,
+    pub recorder_file: Option<String>,
+    pub announcement_channel: Option<String>,
+    pub private_channel: Option<String>,
+}
+
+/// everything that differs from one monitored chain to the next
+#[derive(Clone, Debug)]
+pub struct ChainProfile {
+    pub name: String,
+    pub chain_id: String,
+    pub lcd: String,
+    pub rpc: String,
+    pub fcd: String,
+    /// where nodes are found. the address book url, followed by any other sources
+    pub address_books: Vec<String>,
+    pub state_file: String,
+    pub state_db: String,
+    pub seen_file: String,
+    /// what the prober found
+    pub reachability_file: String,
+    /// what the bgp & geo lookups remember between runs
+    pub enrichment_file: String,
+    pub validator_file: String,
+    pub recorder_file: String,
+    pub announcement_channel: String,
+    pub private_channel: String,
+}
+
+fn check_url(problems: &mut Vec<String>, key: &str, value: &str) {
+    if let Err(e) = Url::parse(value) {
+        problems.push(format!("{} '{}' is not a valid URL ({})", key, value, e))
+    }
+}
+
+/// put the chain name in front of a file name, keeping the directory. data/state.json -> data/bombay-12-state.json
+fn namespaced_file(name: &str, file: &str) -> String {
+    let path = Path::new(file);
+    match path.file_name() {
+        Some(file_name) => path
+            .with_file_name(format!("{}-{}", name, file_name.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        None => format!("{}-{}", name, file),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateConfig {
+    /// where to store state to survive restarts
+    pub file: String,
+    pub backend: StateBackend,
+    /// the database directory, for the sled backend
+    pub db: String,
+    /// when each node & IP was first and last seen
+    pub seen_file: String,
+    /// IPs the bgp & geo lookups had no answer for, when geo last located each IP,
+    /// and the changes seen when IPs were looked up again
+    pub enrichment_file: String,
+}
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            file: "state.json".into(),
+            backend: StateBackend::Json,
+            db: "state.db".into(),
+            seen_file: "seen.json".into(),
+            enrichment_file: "enrichment.json".into(),
+        }
+    }
+}
+
+/// how state is kept between restarts
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// the whole state is written to `file` every checkpoint
+    Json,
+    /// changes are written to an embedded database as they happen. `file` is imported on first start
+    Sled,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TickConfig {
+    pub period_secs: u64,
+}
+impl Default for TickConfig {
+    fn default() -> Self {
+        TickConfig {
+            period_secs: 60 * 5,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub max_restarts: usize,
+    pub reset_after_secs: u64,
+}
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        let policy = RestartPolicy::default();
+        SupervisorConfig {
+            initial_backoff_secs: policy.initial_backoff.as_secs(),
+            max_backoff_secs: policy.max_backoff.as_secs(),
+            max_restarts: policy.max_restarts,
+            reset_after_secs: policy.reset_after.as_secs(),
+        }
+    }
+}
+impl SupervisorConfig {
+    pub fn policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_secs(self.initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+            max_restarts: self.max_restarts,
+            reset_after: Duration::from_secs(self.reset_after_secs),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// how long modules get to flush their state when asked to stop, before we exit regardless
+    pub deadline_secs: u64,
+}
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { deadline_secs: 30 }
+    }
+}
+impl ShutdownConfig {
+    pub fn deadline(&self) -> Duration {
+        Duration::from_secs(self.deadline_secs)
+    }
+}
+
+/// a module with nothing to configure except whether it runs
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModuleConfig {
+    pub enabled: bool,
+}
+impl Default for ModuleConfig {
+    fn default() -> Self {
+        ModuleConfig { enabled: true }
+    }
+}
+
+/// a module that wakes up every `period_secs`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeriodicConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+}
+impl Default for PeriodicConfig {
+    fn default() -> Self {
+        PeriodicConfig {
+            enabled: true,
+            period_secs: 60 * 5,
+        }
+    }
+}
+impl PeriodicConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+}
+
+/// where bgp looks up which ASN each IP belongs to
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AsnSourceKind {
+    /// team cymru's DNS zones, via `nameserver` if set
+    Dns,
+    /// an iptoasn.com style ip2asn-combined.tsv, in `db_file`
+    Tsv,
+    /// a maxmind GeoLite2-ASN database, in `db_file`
+    Mmdb,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BgpConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    pub source: AsnSourceKind,
+    /// ip or ip:port. defaults to the system's resolvers
+    pub nameserver: Option<String>,
+    pub db_file: String,
+    /// lookups in flight at once
+    pub concurrency: usize,
+    /// most DNS queries a second to team cymru, shared by every chain. 0 is no limit.
+    /// local files aren't limited
+    pub rate_per_sec: u32,
+    /// IPs are looked up again once their answer is this old. 0 never looks again
+    pub ttl_hours: u64,
+    /// most stale IPs looked up again each run
+    pub refresh_per_run: usize,
+    /// how long an IP with no answer waits before it is tried again. doubles with each miss,
+    /// up to `retry_max_secs`
+    pub retry_secs: u64,
+    pub retry_max_secs: u64,
+}
+impl Default for BgpConfig {
+    fn default() -> Self {
+        BgpConfig {
+            enabled: true,
+            period_secs: 60 * 5,
+            source: AsnSourceKind::Dns,
+            nameserver: None,
+            db_file: "db/ip2asn-combined.tsv".into(),
+            concurrency: 16,
+            rate_per_sec: 20,
+            ttl_hours: 24 * 7,
+            refresh_per_run: 500,
+            retry_secs: 60 * 5,
+            retry_max_secs: 60 * 60 * 24,
+        }
+    }
+}
+impl BgpConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    pub fn refresh(&self) -> Refresh {
+        refresh(
+            self.ttl_hours,
+            self.refresh_per_run,
+            self.retry_secs,
+            self.retry_max_secs,
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AddressBookConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    pub url: String,
+    /// more places to find nodes, merged with `url`: addrbook.json URLs, addrbook.json files
+    /// (eg. from our own sentries), and `rpc` for the peers the RPC crawler finds
+    pub sources: Vec<String>,
+}
+impl Default for AddressBookConfig {
+    fn default() -> Self {
+        AddressBookConfig {
+            enabled: true,
+            period_secs: 60 * 5,
+            url: "https://network.terra.dev/addrbook.json".into(),
+            sources: vec![],
+        }
+    }
+}
+impl AddressBookConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    /// `url` (unless it is blank) then `sources`, without repeats
+    pub fn sources(&self, url: &str, sources: &[String]) -> Vec<String> {
+        let mut all: Vec<String> = vec![];
+        for source in std::iter::once(url).chain(sources.iter().map(|s| s.as_str())) {
+            if !source.is_empty() && !all.iter().any(|s| s == source) {
+                all.push(source.to_string());
+            }
+        }
+        all
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckpointConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// how many checkpoints to keep. restore falls back to older ones if the newest is damaged
+    pub generations: u32,
+}
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            enabled: true,
+            period_secs: 60,
+            generations: 3,
+        }
+    }
+}
+impl CheckpointConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+}
+
+/// drop nodes & IPs that haven't been in the address book for a while
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// how long something can go unseen before it is dropped
+    pub days: u32,
+}
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            enabled: true,
+            period_secs: 60 * 60,
+            days: 90,
+        }
+    }
+}
+impl RetentionConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.days as i64)
+    }
+}
+
+/// connect to every node to see which are online. off by default
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProberConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// connections open at once
+    pub concurrency: usize,
+    /// how long a connect can take before the node counts as unreachable
+    pub timeout_ms: u64,
+    /// also do the tendermint handshake, to get each node's network, version & moniker
+    pub handshake: bool,
+    /// where the results are kept, for restarts & the `peers` command
+    pub file: String,
+}
+impl Default for ProberConfig {
+    fn default() -> Self {
+        ProberConfig {
+            enabled: false,
+            period_secs: 60 * 10,
+            concurrency: 64,
+            timeout_ms: 3000,
+            handshake: false,
+            file: "reachability.json".into(),
+        }
+    }
+}
+impl ProberConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// announce how many nodes came & went, every period
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeSummaryConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+}
+impl Default for NodeSummaryConfig {
+    fn default() -> Self {
+        NodeSummaryConfig {
+            enabled: true,
+            period_secs: 60 * 60,
+        }
+    }
+}
+impl NodeSummaryConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+}
+
+/// capture broker messages, so they can be replayed later (see the replay command)
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    /// JSONL file messages are appended to
+    pub file: String,
+}
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        RecorderConfig {
+            enabled: false,
+            file: "broker.jsonl".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// maxmind city db file
+    pub db_file: String,
+    /// lookups in flight at once
+    pub concurrency: usize,
+    /// IPs are located again once they were located this long ago. 0 never looks again
+    pub ttl_hours: u64,
+    /// most stale IPs located again each run
+    pub refresh_per_run: usize,
+    /// how long an IP the db doesn't have waits before it is tried again. doubles with each miss,
+    /// up to `retry_max_secs`
+    pub retry_secs: u64,
+    pub retry_max_secs: u64,
+}
+impl Default for GeoConfig {
+    fn default() -> Self {
+        GeoConfig {
+            enabled: true,
+            period_secs: 60 * 5,
+            db_file: "db/GeoLite2-City.mmdb".into(),
+            concurrency: 4,
+            // the db is updated monthly
+            ttl_hours: 24 * 30,
+            refresh_per_run: 5000,
+            retry_secs: 60 * 60,
+            retry_max_secs: 60 * 60 * 24 * 7,
+        }
+    }
+}
+impl GeoConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    pub fn refresh(&self) -> Refresh {
+        refresh(
+            self.ttl_hours,
+            self.refresh_per_run,
+            self.retry_secs,
+            self.retry_max_secs,
+        )
+    }
+}
+
+fn refresh(ttl_hours: u64, per_run: usize, retry_secs: u64, retry_max_secs: u64) -> Refresh {
+    Refresh {
+        ttl: match ttl_hours {
+            0 => None,
+            hours => Some(chrono::Duration::hours(hours as i64)),
+        },
+        per_run,
+        retry: chrono::Duration::seconds(retry_secs as i64),
+        retry_max: chrono::Duration::seconds(retry_max_secs as i64),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidatorConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// where the validator actor keeps its state
+    pub file: String,
+    /// how often the validator actor is saved, if anything has changed
+    pub save_secs: u64,
+    /// send an abstain notification every N abstains
+    pub abstain_alert_every: u64,
+    /// % a validator's reward rate can stray from the average before it is reported
+    pub reward_slip_percent: f64,
+}
+impl Default for ValidatorConfig {
+    fn default() -> Self {
+        ValidatorConfig {
+            enabled: true,
+            period_secs: 60 * 5,
+            file: "validator.json".into(),
+            save_secs: 60,
+            abstain_alert_every: 10,
+            reward_slip_percent: 5.0,
+        }
+    }
+}
+impl ValidatorConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+    pub fn save_every(&self) -> Duration {
+        Duration::from_secs(self.save_secs)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub enabled: bool,
+    /// endpoint for discord api
+    pub url: String,
+    /// token for discord api
+    pub token: Option<String>,
+    /// #retries for discord api, when we hit discord API caps
+    pub retries: usize,
+    pub announcement_channel: String,
+    pub private_channel: String,
+}
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        DiscordConfig {
+            enabled: true,
+            url: "https://discordapp.com/".into(),
+            token: None,
+            retries: 4,
+            announcement_channel: "announcements".into(),
+            private_channel: "private".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    pub enabled: bool,
+    pub bind: String,
+    /// /ready fails when a module hasn't succeeded in this many of its periods
+    pub ready_intervals: u32,
+}
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            enabled: true,
+            bind: "0.0.0.0:8080".into(),
+            ready_intervals: 3,
+        }
+    }
+}
+
+impl Config {
+    /// read the config file. if `file` is None, the default file is read if it exists
+    pub fn load(file: Option<&str>) -> Result<Config, ConstellationError> {
+        let (file_name, must_exist) = match file {
+            Some(f) => (f, true),
+            None => (DEFAULT_CONFIG_FILE, false),
+        };
+        if !Path::new(file_name).exists() {
+            if must_exist {
+                return Err(ConstellationError::ConfigFile(
+                    file_name.into(),
+                    "file not found".into(),
+                ));
+            }
+            log::info!("No config file {} found. using defaults", file_name);
+            return Ok(Config::default());
+        }
+        log::info!("Reading config file {}", file_name);
+        let contents = std::fs::read_to_string(file_name)
+            .map_err(|e| ConstellationError::ConfigFile(file_name.into(), e.to_string()))?;
+        toml::from_str(&contents)
+            .map_err(|e| ConstellationError::ConfigFile(file_name.into(), e.to_string()))
+    }
+
+    /// the chains to monitor. with no `[[chains]]` this is just the top level chain, unchanged
+    pub fn profiles(&self) -> Vec<ChainProfile> {
+        if self.chains.is_empty() {
+            return vec![ChainProfile {
+                name: self.chain.chain_id.clone(),
+                chain_id: self.chain.chain_id.clone(),
+                lcd: self.chain.lcd.clone(),
+                rpc: self.chain.rpc.clone(),
+                fcd: self.chain.fcd.clone(),
+                address_books: self
+                    .address_book
+                    .sources(&self.address_book.url, &self.address_book.sources),
+                state_file: self.state.file.clone(),
+                state_db: self.state.db.clone(),
+                seen_file: self.state.seen_file.clone(),
+                reachability_file: self.prober.file.clone(),
+                enrichment_file: self.state.enrichment_file.clone(),
+                validator_file: self.validator.file.clone(),
+                recorder_file: self.recorder.file.clone(),
+                announcement_channel: self.discord.announcement_channel.clone(),
+                private_channel: self.discord.private_channel.clone(),
+            }];
+        }
+        self.chains
+            .iter()
+            .map(|chain| {
+                let chain_id = chain
+                    .chain_id
+                    .clone()
+                    .unwrap_or_else(|| self.chain.chain_id.clone());
+                let name = chain.name.clone().unwrap_or_else(|| chain_id.clone());
+                ChainProfile {
+                    lcd: chain.lcd.clone().unwrap_or_else(|| self.chain.lcd.clone()),
+                    rpc: chain.rpc.clone().unwrap_or_else(|| self.chain.rpc.clone()),
+                    fcd: chain.fcd.clone().unwrap_or_else(|| self.chain.fcd.clone()),
+                    address_books: self.address_book.sources(
+                        chain
+                            .address_book
+                            .as_deref()
+                            .unwrap_or(&self.address_book.url),
+                        chain
+                            .address_book_sources
+                            .as_deref()
+                            .unwrap_or(&self.address_book.sources),
+                    ),
+                    state_file: chain
+                        .state_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.state.file)),
+                    state_db: chain
+                        .state_db
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.state.db)),
+                    seen_file: chain
+                        .seen_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.state.seen_file)),
+                    reachability_file: chain
+                        .reachability_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.prober.file)),
+                    enrichment_file: chain
+                        .enrichment_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.state.enrichment_file)),
+                    validator_file: chain
+                        .validator_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.validator.file)),
+                    recorder_file: chain
+                        .recorder_file
+                        .clone()
+                        .unwrap_or_else(|| namespaced_file(&name, &self.recorder.file)),
+                    announcement_channel: chain.announcement_channel.clone().unwrap_or_else(|| {
+                        format!("{}-{}", self.discord.announcement_channel, name)
+                    }),
+                    private_channel: chain
+                        .private_channel
+                        .clone()
+                        .unwrap_or_else(|| format!("{}-{}", self.discord.private_channel, name)),
+                    chain_id,
+                    name,
+                }
+            })
+            .collect()
+    }
+
+    /// only monitor the named chains
+    pub fn select_chains(&mut self, names: &[&str]) -> Result<(), ConstellationError> {
+        let profiles = self.profiles();
+        let unknown = names
+            .iter()
+            .filter(|name| !profiles.iter().any(|p| &p.name == *name))
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(ConstellationError::UnknownChain(
+                unknown.join(","),
+                profiles
+                    .iter()
+                    .map(|p| p.name.clone())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ));
+        }
+        if !self.chains.is_empty() {
+            self.chains = self
+                .chains
+                .drain(..)
+                .zip(profiles.iter())
+                .filter(|(_, profile)| names.contains(&profile.name.as_str()))
+                .map(|(chain, _)| chain)
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// turn on exactly the modules listed, and turn off the rest
+    pub fn enable_only(&mut self, modules: &[&str]) {
+        let on = |name: &str| modules.contains(&name);
+
+        self.address_book.enabled = on("address-book");
+        self.bgp.enabled = on("bgp");
+        self.checkpoint.enabled = on("checkpoint");
+        self.retention.enabled = on("retention");
+        self.node_summary.enabled = on("node-summary");
+        self.recorder.enabled = on("recorder");
+        self.geo.enabled = on("geo");
+        self.rpc.enabled = on("rpc");
+        self.prober.enabled = on("prober");
+        self.websocket.enabled = on("websocket");
+        self.oracle.enabled = on("oracle");
+        self.price.enabled = on("price");
+        self.validator.enabled = on("validator");
+        self.discord.enabled = on("discord") || on("discord-gateway");
+        self.web.enabled = on("web");
+    }
+
+    /// modules switched on in the config file
+    pub fn enabled_modules(&self) -> Vec<&'static str> {
+        vec![
+            ("address-book", self.address_book.enabled),
+            ("bgp", self.bgp.enabled),
+            ("checkpoint", self.checkpoint.enabled),
+            ("retention", self.retention.enabled),
+            ("node-summary", self.node_summary.enabled),
+            ("recorder", self.recorder.enabled),
+            ("geo", self.geo.enabled),
+            ("rpc", self.rpc.enabled),
+            ("prober", self.prober.enabled),
+            ("websocket", self.websocket.enabled),
+            ("oracle", self.oracle.enabled),
+            // price check is only available in 'private' builds
+            ("price", self.price.enabled && cfg!(feature = "private")),
+            ("validator", self.validator.enabled),
+            ("discord", self.discord.enabled),
+            ("web", self.web.enabled),
+        ]
+        .into_iter()
+        .filter(|m| m.1)
+        .map(|m| m.0)
+        .collect()
+    }
+
+    /// check the settings make sense, reporting every problem found, not just the first
+    pub fn validate(&self) -> Result<(), ConstellationError> {
+        let mut problems: Vec<String> = vec![];
+        if self.discord.enabled {
+            check_url(&mut problems, "discord.url", &self.discord.url);
+        }
+        let profiles = self.profiles();
+        if profiles.is_empty() {
+            problems.push("at least one chain must be monitored".into());
+        }
+        for (i, profile) in profiles.iter().enumerate() {
+            let key = if self.chains.is_empty() {
+                "chain".to_string()
+            } else {
+                format!("chains[{}]", i)
+            };
+            check_url(&mut problems, &format!("{}.lcd", key), &profile.lcd);
+            check_url(&mut problems, &format!("{}.rpc", key), &profile.rpc);
+            check_url(&mut problems, &format!("{}.fcd", key), &profile.fcd);
+            if self.address_book.enabled {
+                if profile.address_books.is_empty() {
+                    problems.push(format!("{} has no address book sources", key));
+                }
+                for source in &profile.address_books {
+                    if source == "rpc" {
+                        if !self.rpc.enabled {
+                            problems.push(format!(
+                                "{} uses the address book source 'rpc', which needs the rpc module",
+                                key
+                            ));
+                        }
+                    } else if source.contains("://") {
+                        check_url(&mut problems, &format!("{}.address_book", key), source);
+                    }
+                }
+            }
+            if profile.chain_id.is_empty() {
+                problems.push(format!("{}.chain_id must be set", key));
+            }
+            if !profile
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+            {
+                problems.push(format!(
+                    "{}.name '{}' can only contain a-z, 0-9, '-' and '_'",
+                    key, profile.name
+                ));
+            }
+            if profile.state_file.is_empty() {
+                problems.push(format!("state file for chain {} must be set", profile.name));
+            }
+            if profile.seen_file.is_empty() {
+                problems.push(format!("seen file for chain {} must be set", profile.name));
+            }
+            if self.prober.enabled && profile.reachability_file.is_empty() {
+                problems.push(format!(
+                    "reachability file for chain {} must be set",
+                    profile.name
+                ));
+            }
+            if (self.bgp.enabled || self.geo.enabled) && profile.enrichment_file.is_empty() {
+                problems.push(format!(
+                    "enrichment file for chain {} must be set",
+                    profile.name
+                ));
+            }
+            if self.state.backend == StateBackend::Sled && profile.state_db.is_empty() {
+                problems.push(format!("state db for chain {} must be set", profile.name));
+            }
+            if self.recorder.enabled && profile.recorder_file.is_empty() {
+                problems.push(format!(
+                    "recorder file for chain {} must be set",
+                    profile.name
+                ));
+            }
+            if self.validator.enabled && profile.validator_file.is_empty() {
+                problems.push(format!(
+                    "validator file for chain {} must be set",
+                    profile.name
+                ));
+            }
+            if self.discord.enabled
+                && (profile.announcement_channel.is_empty() || profile.private_channel.is_empty())
+            {
+                problems.push(format!(
+                    "discord channels for chain {} must be set",
+                    profile.name
+                ));
+            }
+            for other in &profiles[..i] {
+                if other.name == profile.name {
+                    problems.push(format!(
+                        "chain name '{}' is used more than once",
+                        profile.name
+                    ));
+                }
+                if other.state_file == profile.state_file {
+                    problems.push(format!(
+                        "chains {} and {} share the state file {}",
+                        other.name, profile.name, profile.state_file
+                    ));
+                }
+                if other.seen_file == profile.seen_file {
+                    problems.push(format!(
+                        "chains {} and {} share the seen file {}",
+                        other.name, profile.name, profile.seen_file
+                    ));
+                }
+                if self.prober.enabled && other.reachability_file == profile.reachability_file {
+                    problems.push(format!(
+                        "chains {} and {} share the reachability file {}",
+                        other.name, profile.name, profile.reachability_file
+                    ));
+                }
+                if (self.bgp.enabled || self.geo.enabled)
+                    && other.enrichment_file == profile.enrichment_file
+                {
+                    problems.push(format!(
+                        "chains {} and {} share the enrichment file {}",
+                        other.name, profile.name, profile.enrichment_file
+                    ));
+                }
+                if self.state.backend == StateBackend::Sled && other.state_db == profile.state_db {
+                    problems.push(format!(
+                        "chains {} and {} share the state db {}",
+                        other.name, profile.name, profile.state_db
+                    ));
+                }
+                if self.recorder.enabled && other.recorder_file == profile.recorder_file {
+                    problems.push(format!(
+                        "chains {} and {} share the recorder file {}",
+                        other.name, profile.name, profile.recorder_file
+                    ));
+                }
+                if self.validator.enabled && other.validator_file == profile.validator_file {
+                    problems.push(format!(
+                        "chains {} and {} share the validator file {}",
+                        other.name, profile.name, profile.validator_file
+                    ));
+                }
+            }
+        }
+        for (key, period) in &[
+            ("tick.period_secs", self.tick.period_secs),
+            ("address_book.period_secs", self.address_book.period_secs),
+            ("bgp.period_secs", self.bgp.period_secs),
+            ("checkpoint.period_secs", self.checkpoint.period_secs),
+            ("retention.period_secs", self.retention.period_secs),
+            ("node_summary.period_secs", self.node_summary.period_secs),
+            ("geo.period_secs", self.geo.period_secs),
+            ("rpc.period_secs", self.rpc.period_secs),
+            ("prober.period_secs", self.prober.period_secs),
+            ("validator.period_secs", self.validator.period_secs),
+            ("validator.save_secs", self.validator.save_secs),
+        ] {
+            if *period == 0 {
+                problems.push(format!("{} must be greater than 0", key));
+            }
+        }
+        if self.bgp.enabled && self.bgp.concurrency == 0 {
+            problems.push("bgp.concurrency must be greater than 0".into());
+        }
+        if self.geo.enabled && self.geo.concurrency == 0 {
+            problems.push("geo.concurrency must be greater than 0".into());
+        }
+        for (key, enabled, retry_secs, retry_max_secs) in &[
+            (
+                "bgp",
+                self.bgp.enabled,
+                self.bgp.retry_secs,
+                self.bgp.retry_max_secs,
+            ),
+            (
+                "geo",
+                self.geo.enabled,
+                self.geo.retry_secs,
+                self.geo.retry_max_secs,
+            ),
+        ] {
+            if *enabled && (*retry_secs == 0 || retry_max_secs < retry_secs) {
+                problems.push(format!(
+                    "{}.retry_secs must be greater than 0, and no more than {}.retry_max_secs",
+                    key, key
+                ));
+            }
+        }
+        if self.prober.enabled && (self.prober.concurrency == 0 || self.prober.timeout_ms == 0) {
+            problems.push("prober.concurrency and prober.timeout_ms must be greater than 0".into());
+        }
+        if self.retention.enabled && self.retention.days == 0 {
+            problems.push("retention.days must be greater than 0".into());
+        }
+        if self.checkpoint.generations == 0 {
+            problems.push("checkpoint.generations must be greater than 0".into());
+        }
+        if self.shutdown.deadline_secs == 0 {
+            problems.push("shutdown.deadline_secs must be greater than 0".into());
+        }
+        if self.supervisor.initial_backoff_secs > self.supervisor.max_backoff_secs {
+            problems.push(format!(
+                "supervisor.initial_backoff_secs ({}) is larger than supervisor.max_backoff_secs ({})",
+                self.supervisor.initial_backoff_secs, self.supervisor.max_backoff_secs
+            ));
+        }
+        if self.bgp.enabled
+            && self.bgp.source != AsnSourceKind::Dns
+            && !Path::new(&self.bgp.db_file).exists()
+        {
+            problems.push(format!(
+                "bgp.db_file '{}' not found. see db/README.md",
+                self.bgp.db_file
+            ));
+        }
+        if self.geo.enabled && !Path::new(&self.geo.db_file).exists() {
+            problems.push(format!(
+                "geo.db_file '{}' not found. see db/README.md",
+                self.geo.db_file
+            ));
+        }
+        if self.validator.enabled {
+            if self.validator.abstain_alert_every == 0 {
+                problems.push("validator.abstain_alert_every must be greater than 0".into());
+            }
+            if self.validator.reward_slip_percent < 0.0 {
+                problems.push("validator.reward_slip_percent can't be negative".into());
+            }
+        }
+        if self.discord.enabled && self.discord.token.as_deref().unwrap_or("").is_empty() {
+            problems.push(
+                "discord.token must be set when discord is enabled (or use DISCORD_TOKEN)".into(),
+            );
+        }
+        if self.web.enabled {
+            if let Err(e) = self.web.bind.parse::<SocketAddr>() {
+                problems.push(format!(
+                    "web.bind '{}' is not a valid address:port ({})",
+                    self.web.bind, e
+                ));
+            }
+            if self.web.ready_intervals == 0 {
+                problems.push("web.ready_intervals must be greater than 0".into());
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConstellationError::Config(problems.join("\n  ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the defaults, plus the two things only the user can supply
+    fn usable() -> Config {
+        let mut config = Config::default();
+        config.geo.db_file = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml").into();
+        config.discord.token = Some("token".into());
+        config
+    }
+
+    fn problems(config: &Config) -> Vec<String> {
+        match config.validate() {
+            Ok(()) => vec![],
+            Err(ConstellationError::Config(problems)) => {
+                problems.split("\n  ").map(|p| p.to_string()).collect()
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn chains(toml: &str) -> Vec<ChainProfileConfig> {
+        toml::from_str::<Config>(toml).unwrap().chains
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(
+            problems(&Config::default()),
+            vec![
+                "geo.db_file 'db/GeoLite2-City.mmdb' not found. see db/README.md",
+                "discord.token must be set when discord is enabled (or use DISCORD_TOKEN)",
+            ]
+        );
+        assert!(usable().validate().is_ok());
+        let empty: Config = toml::from_str("").unwrap();
+        assert_eq!(empty.profiles().len(), 1);
+        assert_eq!(empty.profiles()[0].name, "columbus-5");
+        assert_eq!(empty.profiles()[0].state_file, "state.json");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let e = toml::from_str::<Config>("[bgp]\nconcurency = 4\n").unwrap_err();
+        assert!(
+            e.to_string().contains("unknown field `concurency`"),
+            "{}",
+            e
+        );
+        let e = toml::from_str::<Config>("[[chains]]\nstat_file = \"a.json\"\n").unwrap_err();
+        assert!(e.to_string().contains("unknown field `stat_file`"), "{}", e);
+        let e = toml::from_str::<Config>("[chian]\n").unwrap_err();
+        assert!(e.to_string().contains("unknown field `chian`"), "{}", e);
+    }
+
+    #[test]
+    fn namespaces_files_by_chain() {
+        assert_eq!(
+            namespaced_file("bombay-12", "data/state.json"),
+            "data/bombay-12-state.json"
+        );
+        assert_eq!(
+            namespaced_file("bombay-12", "state.json"),
+            "bombay-12-state.json"
+        );
+        assert_eq!(
+            namespaced_file("bombay-12", "/var/lib/constellation/seen.json"),
+            "/var/lib/constellation/bombay-12-seen.json"
+        );
+
+        let mut config = usable();
+        config.state.file = "data/state.json".into();
+        config.chains = chains(
+            r#"
+            [[chains]]
+            chain_id = "columbus-5"
+
+            [[chains]]
+            name = "bombay"
+            chain_id = "bombay-12"
+            lcd = "https://bombay-lcd.terra.dev"
+            seen_file = "bombay-seen.json"
+            "#,
+        );
+        let profiles = config.profiles();
+        assert_eq!(profiles.len(), 2);
+
+        let (columbus, bombay) = (&profiles[0], &profiles[1]);
+        assert_eq!(columbus.name, "columbus-5");
+        assert_eq!(columbus.state_file, "data/columbus-5-state.json");
+        assert_eq!(columbus.lcd, "https://lcd.terra.dev");
+        assert_eq!(columbus.announcement_channel, "announcements-columbus-5");
+
+        assert_eq!(bombay.name, "bombay");
+        assert_eq!(bombay.chain_id, "bombay-12");
+        assert_eq!(bombay.lcd, "https://bombay-lcd.terra.dev");
+        assert_eq!(bombay.state_file, "data/bombay-state.json");
+        assert_eq!(bombay.state_db, "bombay-state.db");
+        assert_eq!(bombay.enrichment_file, "bombay-enrichment.json");
+        // set files are used as they are
+        assert_eq!(bombay.seen_file, "bombay-seen.json");
+        assert_eq!(bombay.private_channel, "private-bombay");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn reports_files_shared_across_chains() {
+        let mut config = usable();
+        config.chains = chains(
+            r#"
+            [[chains]]
+            name = "a"
+            state_file = "state.json"
+            enrichment_file = "enrichment.json"
+
+            [[chains]]
+            name = "b"
+            state_file = "state.json"
+            enrichment_file = "enrichment.json"
+
+            [[chains]]
+            name = "a"
+            "#,
+        );
+        assert_eq!(
+            problems(&config),
+            vec![
+                "chains a and b share the state file state.json",
+                "chains a and b share the enrichment file enrichment.json",
+                "chain name 'a' is used more than once",
+                "chains a and a share the seen file a-seen.json",
+                "chains a and a share the validator file a-validator.json",
+            ]
+        );
+
+        // with bgp & geo off, nothing uses the enrichment file
+        config.chains.truncate(2);
+        config.bgp.enabled = false;
+        config.geo.enabled = false;
+        assert_eq!(
+            problems(&config),
+            vec!["chains a and b share the state file state.json"]
+        );
+    }
+
+    #[test]
+    fn selects_chains_by_name() {
+        let mut config = usable();
+        config.chains = chains(
+            r#"
+            [[chains]]
+            name = "a"
+
+            [[chains]]
+            name = "b"
+
+            [[chains]]
+            name = "c"
+            "#,
+        );
+        match config.clone().select_chains(&["b", "x", "y"]) {
+            Err(ConstellationError::UnknownChain(unknown, known)) => {
+                assert_eq!(unknown, "x,y");
+                assert_eq!(known, "a,b,c");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        config.select_chains(&["c", "a"]).unwrap();
+        let names = config
+            .profiles()
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "c"]);
+
+        // a single chain is known by its chain id
+        let mut config = usable();
+        config.select_chains(&["columbus-5"]).unwrap();
+        assert_eq!(config.profiles().len(), 1);
+        assert!(config.select_chains(&["bombay-12"]).is_err());
+    }
+}
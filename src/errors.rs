@@ -6,4 +6,16 @@ pub enum ConstellationError {
     _ResponseError(String),
     #[error(transparent)]
     URLParseError(#[from] url::ParseError),
+    #[error("Config file {0}: {1}")]
+    ConfigFile(String, String),
+    #[error("Invalid configuration:\n  {0}")]
+    Config(String),
+    #[error("Unknown module(s) {0}. Available modules are: {1}")]
+    UnknownModule(String, String),
+    #[error("Module dependencies form a loop {0}")]
+    ModuleCycle(String),
+    #[error("Unknown chain(s) {0}. Configured chains are: {1}")]
+    UnknownChain(String, String),
+    #[error("Module {0} is already running")]
+    DuplicateModule(String),
 }
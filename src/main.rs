@@ -1,20 +1,25 @@
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-
-use actix::prelude::*;
-
 //use actix_web::dev::Server;
 use dotenv::dotenv;
+use std::process::ExitCode;
 use structopt::StructOpt;
-use tokio::task::JoinHandle;
-
-use constellation_shared::state::{AppState, State, StateVersion};
-//use futures::FutureExt;
-use actix_broker::{Broker, SystemBroker};
-use constellation_shared::MessageStop;
-use std::collections::HashSet;
+use tokio::sync::mpsc;
 
+mod chain;
+mod config;
 mod errors;
+mod migrate;
+mod modules;
+mod peers;
+mod replay;
+mod state;
+mod supervisor;
+
+use chain::ChainHandle;
+use config::Config;
+use constellation_status::StatusRegistry;
+use errors::ConstellationError;
+use modules::{ModuleContext, ModuleScope};
+use supervisor::Supervisor;
 
 /// VERSION number of package
 pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
@@ -22,302 +27,409 @@ pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 pub const NAME: Option<&'static str> = option_env!("CARGO_PKG_NAME");
 #[derive(StructOpt)]
 struct Cli {
+    #[structopt(
+        name = "config",
+        env = "CONSTELLATION_CONFIG",
+        long,
+        help = "config file (defaults to constellation.toml if present)"
+    )]
+    config: Option<String>,
     #[structopt(
         name = "lcd",
         env = "TERRARUST_LCD",
-        default_value = "https://lcd.terra.dev",
         short,
         long = "lcd-client-url",
         help = "https://lcd.terra.dev is main-net"
     )]
     // Terra cli Client daemon
-    lcd_endpoint: String,
+    lcd_endpoint: Option<String>,
     #[structopt(
         name = "rpc",
         about = "RPC endpoint",
         env = "TERRARUST_RPC_ENDPOINT",
-        long,
-        default_value = "http://public-node.terra.dev:26657"
+        long
     )]
-    rpc_endpoint: String,
+    rpc_endpoint: Option<String>,
     #[structopt(
         name = "fcd",
         about = "fcd endpoint",
         env = "TERRARUST_FCD_ENDPOINT",
-        long,
-        default_value = "https://fcd.terra.dev"
+        long
     )]
-    _fcd_endpoint: String,
+    fcd_endpoint: Option<String>,
     #[structopt(
         name = "chain",
         env = "TERRARUST_CHAIN",
-        default_value = "columbus-5",
         long = "chain",
         help = "bombay-12 is testnet, columbus-5 is main-net"
     )]
-    chain_id: String,
+    chain_id: Option<String>,
     #[structopt(
         name = "address-book",
         env = "ADDRESS_BOOK_MAIN",
         long,
-        default_value = "https://network.terra.dev/addrbook.json",
         help = "default address book for columbus"
     )]
     // Terra cli Client daemon
-    address_book: String,
+    address_book: Option<String>,
 
     #[structopt(
         name = "state-file",
-        default_value = "state.json",
         long,
         help = "where to store state to survive restarts"
     )]
     // state file for checkpoints/backups
-    state_file: String,
+    state_file: Option<String>,
+    #[structopt(name = "geodb-file", long, help = "maxmind city db file")]
+    // state file for checkpoints/backups
+    db_file: Option<String>,
     #[structopt(
-        name = "geodb-file",
-        default_value = "db/GeoLite2-City.mmdb",
+        name = "chains",
+        env = "CONSTELLATION_CHAINS",
         long,
-        help = "maxmind city db file"
+        help = "which of the configured chains to watch (comma separated names). defaults to all of them"
     )]
-    // state file for checkpoints/backups
-    db_file: String,
+    chains: Option<String>,
     #[structopt(
         name = "run-modules",
         env = "CONSTELLATION_RUN",
         long,
-        default_value = "all",
-        help = "what modules to run"
+        help = "what modules to run. overrides the enabled flags in the config file"
     )]
-    run_modules: String,
+    run_modules: Option<String>,
     #[structopt(
         name = "discord-url",
         env = "DISCORD_URL",
         long,
-        default_value = "https://discordapp.com/",
         help = "endpoint for discord api"
     )]
-    discord_url: String,
+    discord_url: Option<String>,
     #[structopt(
         name = "discord-token",
         env = "DISCORD_TOKEN",
         long,
         help = "token for discord api"
     )]
-    discord_token: String,
+    discord_token: Option<String>,
     #[structopt(
         name = "discord-retries",
         env = "DISCORD_RETRIES",
-        default_value = "4",
         long,
         help = "#retries for discord api, when we hit discord API caps"
     )]
-    discord_retries: usize,
+    discord_retries: Option<usize>,
+    #[structopt(
+        name = "web-bind",
+        env = "CONSTELLATION_WEB_BIND",
+        long,
+        help = "address:port for the web server to listen on"
+    )]
+    web_bind: Option<String>,
 
     #[structopt(name = "clean-start", long, help = "clean start, delete state")]
     clean: Option<bool>,
+    #[structopt(
+        name = "migrate-state",
+        long,
+        help = "report what loading the saved state & validator files would migrate, then exit. nothing is written"
+    )]
+    migrate_state: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-async fn run() -> anyhow::Result<()> {
-    println!("Starting ...");
-    let cli: Cli = Cli::from_args();
-    let discord_token = cli.discord_token;
-    //env::var("DISCORD_TOKEN").expect("Expected a discord token in the environment");
-    let discord_url = cli.discord_url; // env::var("DISCORD_URL").expect("Expected a discord URL in the environment");
-
-    let discord_retries: usize = cli.discord_retries;
-    let mut modules: HashSet<_> = cli.run_modules.split(',').collect();
-    if modules.contains("validator") {
-        modules.insert("websocket");
-    }
-    if modules.contains("price") {
-        modules.insert("oracle");
-        modules.insert("websocket");
-    }
+#[derive(StructOpt)]
+enum Command {
+    /// feed a broker recording back through the validator & discord actors, without connecting to the chain
+    Replay(replay::ReplayOptions),
+    /// inspect, diff, export or prune checkpoint files
+    State(state::StateCommand),
+    /// recommend persistent_peers from what the prober, bgp & geo collected
+    Peers(peers::PeersOptions),
+    /// handshake with one node and show what it says about itself
+    Handshake(peers::HandshakeOptions),
+}
 
-    for module in modules.iter() {
-        log::info!("Module {} enabled", module)
+impl Cli {
+    /// environment/command line settings win over whatever the config file says
+    fn apply(&self, config: &mut Config) {
+        if let Some(db_file) = &self.db_file {
+            config.geo.db_file = db_file.clone();
+        }
+        if let Some(discord_url) = &self.discord_url {
+            config.discord.url = discord_url.clone();
+        }
+        if let Some(discord_token) = &self.discord_token {
+            config.discord.token = Some(discord_token.clone());
+        }
+        if let Some(discord_retries) = self.discord_retries {
+            config.discord.retries = discord_retries;
+        }
+        if let Some(web_bind) = &self.web_bind {
+            config.web.bind = web_bind.clone();
+        }
     }
 
-    let state_data = if cli.clean.unwrap_or(false) {
-        State::new()?
-    } else {
-        match State::restore(&cli.state_file) {
-            Ok(versioned) => match versioned {
-                StateVersion::StateVersion1(state_v1) => state_v1,
-            },
-            Err(e) => {
-                log::info!(
-                    "State file {} unable to be read. ({}) starting new",
-                    cli.state_file,
-                    e
-                );
-                State::new()?
+    /// the endpoint, chain & state file settings describe a single chain. with `[[chains]]`
+    /// they go to the one selected, and are refused when more than one is
+    fn apply_chain(&self, config: &mut Config) -> Result<(), ConstellationError> {
+        if config.chains.is_empty() {
+            if let Some(lcd) = &self.lcd_endpoint {
+                config.chain.lcd = lcd.clone();
             }
+            if let Some(rpc) = &self.rpc_endpoint {
+                config.chain.rpc = rpc.clone();
+            }
+            if let Some(fcd) = &self.fcd_endpoint {
+                config.chain.fcd = fcd.clone();
+            }
+            if let Some(chain_id) = &self.chain_id {
+                config.chain.chain_id = chain_id.clone();
+            }
+            if let Some(address_book) = &self.address_book {
+                config.address_book.url = address_book.clone();
+            }
+            if let Some(state_file) = &self.state_file {
+                config.state.file = state_file.clone();
+            }
+            return Ok(());
         }
-    };
-    let state: AppState = Arc::new(Mutex::new(state_data));
-    let mut tasks: Vec<JoinHandle<_>> = vec![actix_rt::spawn(constellation_shared::run(
-        Duration::from_secs(60 * 5),
-    ))];
+        let given = [
+            ("--lcd-client-url", &self.lcd_endpoint),
+            ("--rpc-endpoint", &self.rpc_endpoint),
+            ("--fcd-endpoint", &self.fcd_endpoint),
+            ("--chain", &self.chain_id),
+            ("--address-book", &self.address_book),
+            ("--state-file", &self.state_file),
+        ]
+        .iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(flag, _)| *flag)
+        .collect::<Vec<_>>();
+        if given.is_empty() {
+            return Ok(());
+        }
+        if config.chains.len() > 1 {
+            return Err(ConstellationError::Config(format!(
+                "{} would apply to all {} chains. pick one with --chains",
+                given.join(", "),
+                config.chains.len()
+            )));
+        }
+        let chain = &mut config.chains[0];
+        if let Some(lcd) = &self.lcd_endpoint {
+            chain.lcd = Some(lcd.clone());
+        }
+        if let Some(rpc) = &self.rpc_endpoint {
+            chain.rpc = Some(rpc.clone());
+        }
+        if let Some(fcd) = &self.fcd_endpoint {
+            chain.fcd = Some(fcd.clone());
+        }
+        if let Some(chain_id) = &self.chain_id {
+            chain.chain_id = Some(chain_id.clone());
+        }
+        if let Some(address_book) = &self.address_book {
+            chain.address_book = Some(address_book.clone());
+        }
+        if let Some(state_file) = &self.state_file {
+            chain.state_file = Some(state_file.clone());
+        }
+        Ok(())
+    }
+}
 
-    if modules.contains("all") || modules.contains("address-book") {
-        tasks.push(actix_rt::spawn(constellation_address_book::run(
-            //tasks.push(tokio::task::spawn(constellation_address_book::run(
-            state.clone(),
-            Duration::from_secs(60 * 5),
-            cli.address_book,
-        )));
+async fn run() -> anyhow::Result<()> {
+    println!("Starting ...");
+    let cli: Cli = Cli::from_args();
+    let mut config = Config::load(cli.config.as_deref())?;
+    cli.apply(&mut config);
+    if let Some(chains) = &cli.chains {
+        let names = chains
+            .split(',')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        config.select_chains(&names)?;
+    }
+    cli.apply_chain(&mut config)?;
+    let clean = cli.clean.unwrap_or(false);
+    if let Some(Command::Replay(options)) = &cli.command {
+        return replay::run(&config, options).await;
     }
-    if modules.contains("all") || modules.contains("bgp") {
-        tasks.push(actix_rt::spawn(constellation_bgp::run(
-            state.clone(),
-            Duration::from_secs(60 * 5),
-        )));
+
+    let mut registry = modules::registry();
+    let requested = match &cli.run_modules {
+        Some(run_modules) => run_modules
+            .split(',')
+            .map(|m| m.trim())
+            .filter(|m| !m.is_empty())
+            .collect::<Vec<_>>(),
+        None => config.enabled_modules(),
+    };
+    let run_order = registry.resolve(&requested)?;
+    for module in &run_order {
+        if requested.contains(module) || requested.contains(&"all") {
+            log::info!("Module {} enabled", module)
+        } else {
+            log::info!(
+                "Module {} enabled, as it is needed by another module",
+                module
+            )
+        }
     }
-    if modules.contains("all") || modules.contains("checkpoint") {
-        tasks.push(actix_rt::spawn(constellation_state_checkpoint::run(
-            state.clone(),
-            Duration::from_secs(60),
-            cli.state_file,
-        )));
+    config.enable_only(&run_order);
+    if cli.migrate_state {
+        return migrate::dry_run(&config);
     }
-    if modules.contains("all") || modules.contains("geo") {
-        tasks.push(actix_rt::spawn(constellation_geo::run(
-            state.clone(),
-            Duration::from_secs(60 * 5),
-            cli.db_file,
-        )));
+    if let Some(Command::State(command)) = &cli.command {
+        return state::run(&config, command);
     }
-    if modules.contains("all") || modules.contains("rpc") {
-        tasks.push(actix_rt::spawn(constellation_rpc_crawler::run(
-            state.clone(),
-            Duration::from_secs(60 * 5),
-            cli.chain_id.clone(),
-            cli.lcd_endpoint.clone(),
-            cli.rpc_endpoint.clone(),
-        )));
+    if let Some(Command::Peers(options)) = &cli.command {
+        return peers::run(&config, options);
     }
-    if modules.contains("all") || modules.contains("websocket") {
-        tasks.push(actix_rt::spawn(constellation_web_socket::run(
-            cli.clean.unwrap_or(false),
-            cli.lcd_endpoint.clone(),
-            cli.chain_id.clone(),
-            cli.rpc_endpoint.clone(),
-        )));
+    if let Some(Command::Handshake(options)) = &cli.command {
+        return peers::handshake_with(&config, options);
     }
-    if modules.contains("all") || modules.contains("oracle") {
-        let oracle_actor = constellation_price_oracle::actor::OracleActor::create(
-            cli.clean.unwrap_or(false),
-            &cli.lcd_endpoint,
-            &cli.chain_id,
-        )
-        .await?;
+    config.validate()?;
 
-        oracle_actor.start();
+    // each chain runs on a thread of its own, the process wide modules (web) run here
+    let status = StatusRegistry::default();
+    let (finished_tx, mut finished_rx) = mpsc::unbounded_channel();
+    let mut chains: Vec<ChainHandle> = vec![];
+    let mut chain_states = vec![];
+    let mut first = None;
+    for profile in config.profiles() {
+        let chain_state = chain::restore_state(&config, &profile, clean)?;
+        chains.push(chain::spawn(
+            config.clone(),
+            profile.clone(),
+            chain_state.clone(),
+            run_order.clone(),
+            clean,
+            status.clone(),
+            finished_tx.clone(),
+        )?);
+        chain_states.push((profile.clone(), chain_state.clone()));
+        first.get_or_insert((profile, chain_state));
     }
-    #[cfg(feature = "private")]
-    {
-        if modules.contains("all") || modules.contains("price") {
-            log::info!("Starting private price check module");
-            let price_actor = constellation_price_check::actor::PriceCheckActor::create(
-                cli.clean.unwrap_or(false),
-                &cli.lcd_endpoint,
-                &cli.chain_id,
-            )
-            .await?;
+    drop(finished_tx);
+
+    let (chain, chain_state) = first.ok_or_else(|| anyhow::anyhow!("no chains to watch"))?;
+    let supervisor = Supervisor::new("constellation", config.supervisor.policy());
+    let mut ctx = ModuleContext {
+        config,
+        chain,
+        state: chain_state.state,
+        chains: chain_states,
+        store: chain_state.store,
+        seen: chain_state.seen,
+        crawled: chain_state.crawled,
+        reachability: chain_state.reachability,
+        enrichment: chain_state.enrichment,
+        discord: chain_state.discord,
+        status,
+        supervisor,
+        clean,
+    };
+    let mut result = registry
+        .start(&run_order, ModuleScope::Process, &mut ctx)
+        .await;
 
-            price_actor.start();
+    if result.is_ok() {
+        let has_process_modules = registry.has_started();
+        let supervisor = &mut ctx.supervisor;
+        let process_stopped = async move {
+            if has_process_modules {
+                supervisor.join().await
+            } else {
+                futures::future::pending().await
+            }
+        };
+        tokio::pin!(process_stopped);
+        let signal = shutdown_signal();
+        tokio::pin!(signal);
+        let mut running = chains.len();
+        loop {
+            tokio::select! {
+                received = &mut signal => {
+                    log::warn!("{} ..terminating", received);
+                    break;
+                }
+                _ = &mut process_stopped => {
+                    log::error!("All process wide modules have stopped");
+                    break;
+                }
+                finished = finished_rx.recv() => match finished {
+                    Some((name, chain_result)) => {
+                        running -= 1;
+                        match chain_result {
+                            Ok(_) => log::error!("Chain {} has stopped", name),
+                            Err(e) => {
+                                log::error!("Chain {} failed: {}", name, e);
+                                result = Err(e);
+                            }
+                        }
+                        if running == 0 {
+                            log::error!("No chains are running");
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
         }
     }
 
-    if modules.contains("all") || modules.contains("validator") {
-        log::info!("Validator turned on");
-        tasks.push(actix_rt::spawn(constellation_validator::run(
-            state.clone(),
-            Duration::from_secs(60 * 5),
-            cli.chain_id.clone(),
-            cli.lcd_endpoint.clone(),
-        )));
-        let validator_actor = constellation_validator::actor::ValidatorActor::create(
-            cli.clean.unwrap_or(false),
-            &cli.lcd_endpoint,
-            &cli.chain_id,
-        )
-        .await?;
-        validator_actor.start();
+    let deadline = ctx.config.shutdown.deadline();
+    let stopping = async {
+        for chain in chains.iter_mut() {
+            log::info!("Chain {} stopping", chain.name);
+            chain.stop();
+        }
+        chain::shutdown(&mut registry, &mut ctx).await;
+        // the chains report back once they have saved their state
+        while let Some((name, chain_result)) = finished_rx.recv().await {
+            if let Err(e) = chain_result {
+                log::error!("Chain {} failed: {}", name, e);
+            }
+        }
+    };
+    if tokio::time::timeout(deadline, stopping).await.is_err() {
+        log::error!(
+            "Shutdown took longer than {}s, some state may not have been saved",
+            deadline.as_secs()
+        );
+        std::process::exit(1);
     }
+    log::info!("Shutdown complete");
 
-    if modules.contains("all") || modules.contains("discord") {
-        let discord_actor = constellation_discord::actor::DiscordValidatorActor::create(
-            &discord_token,
-            &discord_url,
-            discord_retries,
-        )
-        .await?;
-        discord_actor.start();
-
-        let bot = actix_rt::spawn(constellation_discord::run(
-            state.clone(),
-            discord_token.clone(),
-            //  discord_category_name.clone(),
-            discord_url.clone(),
-            discord_retries,
-        ));
+    result
+}
 
-        tasks.push(bot);
-        //bot.await?;
-    }
-    if modules.contains("all") || modules.contains("web") {
-        let web_join = actix_rt::spawn(constellation_web::run(
-            state.clone(),
-            //  tx_web,
-            NAME.unwrap_or("constellation"),
-            VERSION.unwrap_or("dev"),
-        ));
-        tasks.push(web_join);
+/// wait for ^C, or a SIGTERM (which is how systemd asks us to stop)
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Could not register SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "^c",
+            _ = terminate.recv() => "SIGTERM",
+        }
     }
-    // TODO - respawn failed tasks
-
-    tokio::spawn(async move {
+    #[cfg(not(unix))]
+    {
         tokio::signal::ctrl_c()
             .await
             .expect("Could not register ctrl+c handler");
-        log::error!("^c ..terminating");
-        Broker::<SystemBroker>::issue_async(MessageStop {});
-        std::process::exit(-1);
-    });
-
-    let returns = futures::future::join_all(tasks).await;
-    Broker::<SystemBroker>::issue_async(MessageStop {});
-    for result in returns {
-        if let Err(e) = result {
-            log::error!("task failed? {}", e)
-        }
+        "^c"
     }
-    /*
-        returns
-            .iter()
-            .for_each(|task_fut_result| match task_fut_result {
-                Ok(task) => match task {
-                    /* this would be where something inside the task killed itself */
-                    Ok(_task_return) => {}
-                    Err(e) => {
-                        log::error!("Task internal fail: {}", e)
-                    }
-                },
-                Err(e) => {
-                    log::error!("Task fail: {}", e)
-                }
-            });
-    */
-    //   web_join.await?;
-
-    Ok(())
 }
 
 #[actix_web::main]
-async fn main() {
+async fn main() -> ExitCode {
     dotenv().ok(); // this fails if .env isn't present. It is safe to be ignored
     env_logger::init();
     if let Err(ref err) = run().await {
@@ -325,5 +437,7 @@ async fn main() {
         err.chain()
             .skip(1)
             .for_each(|cause| log::error!("because: {}", cause));
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use constellation_state_checkpoint::generation_file;
+use constellation_validator::actor::ValidatorActor;
+
+use crate::config::Config;
+
+/// report what restoring each chain's saved files would migrate. nothing is written;
+/// the migrated versions are saved by the next checkpoint once constellation runs
+pub fn dry_run(config: &Config) -> anyhow::Result<()> {
+    let mut failed = 0;
+    for profile in config.profiles() {
+        println!("Chain {}", profile.name);
+        let mut found = false;
+        for generation in 0..config.checkpoint.generations.max(1) {
+            let file = generation_file(&profile.state_file, generation);
+            if !Path::new(&file).exists() {
+                continue;
+            }
+            found = true;
+            match constellation_state_checkpoint::check(&file) {
+                Ok(report) => println!("  {}: {}", file, report),
+                Err(e) => {
+                    println!("  {}: FAILED {}", file, e);
+                    failed += 1;
+                }
+            }
+        }
+        if !found {
+            println!("  {}: not found", profile.state_file);
+        }
+        if Path::new(&profile.validator_file).exists() {
+            match ValidatorActor::load(&profile.validator_file) {
+                Ok((_, report)) => println!("  {}: {}", profile.validator_file, report),
+                Err(e) => {
+                    println!("  {}: FAILED {}", profile.validator_file, e);
+                    failed += 1;
+                }
+            }
+        } else {
+            println!("  {}: not found", profile.validator_file);
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} file(s) could not be migrated", failed)
+    }
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use constellation_address_book::CrawledPeers;
+use constellation_discord::actor::DiscordRoute;
+use constellation_enrich::AppEnrichment;
+use constellation_prober::AppReachability;
+use constellation_state::{AppSightings, SharedState};
+use constellation_status::StatusRegistry;
+use constellation_store::Store;
+
+use crate::chain::ChainState;
+use crate::config::{ChainProfile, Config};
+use crate::errors::ConstellationError;
+use crate::supervisor::Supervisor;
+
+mod builtin;
+
+pub use builtin::registry;
+
+/// everything a module needs to get itself going
+pub struct ModuleContext {
+    pub config: Config,
+    /// the chain being watched. for process wide modules this is the first chain
+    pub chain: ChainProfile,
+    pub state: SharedState,
+    /// every chain being watched. chain modules only see their own
+    pub chains: Vec<(ChainProfile, ChainState)>,
+    /// where the chain's state is kept as it changes, with the sled backend
+    pub store: Option<Store>,
+    /// when the chain's nodes & IPs were first and last seen
+    pub seen: AppSightings,
+    /// peers the RPC crawler found, for the address book
+    pub crawled: CrawledPeers,
+    /// how each node answered the prober
+    pub reachability: AppReachability,
+    /// what the bgp & geo lookups remember between runs
+    pub enrichment: AppEnrichment,
+    /// where the discord gateway finds the chain's discord actor
+    pub discord: DiscordRoute,
+    /// where modules report how they are getting on
+    pub status: StatusRegistry,
+    pub supervisor: Supervisor,
+    /// clean start, ignore saved state
+    pub clean: bool,
+}
+
+/// where a module runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModuleScope {
+    /// once for each chain, alongside that chain's other modules (and broker)
+    Chain,
+    /// once for the whole process, covering every chain
+    Process,
+}
+
+/// a piece of constellation that can be turned on by name (via CONSTELLATION_RUN or the config file)
+#[async_trait(?Send)]
+pub trait ConstellationModule {
+    /// the name used to turn the module on
+    fn name(&self) -> &'static str;
+    /// modules that need to be running for this one to work. they will be started first
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn scope(&self) -> ModuleScope {
+        ModuleScope::Chain
+    }
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()>;
+    /// the default stops the task the module spawned with its name on the supervisor,
+    /// giving it until the shutdown deadline to finish
+    async fn stop(&mut self, ctx: &mut ModuleContext) {
+        ctx.supervisor
+            .stop(self.name(), ctx.config.shutdown.deadline())
+            .await
+    }
+}
+
+/// the set of modules constellation knows how to run
+#[derive(Default)]
+pub struct ModuleRegistry {
+    modules: Vec<Box<dyn ConstellationModule>>,
+    started: Vec<&'static str>,
+}
+
+impl ModuleRegistry {
+    pub fn register(&mut self, module: Box<dyn ConstellationModule>) {
+        self.modules.push(module);
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.modules.iter().map(|m| m.name()).collect()
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn ConstellationModule> {
+        self.modules
+            .iter()
+            .find(|m| m.name() == name)
+            .map(|m| m.as_ref())
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Box<dyn ConstellationModule>> {
+        self.modules.iter_mut().find(|m| m.name() == name)
+    }
+
+    /// work out what needs to run for the requested modules, in the order they need to start.
+    /// dependencies come before the modules that need them. 'all' requests every module.
+    pub fn resolve(&self, requested: &[&str]) -> Result<Vec<&'static str>, ConstellationError> {
+        let requested = if requested.contains(&"all") {
+            self.names()
+        } else {
+            let unknown = requested
+                .iter()
+                .filter(|name| self.get(name).is_none())
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                return Err(ConstellationError::UnknownModule(
+                    unknown.join(","),
+                    self.names().join(","),
+                ));
+            }
+            requested
+                .iter()
+                .filter_map(|name| self.get(name).map(|m| m.name()))
+                .collect()
+        };
+        let mut order: Vec<&'static str> = vec![];
+        let mut visiting: Vec<&'static str> = vec![];
+        for name in requested {
+            self.visit(name, &mut visiting, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        name: &'static str,
+        visiting: &mut Vec<&'static str>,
+        order: &mut Vec<&'static str>,
+    ) -> Result<(), ConstellationError> {
+        if order.contains(&name) {
+            return Ok(());
+        }
+        if visiting.contains(&name) {
+            visiting.push(name);
+            return Err(ConstellationError::ModuleCycle(visiting.join(" -> ")));
+        }
+        let module = match self.get(name) {
+            Some(m) => m,
+            None => {
+                return Err(ConstellationError::UnknownModule(
+                    format!("{} (needed by {})", name, visiting.join(",")),
+                    self.names().join(","),
+                ))
+            }
+        };
+        visiting.push(name);
+        for dependency in module.dependencies() {
+            self.visit(*dependency, visiting, order)?;
+        }
+        visiting.pop();
+        order.push(name);
+        Ok(())
+    }
+
+    /// start the modules of the given scope, in the order given (see `resolve`)
+    pub async fn start(
+        &mut self,
+        order: &[&'static str],
+        scope: ModuleScope,
+        ctx: &mut ModuleContext,
+    ) -> anyhow::Result<()> {
+        let mut seen: HashSet<&'static str> = HashSet::new();
+        for name in order.iter().copied() {
+            if !seen.insert(name) {
+                continue;
+            }
+            if let Some(module) = self.get_mut(name) {
+                if module.scope() != scope {
+                    continue;
+                }
+                log::info!("Module {} starting", name);
+                module.start(ctx).await?;
+                self.started.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// have any modules been started (and not stopped)
+    pub fn has_started(&self) -> bool {
+        !self.started.is_empty()
+    }
+
+    /// stop the started modules, in the reverse order they were started
+    pub async fn stop(&mut self, ctx: &mut ModuleContext) {
+        while let Some(name) = self.started.pop() {
+            if let Some(module) = self.get_mut(name) {
+                module.stop(ctx).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a module that only has a name and dependencies
+    struct Stub(&'static str, &'static [&'static str]);
+
+    #[async_trait(?Send)]
+    impl ConstellationModule for Stub {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.1
+        }
+        async fn start(&mut self, _ctx: &mut ModuleContext) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stubs(modules: &[(&'static str, &'static [&'static str])]) -> ModuleRegistry {
+        let mut registry = ModuleRegistry::default();
+        for (name, dependencies) in modules {
+            registry.register(Box::new(Stub(name, dependencies)));
+        }
+        registry
+    }
+
+    fn chain_modules() -> ModuleRegistry {
+        stubs(&[
+            ("websocket", &[]),
+            ("oracle", &[]),
+            ("price", &["oracle", "websocket"]),
+            ("validator", &["websocket"]),
+            ("discord-gateway", &[]),
+            ("discord", &["discord-gateway"]),
+        ])
+    }
+
+    #[test]
+    fn rejects_unknown_modules() {
+        match chain_modules().resolve(&["websocket", "nope", "missing"]) {
+            Err(ConstellationError::UnknownModule(unknown, available)) => {
+                assert_eq!(unknown, "nope,missing");
+                assert_eq!(
+                    available,
+                    "websocket,oracle,price,validator,discord-gateway,discord"
+                );
+            }
+            other => panic!("expected UnknownModule, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_an_unknown_dependency() {
+        match stubs(&[("a", &["ghost"])]).resolve(&["a"]) {
+            Err(ConstellationError::UnknownModule(unknown, _)) => {
+                assert_eq!(unknown, "ghost (needed by a)")
+            }
+            other => panic!("expected UnknownModule, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let registry = stubs(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &["d"])]);
+        match registry.resolve(&["a"]) {
+            Err(ConstellationError::ModuleCycle(cycle)) => assert_eq!(cycle, "a -> b -> c -> a"),
+            other => panic!("expected ModuleCycle, got {:?}", other),
+        }
+        match registry.resolve(&["d"]) {
+            Err(ConstellationError::ModuleCycle(cycle)) => assert_eq!(cycle, "d -> d"),
+            other => panic!("expected ModuleCycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn starts_dependencies_first() {
+        let registry = chain_modules();
+        assert_eq!(
+            registry.resolve(&["validator"]).unwrap(),
+            vec!["websocket", "validator"]
+        );
+        // each module once, however many need it
+        assert_eq!(
+            registry
+                .resolve(&["price", "validator", "websocket"])
+                .unwrap(),
+            vec!["oracle", "websocket", "price", "validator"]
+        );
+        // a diamond that shares a dependency still only has it once
+        let diamond = stubs(&[("a", &["b", "c"]), ("b", &["d"]), ("c", &["d"]), ("d", &[])]);
+        assert_eq!(diamond.resolve(&["a"]).unwrap(), vec!["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn resolves_all_in_dependency_order() {
+        let order = chain_modules().resolve(&["all"]).unwrap();
+        assert_eq!(order.len(), 6);
+        let position = |name| order.iter().position(|m| *m == name).unwrap();
+        assert!(position("websocket") < position("validator"));
+        assert!(position("oracle") < position("price"));
+        assert!(position("discord-gateway") < position("discord"));
+    }
+
+    #[test]
+    fn builtin_validator_pulls_in_websocket() {
+        assert_eq!(
+            registry().resolve(&["validator"]).unwrap(),
+            vec!["websocket", "validator"]
+        );
+    }
+}
@@ -0,0 +1,637 @@
+use std::sync::{Arc, PoisonError};
+
+use actix::prelude::*;
+use async_trait::async_trait;
+use constellation_address_book::Source;
+use constellation_bgp::{AsnSource, BgpSettings, CymruDns, MmdbAsn, TsvAsn};
+use constellation_discord::actor::{DiscordValidatorActor, GatewayRelay, MessageDrain};
+use constellation_geo::GeoSettings;
+use constellation_node_events::NodeSummaryActor;
+use constellation_prober::ProberSettings;
+use constellation_recorder::actor::RecorderActor;
+use constellation_shared::MessageStop;
+use constellation_validator::actor::{ValidatorActor, ValidatorSettings};
+use constellation_web::WebChain;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+
+use super::{ConstellationModule, ModuleContext, ModuleRegistry, ModuleScope};
+use crate::config::AsnSourceKind;
+use crate::supervisor::until_stopped;
+use crate::{NAME, VERSION};
+
+/// all the modules constellation ships with
+pub fn registry() -> ModuleRegistry {
+    let mut registry = ModuleRegistry::default();
+    // the recorder goes first, so it hears everything the others say
+    registry.register(Box::new(RecorderModule));
+    // before the address book, so it hears about the nodes found on the first run
+    registry.register(Box::new(NodeSummaryModule));
+    registry.register(Box::new(AddressBookModule));
+    registry.register(Box::new(BgpModule));
+    registry.register(Box::new(CheckpointModule));
+    registry.register(Box::new(RetentionModule));
+    registry.register(Box::new(GeoModule));
+    registry.register(Box::new(RpcModule));
+    registry.register(Box::new(ProberModule));
+    registry.register(Box::new(WebSocketModule));
+    registry.register(Box::new(OracleModule));
+    #[cfg(feature = "private")]
+    registry.register(Box::new(PriceModule));
+    registry.register(Box::new(ValidatorModule::default()));
+    registry.register(Box::new(DiscordGatewayModule));
+    registry.register(Box::new(DiscordModule::default()));
+    registry.register(Box::new(WebModule));
+    registry
+}
+
+/// stops itself on MessageStop, after everything else has shut down
+pub struct RecorderModule;
+#[async_trait(?Send)]
+impl ConstellationModule for RecorderModule {
+    fn name(&self) -> &'static str {
+        "recorder"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let recorder = RecorderActor::create(&ctx.chain.recorder_file, &ctx.chain.name)?;
+        recorder.start();
+        Ok(())
+    }
+}
+
+pub struct AddressBookModule;
+#[async_trait(?Send)]
+impl ConstellationModule for AddressBookModule {
+    fn name(&self) -> &'static str {
+        "address-book"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let period = ctx.config.address_book.period();
+        let sources = ctx
+            .chain
+            .address_books
+            .iter()
+            .map(|source| Source::parse(source))
+            .collect::<Vec<_>>();
+        let crawled = ctx.crawled.clone();
+        let seen = ctx.seen.clone();
+        let store = ctx.store.clone();
+        let status = ctx.status.reporter(&ctx.chain.name, self.name(), period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_address_book::run(
+                state.clone(),
+                period,
+                sources.clone(),
+                crawled.clone(),
+                seen.clone(),
+                store.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct BgpModule;
+#[async_trait(?Send)]
+impl ConstellationModule for BgpModule {
+    fn name(&self) -> &'static str {
+        "bgp"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let bgp = &ctx.config.bgp;
+        // local files aren't limited
+        let source: Arc<dyn AsnSource> = match bgp.source {
+            AsnSourceKind::Dns => {
+                Arc::new(CymruDns::new(bgp.nameserver.as_deref(), bgp.rate_per_sec)?)
+            }
+            AsnSourceKind::Tsv => Arc::new(TsvAsn::open(&bgp.db_file)?),
+            AsnSourceKind::Mmdb => Arc::new(MmdbAsn::open(&bgp.db_file)?),
+        };
+        let settings = BgpSettings {
+            period: bgp.period(),
+            concurrency: bgp.concurrency,
+            refresh: bgp.refresh(),
+            enrichment_file: ctx.chain.enrichment_file.clone(),
+            chain: ctx.chain.chain_id.clone(),
+        };
+        let enrichment = ctx.enrichment.clone();
+        let store = ctx.store.clone();
+        let status = ctx
+            .status
+            .reporter(&ctx.chain.name, self.name(), settings.period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_bgp::run(
+                state.clone(),
+                source.clone(),
+                enrichment.clone(),
+                store.clone(),
+                settings.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct CheckpointModule;
+#[async_trait(?Send)]
+impl ConstellationModule for CheckpointModule {
+    fn name(&self) -> &'static str {
+        "checkpoint"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let seen = ctx.seen.clone();
+        let period = ctx.config.checkpoint.period();
+        let state_file = ctx.chain.state_file.clone();
+        let seen_file = ctx.chain.seen_file.clone();
+        let generations = ctx.config.checkpoint.generations;
+        let status = ctx.status.reporter(&ctx.chain.name, self.name(), period);
+        // the modules write to the store as they go. all that's left is making sure it reaches the disk
+        if let Some(store) = ctx.store.clone() {
+            ctx.supervisor.spawn(self.name(), move |shutdown| {
+                constellation_state_checkpoint::run_store(
+                    store.clone(),
+                    seen.clone(),
+                    period,
+                    seen_file.clone(),
+                    status.clone(),
+                    shutdown,
+                )
+            })?;
+            return Ok(());
+        }
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_state_checkpoint::run(
+                state.clone(),
+                seen.clone(),
+                period,
+                state_file.clone(),
+                seen_file.clone(),
+                generations,
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+
+    async fn stop(&mut self, ctx: &mut ModuleContext) {
+        ctx.supervisor
+            .stop(self.name(), ctx.config.shutdown.deadline())
+            .await;
+        if let Err(e) =
+            constellation_state_checkpoint::save_sightings(&ctx.seen, &ctx.chain.seen_file)
+        {
+            log::error!("Unable to save {} {}", ctx.chain.seen_file, e)
+        }
+        if let Some(store) = &ctx.store {
+            log::info!("Flushing state db {}", ctx.chain.state_db);
+            if let Err(e) = store.flush() {
+                log::error!("Unable to flush state db {} {}", ctx.chain.state_db, e)
+            }
+            return;
+        }
+        log::info!("Saving final checkpoint {}", ctx.chain.state_file);
+        if let Err(e) = constellation_state_checkpoint::save(
+            &ctx.state,
+            &ctx.chain.state_file,
+            ctx.config.checkpoint.generations,
+        ) {
+            log::error!(
+                "Unable to save final checkpoint {} {}",
+                ctx.chain.state_file,
+                e
+            )
+        }
+    }
+}
+
+/// counts the address book's node events, and announces them every period
+pub struct NodeSummaryModule;
+#[async_trait(?Send)]
+impl ConstellationModule for NodeSummaryModule {
+    fn name(&self) -> &'static str {
+        "node-summary"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        NodeSummaryActor::new(&ctx.chain.chain_id, ctx.config.node_summary.period()).start();
+        Ok(())
+    }
+}
+
+/// prunes nodes & IPs that haven't been seen for `retention.days`
+pub struct RetentionModule;
+#[async_trait(?Send)]
+impl ConstellationModule for RetentionModule {
+    fn name(&self) -> &'static str {
+        "retention"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let seen = ctx.seen.clone();
+        let period = ctx.config.retention.period();
+        let retention = ctx.config.retention.retention();
+        let store = ctx.store.clone();
+        let status = ctx.status.reporter(&ctx.chain.name, self.name(), period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_state::run(
+                state.clone(),
+                seen.clone(),
+                period,
+                retention,
+                store.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct GeoModule;
+#[async_trait(?Send)]
+impl ConstellationModule for GeoModule {
+    fn name(&self) -> &'static str {
+        "geo"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let settings = GeoSettings {
+            period: ctx.config.geo.period(),
+            db_file: ctx.config.geo.db_file.clone(),
+            concurrency: ctx.config.geo.concurrency,
+            refresh: ctx.config.geo.refresh(),
+            enrichment_file: ctx.chain.enrichment_file.clone(),
+            chain: ctx.chain.chain_id.clone(),
+        };
+        let enrichment = ctx.enrichment.clone();
+        let store = ctx.store.clone();
+        let status = ctx
+            .status
+            .reporter(&ctx.chain.name, self.name(), settings.period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_geo::run(
+                state.clone(),
+                enrichment.clone(),
+                store.clone(),
+                settings.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct RpcModule;
+#[async_trait(?Send)]
+impl ConstellationModule for RpcModule {
+    fn name(&self) -> &'static str {
+        "rpc"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let period = ctx.config.rpc.period();
+        let chain_id = ctx.chain.chain_id.clone();
+        let lcd_endpoint = ctx.chain.lcd.clone();
+        let rpc_endpoint = ctx.chain.rpc.clone();
+        let crawled = ctx.crawled.clone();
+        let status = ctx.status.reporter(&ctx.chain.name, self.name(), period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_rpc_crawler::run(
+                state.clone(),
+                period,
+                chain_id.clone(),
+                lcd_endpoint.clone(),
+                rpc_endpoint.clone(),
+                crawled.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// checks which nodes accept connections
+pub struct ProberModule;
+#[async_trait(?Send)]
+impl ConstellationModule for ProberModule {
+    fn name(&self) -> &'static str {
+        "prober"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let state = ctx.state.clone();
+        let reachability = ctx.reachability.clone();
+        let settings = ProberSettings {
+            file: ctx.chain.reachability_file.clone(),
+            period: ctx.config.prober.period(),
+            concurrency: ctx.config.prober.concurrency,
+            timeout: ctx.config.prober.timeout(),
+            handshakes: ctx.config.prober.handshake,
+            chain: ctx.chain.chain_id.clone(),
+        };
+        let status = ctx
+            .status
+            .reporter(&ctx.chain.name, self.name(), settings.period);
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_prober::run(
+                state.clone(),
+                reachability.clone(),
+                settings.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct WebSocketModule;
+#[async_trait(?Send)]
+impl ConstellationModule for WebSocketModule {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let clean = ctx.clean;
+        let lcd_endpoint = ctx.chain.lcd.clone();
+        let chain_id = ctx.chain.chain_id.clone();
+        let rpc_endpoint = ctx.chain.rpc.clone();
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            until_stopped(
+                constellation_web_socket::run(
+                    clean,
+                    lcd_endpoint.clone(),
+                    chain_id.clone(),
+                    rpc_endpoint.clone(),
+                ),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct OracleModule;
+#[async_trait(?Send)]
+impl ConstellationModule for OracleModule {
+    fn name(&self) -> &'static str {
+        "oracle"
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let oracle_actor = constellation_price_oracle::actor::OracleActor::create(
+            ctx.clean,
+            &ctx.chain.lcd,
+            &ctx.chain.chain_id,
+        )
+        .await?;
+
+        oracle_actor.start();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "private")]
+pub struct PriceModule;
+#[cfg(feature = "private")]
+#[async_trait(?Send)]
+impl ConstellationModule for PriceModule {
+    fn name(&self) -> &'static str {
+        "price"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["oracle", "websocket"]
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        log::info!("Starting private price check module");
+        let price_actor = constellation_price_check::actor::PriceCheckActor::create(
+            ctx.clean,
+            &ctx.chain.lcd,
+            &ctx.chain.chain_id,
+        )
+        .await?;
+
+        price_actor.start();
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct ValidatorModule {
+    actor: Option<Addr<ValidatorActor>>,
+}
+#[async_trait(?Send)]
+impl ConstellationModule for ValidatorModule {
+    fn name(&self) -> &'static str {
+        "validator"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["websocket"]
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        log::info!("Validator turned on");
+        let state = ctx.state.clone();
+        let period = ctx.config.validator.period();
+        let chain_id = ctx.chain.chain_id.clone();
+        let lcd_endpoint = ctx.chain.lcd.clone();
+        let status = ctx.status.reporter(&ctx.chain.name, self.name(), period);
+        let save_status = status.clone();
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_validator::run(
+                state.clone(),
+                period,
+                chain_id.clone(),
+                lcd_endpoint.clone(),
+                status.clone(),
+                shutdown,
+            )
+        })?;
+        let validator_actor = ValidatorActor::create(
+            ctx.clean,
+            &ctx.chain.lcd,
+            &ctx.chain.chain_id,
+            ValidatorSettings {
+                file: ctx.chain.validator_file.clone(),
+                save_every: ctx.config.validator.save_every(),
+                status: Some(save_status),
+                abstain_alert_every: ctx.config.validator.abstain_alert_every,
+                reward_slip: Decimal::from_f64(ctx.config.validator.reward_slip_percent / 100.0)
+                    .unwrap_or_default(),
+            },
+        )
+        .await?;
+        self.actor = Some(validator_actor.start());
+        Ok(())
+    }
+
+    async fn stop(&mut self, ctx: &mut ModuleContext) {
+        ctx.supervisor
+            .stop(self.name(), ctx.config.shutdown.deadline())
+            .await;
+        // the actor saves its state as it stops
+        if let Some(actor) = self.actor.take() {
+            if let Err(e) = actor.send(MessageStop {}).await {
+                log::warn!("Validator actor didn't stop cleanly {}", e);
+            }
+        }
+    }
+}
+
+/// a discord actor for each chain, posting to the chain's channels
+#[derive(Default)]
+pub struct DiscordModule {
+    actor: Option<Addr<DiscordValidatorActor>>,
+}
+#[async_trait(?Send)]
+impl ConstellationModule for DiscordModule {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["discord-gateway"]
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let discord_token = ctx.config.discord.token.clone().unwrap_or_default();
+        let discord_actor = DiscordValidatorActor::create(
+            &discord_token,
+            &ctx.config.discord.url,
+            ctx.config.discord.retries,
+            &ctx.chain.announcement_channel,
+            &ctx.chain.private_channel,
+            &ctx.chain.chain_id,
+        )
+        .await?;
+        let actor = discord_actor.start();
+        *ctx.discord.lock().unwrap_or_else(PoisonError::into_inner) = Some(actor.clone());
+        self.actor = Some(actor);
+        Ok(())
+    }
+
+    async fn stop(&mut self, ctx: &mut ModuleContext) {
+        ctx.discord
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(actor) = self.actor.take() {
+            if let Err(e) = actor.send(MessageDrain {}).await {
+                log::warn!("Discord messages may not have all been sent {}", e);
+            }
+        }
+    }
+}
+
+/// one gateway session for the whole process, however many chains there are.
+/// discord's events are passed on to each chain's discord actor
+pub struct DiscordGatewayModule;
+#[async_trait(?Send)]
+impl ConstellationModule for DiscordGatewayModule {
+    fn name(&self) -> &'static str {
+        "discord-gateway"
+    }
+
+    fn scope(&self) -> ModuleScope {
+        ModuleScope::Process
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let routes = ctx
+            .chains
+            .iter()
+            .map(|(_, chain_state)| chain_state.discord.clone())
+            .collect::<Vec<_>>();
+        GatewayRelay::new(routes).start();
+
+        let discord_token = ctx.config.discord.token.clone().unwrap_or_default();
+        let discord_url = ctx.config.discord.url.clone();
+        let discord_retries = ctx.config.discord.retries;
+        let state = ctx.state.clone();
+        let status = ctx.status.reporter(
+            "constellation",
+            self.name(),
+            constellation_discord::HEARTBEAT,
+        );
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            until_stopped(
+                constellation_discord::run(
+                    state.clone(),
+                    discord_token.clone(),
+                    //  discord_category_name.clone(),
+                    discord_url.clone(),
+                    discord_retries,
+                    status.clone(),
+                ),
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+pub struct WebModule;
+#[async_trait(?Send)]
+impl ConstellationModule for WebModule {
+    fn name(&self) -> &'static str {
+        "web"
+    }
+
+    /// one web server covers every chain
+    fn scope(&self) -> ModuleScope {
+        ModuleScope::Process
+    }
+
+    async fn start(&mut self, ctx: &mut ModuleContext) -> anyhow::Result<()> {
+        let chains = ctx
+            .chains
+            .iter()
+            .map(|(chain, chain_state)| WebChain {
+                name: chain.name.clone(),
+                chain_id: chain.chain_id.clone(),
+                state: chain_state.state.clone(),
+                store: chain_state.store.clone(),
+                seen: chain_state.seen.clone(),
+                reachability: chain_state.reachability.clone(),
+                enrichment: chain_state.enrichment.clone(),
+            })
+            .collect::<Vec<_>>();
+        let bind = ctx.config.web.bind.clone();
+        let status = ctx.status.clone();
+        let ready_intervals = ctx.config.web.ready_intervals;
+        ctx.supervisor.spawn(self.name(), move |shutdown| {
+            constellation_web::run(
+                chains.clone(),
+                //  tx_web,
+                NAME.unwrap_or("constellation"),
+                VERSION.unwrap_or("dev"),
+                bind.clone(),
+                status.clone(),
+                ready_intervals,
+                shutdown,
+            )
+        })?;
+        Ok(())
+    }
+}
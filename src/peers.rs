@@ -0,0 +1,116 @@
+use constellation_peers::{persistent_peers, recommend, Criteria};
+use constellation_prober::{handshake, load_reachability};
+use structopt::StructOpt;
+
+use crate::config::{Config, StateBackend};
+
+#[derive(StructOpt)]
+pub struct PeersOptions {
+    #[structopt(long, default_value = "10", help = "how many peers to recommend")]
+    count: usize,
+    #[structopt(
+        long,
+        default_value = "0.5",
+        help = "the share of probes a peer must have answered, 0 to 1"
+    )]
+    min_uptime: f64,
+    #[structopt(
+        long = "peers-chain",
+        help = "which chain's peers (defaults to the first configured chain)"
+    )]
+    chain: Option<String>,
+    #[structopt(long, help = "show each peer's ASN, country, continent & uptime too")]
+    explain: bool,
+}
+
+#[derive(StructOpt)]
+pub struct HandshakeOptions {
+    #[structopt(help = "the node to handshake with, as ip:port")]
+    address: String,
+    #[structopt(
+        long = "handshake-network",
+        help = "the network to claim to be on (defaults to the first configured chain's chain_id)"
+    )]
+    network: Option<String>,
+}
+
+/// print id@ip:port,... for the chain's best peers, ready to paste into config.toml
+pub fn run(config: &Config, options: &PeersOptions) -> anyhow::Result<()> {
+    let profiles = config.profiles();
+    let profile = match &options.chain {
+        Some(name) => profiles.iter().find(|p| &p.name == name),
+        None => profiles.first(),
+    }
+    .ok_or_else(|| anyhow::anyhow!("chain not found"))?;
+    if config.state.backend == StateBackend::Sled {
+        log::warn!(
+            "reading the last checkpoint {}, not {}",
+            profile.state_file,
+            profile.state_db
+        );
+    }
+    let state = constellation_state_checkpoint::read(&profile.state_file)?;
+    let reachability = load_reachability(&profile.reachability_file)?;
+    if reachability.is_empty() {
+        anyhow::bail!(
+            "{} has nothing in it. is the prober module running?",
+            profile.reachability_file
+        )
+    }
+    let peers = recommend(
+        &state,
+        &reachability,
+        &Criteria {
+            count: options.count,
+            min_uptime: options.min_uptime,
+        },
+    );
+    if peers.len() < options.count {
+        log::warn!(
+            "only {} peers were up often enough, {} were asked for",
+            peers.len(),
+            options.count
+        );
+    }
+    if options.explain {
+        for peer in &peers {
+            println!(
+                "{:<70} AS{:<8} {:<3} {:<3} {:>5.1}% {:>6}ms score {:.2}",
+                peer.node,
+                peer.asn.as_deref().unwrap_or("?"),
+                peer.country.as_deref().unwrap_or("?"),
+                peer.continent.as_deref().unwrap_or("?"),
+                peer.uptime * 100.0,
+                peer.latency_ms.map(|l| l.to_string()).unwrap_or_default(),
+                peer.score
+            );
+        }
+    }
+    println!("{}", persistent_peers(&peers));
+    Ok(())
+}
+
+/// handshake with one node, as the prober does, and print what it says about itself
+pub fn handshake_with(config: &Config, options: &HandshakeOptions) -> anyhow::Result<()> {
+    let network = match &options.network {
+        Some(network) => network.clone(),
+        None => config
+            .profiles()
+            .first()
+            .map(|p| p.chain_id.clone())
+            .ok_or_else(|| anyhow::anyhow!("no chain configured"))?,
+    };
+    let node_info = handshake(&options.address, &network, config.prober.timeout())
+        .map_err(|e| anyhow::anyhow!(e))?;
+    println!("{}", serde_json::to_string_pretty(&node_info)?);
+    if node_info.id != node_info.claimed_id {
+        println!(
+            "claims to be {} but holds the key for {}",
+            node_info.claimed_id, node_info.id
+        );
+    }
+    if node_info.network != network {
+        println!("is on {}, not {}", node_info.network, network);
+    }
+    Ok(())
+}
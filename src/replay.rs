@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_broker::{Broker, SystemBroker};
+use constellation_discord::actor::{DiscordValidatorActor, MessageDrain};
+use constellation_recorder::actor::RecorderActor;
+use constellation_shared::state::State;
+use constellation_shared::MessageStop;
+use constellation_state::SharedState;
+use constellation_status::StatusRegistry;
+use constellation_validator::actor::{ValidatorActor, ValidatorSettings};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use structopt::StructOpt;
+
+use crate::config::Config;
+
+#[derive(StructOpt)]
+pub struct ReplayOptions {
+    #[structopt(help = "JSONL file written by the recorder module")]
+    file: String,
+    #[structopt(
+        long,
+        default_value = "1.0",
+        help = "1 is as recorded, 10 is ten times faster, 0 is as fast as possible"
+    )]
+    speed: f64,
+    #[structopt(
+        long = "replay-chain",
+        help = "only replay this chain's messages (defaults to the first configured chain)"
+    )]
+    chain: Option<String>,
+    #[structopt(
+        long,
+        default_value = "validator-replay.json",
+        help = "where the validator actor saves itself while replaying. keeps the real one intact"
+    )]
+    validator_file: String,
+    #[structopt(
+        long,
+        help = "record the replay, including what the actors said, to this file"
+    )]
+    record: Option<String>,
+    #[structopt(long, help = "send what the actors say to discord")]
+    discord: bool,
+    #[structopt(
+        long,
+        help = "also replay what the actors said when recorded, on top of what they say now"
+    )]
+    include_outputs: bool,
+}
+
+/// run the validator (and optionally discord) actors against a recording, without touching the chain
+pub async fn run(config: &Config, options: &ReplayOptions) -> anyhow::Result<()> {
+    let profiles = config.profiles();
+    let profile = match &options.chain {
+        Some(name) => profiles.iter().find(|p| &p.name == name),
+        None => profiles.first(),
+    }
+    .ok_or_else(|| anyhow::anyhow!("chain not found"))?;
+
+    if let Some(record) = &options.record {
+        RecorderActor::create(record, &profile.name)?.start();
+    }
+    let validator = ValidatorActor::empty(
+        &profile.lcd,
+        &profile.chain_id,
+        ValidatorSettings {
+            file: options.validator_file.clone(),
+            save_every: config.validator.save_every(),
+            status: None,
+            abstain_alert_every: config.validator.abstain_alert_every,
+            reward_slip: Decimal::from_f64(config.validator.reward_slip_percent / 100.0)
+                .unwrap_or_default(),
+        },
+    )
+    .start();
+    let discord = if options.discord {
+        let token = config.discord.token.clone().unwrap_or_default();
+        let actor = DiscordValidatorActor::create(
+            &token,
+            &config.discord.url,
+            config.discord.retries,
+            &profile.announcement_channel,
+            &profile.private_channel,
+            &profile.chain_id,
+        )
+        .await?
+        .start();
+        // the gateway tells the actor which channels exist
+        let status = StatusRegistry::default().reporter(
+            &profile.name,
+            "discord",
+            constellation_discord::HEARTBEAT,
+        );
+        actix_rt::spawn(constellation_discord::run(
+            SharedState::new(State::new()?),
+            token,
+            config.discord.url.clone(),
+            config.discord.retries,
+            status,
+        ));
+        log::info!("Waiting for discord to list its channels");
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        Some(actor)
+    } else {
+        None
+    };
+
+    let count = constellation_recorder::replay(
+        &options.file,
+        options.speed,
+        Some(&profile.name),
+        options.include_outputs,
+    )
+    .await?;
+    log::info!("Replayed {} messages from {}", count, options.file);
+
+    // MessageStop goes through the broker behind everything replayed, so once the validator
+    // actor has stopped, it has dealt with the lot
+    Broker::<SystemBroker>::issue_async(MessageStop {});
+    let deadline = config.shutdown.deadline();
+    let wait = async {
+        while validator.connected() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        if let Some(discord) = discord {
+            let _ = discord.send(MessageDrain {}).await;
+        }
+    };
+    if tokio::time::timeout(deadline, wait).await.is_err() {
+        log::warn!("Actors still busy after {}s", deadline.as_secs());
+    }
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::Utc;
+use constellation_shared::state::State;
+use constellation_state::{SharedState, Sightings};
+use constellation_store::Batch;
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::config::{ChainProfile, Config, StateBackend};
+
+/// work with checkpoint files without starting constellation
+#[derive(StructOpt)]
+pub enum StateCommand {
+    /// count what is in each of a checkpoint's maps
+    Inspect {
+        #[structopt(help = "checkpoint file. defaults to the chain's state file")]
+        file: Option<String>,
+    },
+    /// the nodes, IPs and ASNs added or removed between two checkpoints
+    Diff {
+        #[structopt(help = "the older checkpoint")]
+        a: String,
+        #[structopt(help = "the newer checkpoint")]
+        b: String,
+    },
+    /// write some of a checkpoint's maps out as csv or json
+    Export {
+        #[structopt(long, default_value = "json", possible_values = &["csv", "json"])]
+        format: ExportFormat,
+        #[structopt(
+            long = "map",
+            help = "map to export, eg. geo_country. can be repeated. defaults to all of them"
+        )]
+        maps: Vec<String>,
+        #[structopt(long, help = "file to write to. defaults to stdout")]
+        output: Option<String>,
+        #[structopt(help = "checkpoint file. defaults to the chain's state file")]
+        file: Option<String>,
+    },
+    /// drop nodes & IPs not seen for a while, as the retention module would.
+    /// constellation shouldn't be running, or its next checkpoint will put them back
+    Prune {
+        #[structopt(long, help = "defaults to retention.days")]
+        days: Option<u32>,
+        #[structopt(long, help = "defaults to the chain's seen file")]
+        seen_file: Option<String>,
+        #[structopt(long, help = "report what would be removed, without writing anything")]
+        dry_run: bool,
+        #[structopt(help = "checkpoint file. defaults to the chain's state file")]
+        file: Option<String>,
+    },
+}
+
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!("unknown format {}", other)),
+        }
+    }
+}
+
+pub fn run(config: &Config, command: &StateCommand) -> anyhow::Result<()> {
+    let profile = config
+        .profiles()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no chains configured"))?;
+    let file_or_default =
+        |file: &Option<String>| file.clone().unwrap_or_else(|| profile.state_file.clone());
+    match command {
+        StateCommand::Inspect { file } => inspect(&file_or_default(file)),
+        StateCommand::Diff { a, b } => diff(a, b),
+        StateCommand::Export {
+            format,
+            maps,
+            output,
+            file,
+        } => export(&file_or_default(file), format, maps, output.as_deref()),
+        StateCommand::Prune {
+            days,
+            seen_file,
+            dry_run,
+            file,
+        } => prune(
+            config,
+            &profile,
+            &file_or_default(file),
+            seen_file.as_deref().unwrap_or(&profile.seen_file),
+            days.unwrap_or(config.retention.days),
+            *dry_run,
+        ),
+    }
+}
+
+/// the checkpoint as JSON fields, so every map can be handled alike
+fn fields(file: &str) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let state = constellation_state_checkpoint::read(file)?;
+    match serde_json::to_value(&state)? {
+        Value::Object(fields) => Ok(fields),
+        _ => anyhow::bail!("{} is not a JSON object", file),
+    }
+}
+
+fn inspect(file: &str) -> anyhow::Result<()> {
+    println!("{}", file);
+    for (field, value) in fields(file)? {
+        match value {
+            Value::Object(map) => println!("  {:<20} {}", field, map.len()),
+            Value::Array(set) => println!("  {:<20} {}", field, set.len()),
+            other => println!("  {:<20} {}", field, other),
+        }
+    }
+    Ok(())
+}
+
+fn keys(fields: &serde_json::Map<String, Value>, field: &str) -> BTreeSet<String> {
+    match fields.get(field) {
+        Some(Value::Object(map)) => map.keys().cloned().collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// how one map's keys changed between two checkpoints
+struct KeyChanges {
+    before: usize,
+    after: usize,
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+fn key_changes(
+    a_fields: &serde_json::Map<String, Value>,
+    b_fields: &serde_json::Map<String, Value>,
+    field: &str,
+) -> KeyChanges {
+    let (before, after) = (keys(a_fields, field), keys(b_fields, field));
+    KeyChanges {
+        before: before.len(),
+        after: after.len(),
+        added: after.difference(&before).cloned().collect(),
+        removed: before.difference(&after).cloned().collect(),
+    }
+}
+
+fn diff(a: &str, b: &str) -> anyhow::Result<()> {
+    let (a_fields, b_fields) = (fields(a)?, fields(b)?);
+    println!("--- {}\n+++ {}", a, b);
+    for (what, field) in &[("nodes", "nodes"), ("IPs", "ip_ip_addr"), ("ASNs", "asn")] {
+        let changes = key_changes(&a_fields, &b_fields, field);
+        println!(
+            "{}: {} -> {} ({} added, {} removed)",
+            what,
+            changes.before,
+            changes.after,
+            changes.added.len(),
+            changes.removed.len()
+        );
+        for key in changes.removed {
+            println!("  - {}", key);
+        }
+        for key in changes.added {
+            println!("  + {}", key);
+        }
+    }
+    Ok(())
+}
+
+fn export(
+    file: &str,
+    format: &ExportFormat,
+    maps: &[String],
+    output: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut fields = fields(file)?;
+    fields.retain(|field, value| {
+        (maps.is_empty() || maps.contains(field)) && (value.is_object() || value.is_array())
+    });
+    for map in maps {
+        if !fields.contains_key(map) {
+            anyhow::bail!("{} has no map called {}", file, map)
+        }
+    }
+    let mut out: Box<dyn Write> = match output {
+        Some(output) => Box::new(File::create(output)?),
+        None => Box::new(io::stdout()),
+    };
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &fields)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "map,key,value")?;
+            for (field, value) in fields {
+                match value {
+                    Value::Object(map) => {
+                        for (key, entry) in map {
+                            let entry = match entry {
+                                Value::String(s) => s,
+                                other => other.to_string(),
+                            };
+                            writeln!(out, "{},{},{}", field, csv(&key), csv(&entry))?;
+                        }
+                    }
+                    Value::Array(set) => {
+                        for member in set {
+                            let member = match member {
+                                Value::String(s) => s,
+                                other => other.to_string(),
+                            };
+                            writeln!(out, "{},{},", field, csv(&member))?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// quote a csv field if it needs it
+fn csv(value: &str) -> String {
+    if value.contains(['\r', '\n', ',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn prune(
+    config: &Config,
+    profile: &ChainProfile,
+    file: &str,
+    seen_file: &str,
+    days: u32,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let mut state: State = constellation_state_checkpoint::read(file)?;
+    let mut sightings = Sightings::load(seen_file)?;
+    if sightings.nodes.is_empty() && sightings.ips.is_empty() {
+        log::warn!(
+            "{} has no sightings, so nothing can be pruned from {}",
+            seen_file,
+            file
+        );
+    }
+    let compaction = constellation_state::prune(
+        &mut state,
+        &mut sightings,
+        chrono::Duration::days(days as i64),
+        Utc::now(),
+        &mut Batch::default(),
+    );
+    println!("{} ({} days): {}", file, days, compaction);
+    if dry_run || compaction.is_empty() {
+        return Ok(());
+    }
+    if file == profile.state_file && config.state.backend == StateBackend::Sled {
+        log::warn!("{} is only read to import with the sled backend", file);
+    }
+    constellation_state_checkpoint::save(
+        &SharedState::new(state),
+        file,
+        config.checkpoint.generations,
+    )?;
+    sightings.save(seen_file)?;
+    if config.checkpoint.generations > 1 {
+        println!("Saved {} (the previous version is now {}.1)", file, file);
+    } else {
+        println!("Saved {}", file);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn quotes_csv_fields_that_need_it() {
+        assert_eq!(csv("10.0.0.1"), "10.0.0.1");
+        assert_eq!(csv(""), "");
+        assert_eq!(csv("Amazon.com, Inc."), "\"Amazon.com, Inc.\"");
+        assert_eq!(csv("the \"best\" ISP"), "\"the \"\"best\"\" ISP\"");
+        assert_eq!(csv("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv("two\r\nlines"), "\"two\r\nlines\"");
+        assert_eq!(csv("just\rreturn"), "\"just\rreturn\"");
+    }
+
+    fn object(value: Value) -> serde_json::Map<String, Value> {
+        match value {
+            Value::Object(fields) => fields,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn diffs_the_keys_of_a_map() {
+        let a = object(json!({
+            "nodes": { "a@10.0.0.1:26656": {}, "b@10.0.0.2:26656": {}, "c@10.0.0.3:26656": {} },
+            "asn": { "1": {} },
+            "new_ips_bgp": ["10.0.0.1"],
+        }));
+        let b = object(json!({
+            "nodes": { "b@10.0.0.2:26656": {}, "d@10.0.0.4:26656": {}, "e@10.0.0.5:26656": {} },
+            "asn": {},
+        }));
+
+        let nodes = key_changes(&a, &b, "nodes");
+        assert_eq!((nodes.before, nodes.after), (3, 3));
+        assert_eq!(nodes.added, vec!["d@10.0.0.4:26656", "e@10.0.0.5:26656"]);
+        assert_eq!(nodes.removed, vec!["a@10.0.0.1:26656", "c@10.0.0.3:26656"]);
+
+        let asns = key_changes(&a, &b, "asn");
+        assert_eq!((asns.before, asns.after), (1, 0));
+        assert!(asns.added.is_empty());
+        assert_eq!(asns.removed, vec!["1"]);
+
+        // a map missing from either side (or not a map at all) has no keys
+        let ips = key_changes(&a, &b, "ip_ip_addr");
+        assert_eq!((ips.before, ips.after), (0, 0));
+        let reversed = key_changes(&b, &a, "new_ips_bgp");
+        assert!(reversed.added.is_empty() && reversed.removed.is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_broker::{Broker, SystemBroker};
+use constellation_shared::messages::{MessageSendMessageEvent, SendMessageEventType};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+use crate::errors::ConstellationError;
+
+/// how aggressively a failed module gets restarted
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    /// wait before the first restart
+    pub initial_backoff: Duration,
+    /// backoff doubles on each consecutive failure, up to this
+    pub max_backoff: Duration,
+    /// consecutive restarts allowed before we give up on the module
+    pub max_restarts: usize,
+    /// a module that stays up this long is considered healthy again, and its budget is reset
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60 * 5),
+            max_restarts: 10,
+            reset_after: Duration::from_secs(60 * 30),
+        }
+    }
+}
+
+/// owns the spawned module tasks, and respawns them when they die
+pub struct Supervisor {
+    /// which chain (or the process as a whole) these modules belong to. used in logs & alerts
+    label: String,
+    policy: RestartPolicy,
+    tasks: Vec<JoinHandle<()>>,
+    stop_signals: HashMap<&'static str, mpsc::UnboundedSender<StopRequest>>,
+}
+
+/// ask a supervised module to stop. it is told to via its shutdown receiver, and gets `grace`
+/// to finish by itself before it is aborted
+struct StopRequest {
+    grace: Duration,
+    done: oneshot::Sender<()>,
+}
+
+impl Supervisor {
+    pub fn new(label: &str, policy: RestartPolicy) -> Supervisor {
+        Supervisor {
+            label: label.into(),
+            policy,
+            tasks: vec![],
+            stop_signals: Default::default(),
+        }
+    }
+
+    /// spawn a module. `factory` is called again each time the module needs to be restarted.
+    /// the module is handed a receiver that turns true when it is asked to stop, and should
+    /// return once it has saved whatever it needs to
+    pub fn spawn<F, Fut>(
+        &mut self,
+        name: &'static str,
+        factory: F,
+    ) -> Result<(), ConstellationError>
+    where
+        F: Fn(watch::Receiver<bool>) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        if self.stop_signals.contains_key(name) {
+            return Err(ConstellationError::DuplicateModule(format!(
+                "{}/{}",
+                self.label, name
+            )));
+        }
+        let policy = self.policy.clone();
+        let (stop_tx, stop_rx) = mpsc::unbounded_channel();
+        self.stop_signals.insert(name, stop_tx);
+        let label = format!("{}/{}", self.label, name);
+        self.tasks
+            .push(actix_rt::spawn(supervise(label, policy, factory, stop_rx)));
+        Ok(())
+    }
+
+    /// stop a module, and don't restart it. returns once the module has gone.
+    /// stopping a module we don't know about (or that has already given up) is harmless
+    pub async fn stop(&mut self, name: &str, grace: Duration) {
+        if let Some(stop_tx) = self.stop_signals.remove(name) {
+            log::info!("Module {}/{} stopping", self.label, name);
+            let (done, stopped) = oneshot::channel();
+            if stop_tx.send(StopRequest { grace, done }).is_ok() {
+                let _ = stopped.await;
+            }
+        }
+    }
+
+    /// stop everything that is still running
+    pub async fn stop_all(&mut self, grace: Duration) {
+        let names = self.stop_signals.keys().copied().collect::<Vec<_>>();
+        for name in names {
+            self.stop(name, grace).await;
+        }
+    }
+
+    /// wait for all supervised modules to finish (which only happens when they give up or are stopped)
+    pub async fn join(&mut self) {
+        let tasks = std::mem::take(&mut self.tasks);
+        for result in futures::future::join_all(tasks).await {
+            if let Err(e) = result {
+                log::error!("supervisor task failed? {}", e)
+            }
+        }
+    }
+}
+
+async fn supervise<F, Fut>(
+    name: String,
+    policy: RestartPolicy,
+    factory: F,
+    mut stop_rx: mpsc::UnboundedReceiver<StopRequest>,
+) where
+    F: Fn(watch::Receiver<bool>) -> Fut,
+    Fut: Future<Output = ()> + 'static,
+{
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut restarts: usize = 0;
+    let mut backoff = policy.initial_backoff;
+    loop {
+        log::info!("Module {} starting (restarts:{})", name, restarts);
+        let started = Instant::now();
+        let mut module = actix_rt::spawn(factory(shutdown_rx.clone()));
+        tokio::select! {
+            result = &mut module => match result {
+                Ok(_) => log::warn!("Module {} exited", name),
+                Err(e) => log::error!("Module {} failed: {}", name, e),
+            },
+            request = stop_rx.recv() => {
+                let grace = request.as_ref().map(|r| r.grace).unwrap_or_default();
+                let _ = shutdown_tx.send(true);
+                if tokio::time::timeout(grace, &mut module).await.is_err() {
+                    log::warn!(
+                        "Module {} didn't stop within {}s. aborting it",
+                        name,
+                        grace.as_secs()
+                    );
+                    module.abort();
+                }
+                log::info!("Module {} stopped", name);
+                if let Some(request) = request {
+                    let _ = request.done.send(());
+                }
+                return;
+            }
+        }
+        if started.elapsed() >= policy.reset_after {
+            restarts = 0;
+            backoff = policy.initial_backoff;
+        }
+        if restarts >= policy.max_restarts {
+            let message = format!(
+                "Module {} has failed {} times. giving up on it",
+                name,
+                restarts + 1
+            );
+            log::error!("{}", message);
+            Broker::<SystemBroker>::issue_async(MessageSendMessageEvent {
+                height: 0,
+                event_type: SendMessageEventType::CRITICAL,
+                message,
+                hash: None,
+            });
+            return;
+        }
+        restarts += 1;
+        log::warn!(
+            "Module {} restarting in {}s (restart {}/{})",
+            name,
+            backoff.as_secs(),
+            restarts,
+            policy.max_restarts
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            request = stop_rx.recv() => {
+                log::info!("Module {} stopped", name);
+                if let Some(request) = request {
+                    let _ = request.done.send(());
+                }
+                return;
+            }
+        }
+        backoff = std::cmp::min(backoff * 2, policy.max_backoff);
+    }
+}
+
+/// run a module that knows nothing of shutdown (such as one from another crate) until it is
+/// asked to stop. it is dropped where it is, so it shouldn't have anything to save
+pub async fn until_stopped<Fut>(module: Fut, mut shutdown: watch::Receiver<bool>)
+where
+    Fut: Future<Output = ()>,
+{
+    tokio::select! {
+        _ = module => {}
+        _ = shutdown.changed() => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// a module that records when it was started, and panics. the first `slow` starts run for
+    /// `runs_for` first
+    fn failing(
+        starts: &Arc<Mutex<Vec<Instant>>>,
+        slow: usize,
+        runs_for: Duration,
+    ) -> impl Fn(watch::Receiver<bool>) -> futures::future::BoxFuture<'static, ()> {
+        let starts = starts.clone();
+        move |_shutdown| {
+            let starts = starts.clone();
+            Box::pin(async move {
+                let started = {
+                    let mut starts = starts.lock().unwrap();
+                    starts.push(Instant::now());
+                    starts.len()
+                };
+                if started <= slow {
+                    tokio::time::sleep(runs_for).await;
+                }
+                panic!("module failure {}", started);
+            })
+        }
+    }
+
+    fn policy(initial_backoff: u64, max_backoff: u64, max_restarts: usize) -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(initial_backoff),
+            max_backoff: Duration::from_millis(max_backoff),
+            max_restarts,
+            reset_after: Duration::from_secs(60),
+        }
+    }
+
+    fn gaps(starts: &Arc<Mutex<Vec<Instant>>>) -> Vec<Duration> {
+        let starts = starts.lock().unwrap();
+        starts.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    #[actix_rt::test]
+    async fn doubles_the_backoff_up_to_the_cap_and_gives_up() {
+        let starts = Arc::new(Mutex::new(vec![]));
+        let (_stop_tx, stop_rx) = mpsc::unbounded_channel();
+        supervise(
+            "test/doubling".into(),
+            policy(25, 50, 4),
+            failing(&starts, 0, Duration::ZERO),
+            stop_rx,
+        )
+        .await;
+
+        // the first run and 4 restarts, then it gives up by itself
+        let gaps = gaps(&starts);
+        assert_eq!(gaps.len(), 4, "{:?}", gaps);
+        for (gap, backoff) in gaps.iter().zip([25, 50, 50, 50].iter()) {
+            assert!(*gap >= Duration::from_millis(*backoff), "{:?}", gaps);
+        }
+        // doubling without the cap would have waited 100ms & 200ms
+        assert!(gaps[2] < Duration::from_millis(100), "{:?}", gaps);
+        assert!(gaps[3] < Duration::from_millis(100), "{:?}", gaps);
+    }
+
+    #[actix_rt::test]
+    async fn gives_up_straight_away_without_a_restart_budget() {
+        let starts = Arc::new(Mutex::new(vec![]));
+        let (_stop_tx, stop_rx) = mpsc::unbounded_channel();
+        supervise(
+            "test/no-budget".into(),
+            policy(10, 10, 0),
+            failing(&starts, 0, Duration::ZERO),
+            stop_rx,
+        )
+        .await;
+        assert_eq!(starts.lock().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn resets_the_budget_after_a_long_enough_run() {
+        let starts = Arc::new(Mutex::new(vec![]));
+        let (_stop_tx, stop_rx) = mpsc::unbounded_channel();
+        let policy = RestartPolicy {
+            reset_after: Duration::from_millis(30),
+            ..policy(10, 1000, 2)
+        };
+        supervise(
+            "test/reset".into(),
+            policy,
+            failing(&starts, 3, Duration::from_millis(40)),
+            stop_rx,
+        )
+        .await;
+
+        // without the reset it would have given up after the third start. each of the first
+        // three stayed up long enough, so the budget of 2 restarts only runs out on the fifth
+        let gaps = gaps(&starts);
+        assert_eq!(gaps.len(), 4, "{:?}", gaps);
+        // and the backoff went back to the start each time: 40ms up + 10ms, not 20ms or 40ms
+        for gap in &gaps[..3] {
+            assert!(*gap < Duration::from_millis(80), "{:?}", gaps);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stops_during_the_backoff() {
+        let starts = Arc::new(Mutex::new(vec![]));
+        let (stop_tx, stop_rx) = mpsc::unbounded_channel();
+        let supervisor = actix_rt::spawn(supervise(
+            "test/stop".into(),
+            policy(10_000, 10_000, 5),
+            failing(&starts, 0, Duration::ZERO),
+            stop_rx,
+        ));
+        while starts.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // long enough for the first run to have failed, and the 10s backoff to have begun
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let asked = Instant::now();
+        let (done, stopped) = oneshot::channel();
+        assert!(stop_tx
+            .send(StopRequest {
+                grace: Duration::from_secs(1),
+                done,
+            })
+            .is_ok());
+        tokio::time::timeout(Duration::from_secs(1), stopped)
+            .await
+            .unwrap()
+            .unwrap();
+        supervisor.await.unwrap();
+        assert!(asked.elapsed() < Duration::from_secs(1));
+        assert_eq!(starts.lock().unwrap().len(), 1);
+    }
+}